@@ -0,0 +1,257 @@
+//! Opens a shared frame on a D3D12 device, copies it into a readback buffer,
+//! and compares a few pixels with the same display captured to the CPU. Keep
+//! the desktop still while it runs. Needs the default D3D12 adapter to be the
+//! one capturing, and `cargo run --example d3d12_readback --features d3d12`.
+
+extern crate scrap;
+#[cfg(windows)]
+extern crate winapi;
+
+#[cfg(windows)]
+fn main() {
+    if let Err(err) = unsafe { readback::run() } {
+        println!("FAIL  {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    println!("This example only runs on Windows.");
+}
+
+#[cfg(windows)]
+mod readback {
+    use scrap::dxgi::{
+        open_shared_texture, Capturer, Config, D3D12SharedTexture, Displays, SharedSync,
+    };
+    use std::{io, mem, ptr, slice};
+    use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
+    use winapi::shared::minwindef::FALSE;
+    use winapi::shared::winerror::HRESULT;
+    use winapi::um::d3d12::*;
+    use winapi::um::d3dcommon::D3D_FEATURE_LEVEL_11_0;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::synchapi::{CreateEventW, WaitForSingleObject};
+    use winapi::um::winbase::INFINITE;
+    use winapi::Interface;
+
+    const TOLERANCE: u8 = 2;
+
+    fn check(what: &str, res: HRESULT) -> io::Result<()> {
+        if res < 0 {
+            Err(io::Error::other(format!("{} failed: {:#x}", what, res)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub unsafe fn run() -> io::Result<()> {
+        let display = Displays::new()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        let config = Config {
+            shared_sync: SharedSync::Fence,
+            ..Config::default()
+        };
+        let mut capturer = Capturer::with_config(&display, false, config)?;
+        let shared = capturer.frame_shared_handle(1000)?;
+        let (width, height) = (shared.width(), shared.height());
+
+        // A second duplication gets the current desktop image straight away.
+        let expected = Capturer::new(&display, false)?.frame_owned(1000)?;
+
+        let mut device: *mut ID3D12Device = ptr::null_mut();
+        check(
+            "D3D12CreateDevice",
+            D3D12CreateDevice(
+                ptr::null_mut(),
+                D3D_FEATURE_LEVEL_11_0,
+                &ID3D12Device::uuidof(),
+                &mut device as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+
+        let D3D12SharedTexture {
+            resource,
+            fence,
+            ready,
+            release,
+        } = open_shared_texture(
+            device,
+            shared.handle(),
+            shared.fence_handle().unwrap(),
+            shared.sync(),
+        )?;
+
+        let queue_desc = D3D12_COMMAND_QUEUE_DESC {
+            Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+            Priority: 0,
+            Flags: D3D12_COMMAND_QUEUE_FLAG_NONE,
+            NodeMask: 0,
+        };
+        let mut queue: *mut ID3D12CommandQueue = ptr::null_mut();
+        check(
+            "CreateCommandQueue",
+            (*device).CreateCommandQueue(
+                &queue_desc,
+                &ID3D12CommandQueue::uuidof(),
+                &mut queue as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+        let mut allocator: *mut ID3D12CommandAllocator = ptr::null_mut();
+        check(
+            "CreateCommandAllocator",
+            (*device).CreateCommandAllocator(
+                D3D12_COMMAND_LIST_TYPE_DIRECT,
+                &ID3D12CommandAllocator::uuidof(),
+                &mut allocator as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+        let mut list: *mut ID3D12GraphicsCommandList = ptr::null_mut();
+        check(
+            "CreateCommandList",
+            (*device).CreateCommandList(
+                0,
+                D3D12_COMMAND_LIST_TYPE_DIRECT,
+                allocator,
+                ptr::null_mut(),
+                &ID3D12GraphicsCommandList::uuidof(),
+                &mut list as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+
+        // Where each row lands in the buffer, with D3D12's pitch alignment.
+        let texture_desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Alignment: 0,
+            Width: width as u64,
+            Height: height as u32,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: shared.format(),
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+            Flags: D3D12_RESOURCE_FLAG_NONE,
+        };
+        let mut footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT = mem::zeroed();
+        let mut total = 0;
+        (*device).GetCopyableFootprints(
+            &texture_desc,
+            0,
+            1,
+            0,
+            &mut footprint,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut total,
+        );
+
+        let heap = D3D12_HEAP_PROPERTIES {
+            Type: D3D12_HEAP_TYPE_READBACK,
+            CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 0,
+            VisibleNodeMask: 0,
+        };
+        let buffer_desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+            Width: total,
+            Height: 1,
+            Format: 0,
+            Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            ..texture_desc
+        };
+        let mut buffer: *mut ID3D12Resource = ptr::null_mut();
+        check(
+            "CreateCommittedResource",
+            (*device).CreateCommittedResource(
+                &heap,
+                D3D12_HEAP_FLAG_NONE,
+                &buffer_desc,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                ptr::null(),
+                &ID3D12Resource::uuidof(),
+                &mut buffer as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+
+        let mut dst = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: buffer,
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            u: mem::zeroed(),
+        };
+        *dst.u.PlacedFootprint_mut() = footprint;
+        let mut src = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: resource,
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            u: mem::zeroed(),
+        };
+        *src.u.SubresourceIndex_mut() = 0;
+        (*list).CopyTextureRegion(&dst, 0, 0, 0, &src, ptr::null());
+        check("Close", (*list).Close())?;
+
+        // The protocol: wait for `ready` on the GPU, read, signal `release`.
+        check("Wait", (*queue).Wait(fence, ready))?;
+        let lists = [list as *mut ID3D12CommandList];
+        (*queue).ExecuteCommandLists(1, lists.as_ptr());
+        check("Signal", (*queue).Signal(fence, release))?;
+
+        let event = CreateEventW(ptr::null_mut(), FALSE, FALSE, ptr::null());
+        check(
+            "SetEventOnCompletion",
+            (*fence).SetEventOnCompletion(release, event),
+        )?;
+        WaitForSingleObject(event, INFINITE);
+        CloseHandle(event);
+
+        let mut data = ptr::null_mut();
+        check("Map", (*buffer).Map(0, ptr::null(), &mut data))?;
+        let data = slice::from_raw_parts(data as *const u8, total as usize);
+        let pitch = footprint.Footprint.RowPitch as usize;
+
+        let points = [
+            (0, 0),
+            (width - 1, 0),
+            (width / 2, height / 2),
+            (0, height - 1),
+            (width - 1, height - 1),
+        ];
+        let mut mismatches = 0;
+        for &(x, y) in &points {
+            let got = &data[y * pitch + x * 4..][..4];
+            let want = &expected[y * expected.stride() + x * 4..][..4];
+            let close = got
+                .iter()
+                .zip(want)
+                .take(3)
+                .all(|(&a, &b)| a.abs_diff(b) <= TOLERANCE);
+            if !close {
+                println!("({}, {}): got {:?}, expected {:?}", x, y, got, want);
+                mismatches += 1;
+            }
+        }
+        (*buffer).Unmap(0, ptr::null());
+
+        (*buffer).Release();
+        (*list).Release();
+        (*allocator).Release();
+        (*queue).Release();
+        (*fence).Release();
+        (*resource).Release();
+        (*device).Release();
+
+        if mismatches > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} of {} pixels differ", mismatches, points.len()),
+            ));
+        }
+        println!("ok    {} pixels match", points.len());
+        Ok(())
+    }
+}
@@ -41,7 +41,7 @@ fn main() {
         match capturer.frame() {
             Ok(frame) => {
                 // Write the frame, removing end-of-row padding.
-                let stride = frame.len() / h;
+                let stride = frame.stride();
                 let rowlen = 4 * w;
                 for row in frame.chunks(stride) {
                     let row = &row[..rowlen];
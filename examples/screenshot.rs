@@ -41,7 +41,7 @@ fn main() {
         // Flip the BGRA image into a RGBA image.
 
         let mut bitflipped = Vec::with_capacity(w * h * 4);
-        let stride = buffer.len() / h;
+        let stride = buffer.stride();
 
         for y in 0..h {
             for x in 0..w {
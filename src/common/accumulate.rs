@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// The most frames an `Accumulator` can average without overflowing.
+pub const MAX_ACCUMULATED_FRAMES: usize = 256;
+
+/// Averages the last few frames to reduce temporal noise.
+///
+/// Keeps a copy of each of the last `capacity` frames plus a 16-bit running
+/// sum per byte, so memory use is about `(capacity + 3) * frame.len()` bytes.
+/// Frames are stored with whatever stride they came with.
+pub struct Accumulator {
+    capacity: usize,
+    frames: VecDeque<Vec<u8>>,
+    /// Sum of every frame in `frames`.
+    sum: Vec<u16>,
+    /// Scratch sum for averaging fewer frames than are stored.
+    partial: Vec<u16>,
+    average: Vec<u8>,
+}
+
+impl Accumulator {
+    /// Panics if `capacity` is zero or above `MAX_ACCUMULATED_FRAMES`.
+    pub fn new(capacity: usize) -> Accumulator {
+        assert!(capacity > 0 && capacity <= MAX_ACCUMULATED_FRAMES);
+        Accumulator {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+            sum: Vec::new(),
+            partial: Vec::new(),
+            average: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many frames are currently stored.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Adds a frame, evicting the oldest one if full.
+    /// A frame of a different size (e.g. after a resolution change) resets first.
+    pub fn push(&mut self, frame: &[u8]) {
+        if frame.len() != self.sum.len() {
+            self.reset();
+            self.sum.resize(frame.len(), 0);
+        }
+
+        let mut buffer = if self.frames.len() == self.capacity {
+            let old = self.frames.pop_front().unwrap();
+            for (s, &o) in self.sum.iter_mut().zip(old.iter()) {
+                *s -= o as u16;
+            }
+            old
+        } else {
+            Vec::with_capacity(frame.len())
+        };
+
+        for (s, &n) in self.sum.iter_mut().zip(frame) {
+            *s += n as u16;
+        }
+
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+        self.frames.push_back(buffer);
+    }
+
+    /// The rounded mean of the last `n` frames, or of all of them if fewer are stored.
+    pub fn average(&mut self, n: usize) -> Option<&[u8]> {
+        let n = n.min(self.frames.len());
+        if n == 0 {
+            return None;
+        }
+
+        let sum = if n == self.frames.len() {
+            &self.sum
+        } else {
+            self.partial.clear();
+            self.partial.resize(self.sum.len(), 0);
+            for frame in self.frames.iter().rev().take(n) {
+                for (s, &p) in self.partial.iter_mut().zip(frame.iter()) {
+                    *s += p as u16;
+                }
+            }
+            &self.partial
+        };
+
+        let half = n as u32 / 2;
+        self.average.clear();
+        self.average
+            .extend(sum.iter().map(|&s| ((s as u32 + half) / n as u32) as u8));
+        Some(&self.average)
+    }
+
+    /// The bytes currently allocated, including spare capacity.
+    pub fn memory_usage(&self) -> usize {
+        let frames: usize = self.frames.iter().map(|f| f.capacity()).sum();
+        frames + 2 * self.sum.capacity() + 2 * self.partial.capacity() + self.average.capacity()
+    }
+
+    /// Drops every stored frame.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.sum.clear();
+        self.partial.clear();
+    }
+}
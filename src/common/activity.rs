@@ -0,0 +1,158 @@
+use super::Rect;
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Tuning for `ActivityTracker`.
+#[derive(Copy, Clone, Debug)]
+pub struct Activity {
+    /// How long a change keeps contributing to the active area.
+    pub decay: Duration,
+    /// Pixels added around the active area on every side.
+    pub padding: u32,
+    /// The smallest crop that will be suggested.
+    pub min_width: u32,
+    pub min_height: u32,
+    /// How many pixels the crop may be oversized by before it shrinks.
+    /// Growing is always immediate, so nothing active is ever cut off.
+    pub hysteresis: u32,
+}
+
+impl Default for Activity {
+    fn default() -> Activity {
+        Activity {
+            decay: Duration::from_secs(2),
+            padding: 16,
+            min_width: 320,
+            min_height: 240,
+            hysteresis: 64,
+        }
+    }
+}
+
+/// A decaying union of recently changed rectangles.
+pub struct ActivityTracker {
+    config: Activity,
+    /// The union of each frame's changes, oldest first.
+    history: VecDeque<(Instant, Rect)>,
+    crop: Option<Rect>,
+}
+
+impl ActivityTracker {
+    pub fn new(config: Activity) -> ActivityTracker {
+        ActivityTracker {
+            config,
+            history: VecDeque::new(),
+            crop: None,
+        }
+    }
+
+    pub fn config(&self) -> &Activity {
+        &self.config
+    }
+
+    /// Records the rectangles that changed in one frame.
+    pub fn record(&mut self, rects: &[Rect]) {
+        let now = Instant::now();
+
+        while let Some(&(time, _)) = self.history.front() {
+            if now.duration_since(time) > self.config.decay {
+                self.history.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let changed = rects.iter().fold(Rect::default(), |acc, r| acc.union(r));
+        if !changed.is_empty() {
+            self.history.push_back((now, changed));
+        }
+    }
+
+    /// The union of everything that changed within `window`.
+    /// Nothing older than the configured decay is remembered.
+    pub fn bounds(&self, window: Duration) -> Option<Rect> {
+        let now = Instant::now();
+        let bounds = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|&&(time, _)| now.duration_since(time) <= window)
+            .fold(Rect::default(), |acc, &(_, r)| acc.union(&r));
+
+        if bounds.is_empty() {
+            None
+        } else {
+            Some(bounds)
+        }
+    }
+
+    /// A stable crop of a `width` by `height` frame around the active area.
+    ///
+    /// The crop is padded, at least the minimum size (where the frame allows),
+    /// and always lies within the frame. With no recent activity, the previous
+    /// crop is kept, or the whole frame is used if there is none.
+    pub fn crop(&mut self, width: u32, height: u32) -> Rect {
+        let frame = Rect::new(0, 0, width, height);
+
+        let target = match self.bounds(self.config.decay) {
+            Some(bounds) => self.fit(bounds, frame),
+            None => {
+                let crop = self.crop.and_then(|c| c.intersection(&frame));
+                let crop = crop.unwrap_or(frame);
+                self.crop = Some(crop);
+                return crop;
+            }
+        };
+
+        let crop = match self.crop {
+            Some(crop) if crop.contains(&target) && frame.contains(&crop) => {
+                let slack = self.config.hysteresis as i32;
+                if target.x - crop.x > slack
+                    || target.y - crop.y > slack
+                    || crop.right() - target.right() > slack
+                    || crop.bottom() - target.bottom() > slack
+                {
+                    target
+                } else {
+                    crop
+                }
+            }
+            _ => target,
+        };
+
+        self.crop = Some(crop);
+        crop
+    }
+
+    /// Forgets all activity, e.g. after the resolution changed.
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.crop = None;
+    }
+
+    fn fit(&self, bounds: Rect, frame: Rect) -> Rect {
+        let pad = self.config.padding as i32;
+        let mut rect = Rect::from_edges(
+            bounds.x - pad,
+            bounds.y - pad,
+            bounds.right() + pad,
+            bounds.bottom() + pad,
+        );
+
+        // Grow around the center to the minimum size.
+        let (w, h) = (
+            cmp::min(cmp::max(rect.w, self.config.min_width), frame.w),
+            cmp::min(cmp::max(rect.h, self.config.min_height), frame.h),
+        );
+        rect.x -= (w - cmp::min(rect.w, w)) as i32 / 2;
+        rect.y -= (h - cmp::min(rect.h, h)) as i32 / 2;
+        rect.w = w;
+        rect.h = h;
+
+        // Slide it back inside the frame.
+        rect.x = cmp::max(cmp::min(rect.x, frame.right() - rect.w as i32), 0);
+        rect.y = cmp::max(cmp::min(rect.y, frame.bottom() - rect.h as i32), 0);
+        rect
+    }
+}
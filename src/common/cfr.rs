@@ -0,0 +1,143 @@
+use super::Capturer;
+use std::io::ErrorKind::WouldBlock;
+use std::time::{Duration, Instant};
+use std::{io, ops, thread};
+
+/// Turns a capturer's variable rate into exactly `fps` frames per second,
+/// as encoders and muxers like.
+///
+/// Each tick takes the newest frame, so several changes between ticks
+/// collapse into one, and a tick without changes repeats the last frame.
+/// Ticks are scheduled from the start time rather than from each other, so
+/// they don't drift. If the caller falls behind, the missed ticks are
+/// returned straight away.
+pub struct ConstantRate {
+    fps: f64,
+    start: Option<Instant>,
+    tick: u64,
+    last: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl ConstantRate {
+    pub fn new(fps: f64) -> ConstantRate {
+        assert!(fps > 0.0, "frame rate must be positive");
+        ConstantRate {
+            fps,
+            start: None,
+            tick: 0,
+            last: Vec::new(),
+            width: 0,
+            height: 0,
+            stride: 0,
+        }
+    }
+
+    /// When `tick` is due, relative to the first.
+    pub fn timestamp(&self, tick: u64) -> Duration {
+        Duration::from_secs_f64(tick as f64 / self.fps)
+    }
+
+    /// Waits for the next tick and returns its frame.
+    ///
+    /// Before the first frame has arrived, this fails with `WouldBlock`
+    /// without using up a tick.
+    pub fn next_frame(&mut self, capturer: &mut Capturer) -> io::Result<CfrFrame<'_>> {
+        if let Some(start) = self.start {
+            let due = start + self.timestamp(self.tick);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        #[cfg(dxgi)]
+        capturer.reserve_buffer(
+            "constant rate frame",
+            self.last
+                .capacity()
+                .max(capturer.width() * capturer.height() * 4),
+        )?;
+        let duplicate = match capturer.frame() {
+            Ok(frame) => {
+                self.width = frame.width();
+                self.height = frame.height();
+                self.stride = frame.stride();
+                self.last.clear();
+                self.last.extend_from_slice(&frame);
+                false
+            }
+            Err(ref error) if error.kind() == WouldBlock && !self.last.is_empty() => true,
+            Err(error) => return Err(error),
+        };
+
+        // The grid starts with the first frame.
+        self.start.get_or_insert_with(Instant::now);
+        let tick = self.tick;
+        self.tick += 1;
+        Ok(CfrFrame {
+            data: &self.last,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            tick,
+            timestamp: self.timestamp(tick),
+            duplicate,
+        })
+    }
+
+    /// The bytes held for repeating the last frame.
+    pub fn memory_usage(&self) -> usize {
+        self.last.capacity()
+    }
+}
+
+/// A frame from `ConstantRate`, laid out like the captured one.
+pub struct CfrFrame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    tick: u64,
+    timestamp: Duration,
+    duplicate: bool,
+}
+
+impl<'a> CfrFrame<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Which tick this is, counting from 0.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Where this frame belongs on the fixed grid, from the first tick.
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Whether this repeats the previous tick's frame.
+    pub fn is_duplicate(&self) -> bool {
+        self.duplicate
+    }
+}
+
+impl<'a> ops::Deref for CfrFrame<'a> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
@@ -0,0 +1,462 @@
+//! VP8 and VP9 through libvpx, with the `vpx` feature. The encoder takes
+//! I420 frames, e.g. from `convert::bgra_to_i420`, and the decoder gives them
+//! back, which is enough for loopback tests.
+
+use super::convert::{self, YuvBuffer, YuvMatrix};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr, slice};
+use vpx_sys::*;
+
+/// `VPX_EFLAG_FORCE_KF` in `vpx_encoder.h`.
+const FORCE_KEYFRAME: vpx_enc_frame_flags_t = 1;
+/// `VPX_FRAME_IS_KEY` in `vpx_encoder.h`.
+const FRAME_IS_KEY: u32 = 1;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VideoCodec {
+    Vp8,
+    Vp9,
+}
+
+/// A compressed frame.
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    /// Which frame this is, counting from 0.
+    pub pts: i64,
+    /// When it's shown, from the first frame.
+    pub timestamp: Duration,
+}
+
+/// Encodes frames of one size at a constant frame rate.
+pub struct Encoder {
+    ctx: vpx_codec_ctx_t,
+    cfg: vpx_codec_enc_cfg_t,
+    width: usize,
+    height: usize,
+    fps: u32,
+    pts: i64,
+    force_keyframe: bool,
+    /// The I420 copy `encode_bgra` converts into.
+    yuv: YuvBuffer,
+    encode_time: Duration,
+}
+
+impl Encoder {
+    /// `bitrate` is in kilobits per second. Panics unless `fps` is positive.
+    pub fn new(
+        width: usize,
+        height: usize,
+        fps: u32,
+        bitrate: u32,
+        codec: VideoCodec,
+    ) -> io::Result<Encoder> {
+        assert!(fps > 0, "frame rate must be positive");
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frames can't be empty",
+            ));
+        }
+
+        unsafe {
+            let iface = match codec {
+                VideoCodec::Vp8 => vpx_codec_vp8_cx(),
+                VideoCodec::Vp9 => vpx_codec_vp9_cx(),
+            };
+            let mut cfg: vpx_codec_enc_cfg_t = mem::zeroed();
+            check(
+                "vpx_codec_enc_config_default",
+                vpx_codec_enc_config_default(iface, &mut cfg, 0),
+            )?;
+            cfg.g_w = width as _;
+            cfg.g_h = height as _;
+            cfg.g_timebase.num = 1;
+            cfg.g_timebase.den = fps as _;
+            cfg.rc_target_bitrate = bitrate as _;
+            // Every frame comes out of the call that took it in.
+            cfg.g_lag_in_frames = 0;
+
+            let mut ctx: vpx_codec_ctx_t = mem::zeroed();
+            check(
+                "vpx_codec_enc_init",
+                vpx_codec_enc_init_ver(&mut ctx, iface, &cfg, 0, VPX_ENCODER_ABI_VERSION as _),
+            )?;
+            Ok(Encoder {
+                ctx,
+                cfg,
+                width,
+                height,
+                fps,
+                pts: 0,
+                force_keyframe: false,
+                yuv: YuvBuffer::new(),
+                encode_time: Duration::ZERO,
+            })
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The target bitrate, in kilobits per second.
+    pub fn bitrate(&self) -> u32 {
+        self.cfg.rc_target_bitrate
+    }
+
+    /// Changes the target bitrate from the next frame on.
+    pub fn set_bitrate(&mut self, bitrate: u32) -> io::Result<()> {
+        let old = self.cfg.rc_target_bitrate;
+        self.cfg.rc_target_bitrate = bitrate as _;
+        let result = unsafe {
+            check(
+                "vpx_codec_enc_config_set",
+                vpx_codec_enc_config_set(&mut self.ctx, &self.cfg),
+            )
+        };
+        if result.is_err() {
+            self.cfg.rc_target_bitrate = old;
+        }
+        result
+    }
+
+    /// Makes the next frame a keyframe, e.g. for a viewer that just joined.
+    pub fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    /// How long the last `encode` took.
+    pub fn encode_time(&self) -> Duration {
+        self.encode_time
+    }
+
+    /// Encodes the next frame. Fails with `InvalidInput` if it isn't the
+    /// encoder's size.
+    pub fn encode(&mut self, frame: &YuvBuffer) -> io::Result<Vec<Packet>> {
+        if (frame.width(), frame.height()) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the frame is {}x{}, but the encoder takes {}x{}",
+                    frame.width(),
+                    frame.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+
+        let start = Instant::now();
+        let packets = unsafe {
+            let mut image: vpx_image_t = mem::zeroed();
+            vpx_img_wrap(
+                &mut image,
+                vpx_img_fmt::VPX_IMG_FMT_I420,
+                self.width as _,
+                self.height as _,
+                1,
+                frame.y.as_ptr() as *mut u8,
+            );
+            image.planes[0] = frame.y.as_ptr() as *mut u8;
+            image.planes[1] = frame.u.as_ptr() as *mut u8;
+            image.planes[2] = frame.v.as_ptr() as *mut u8;
+            image.stride[0] = frame.y_stride() as _;
+            image.stride[1] = frame.uv_stride() as _;
+            image.stride[2] = frame.uv_stride() as _;
+            self.encode_image(&image)?
+        };
+        self.encode_time = start.elapsed();
+        Ok(packets)
+    }
+
+    /// Converts a BGRA frame to I420 with `YuvMatrix::Bt601` and encodes it.
+    pub fn encode_bgra(&mut self, src: &[u8], stride: usize) -> io::Result<Vec<Packet>> {
+        let mut yuv = mem::take(&mut self.yuv);
+        let (width, height) = (self.width, self.height);
+        convert::bgra_to_i420(src, stride, width, height, YuvMatrix::Bt601, None, &mut yuv);
+        let result = self.encode(&yuv);
+        self.yuv = yuv;
+        result
+    }
+
+    /// Flushes what's left. Nothing is held back with the encoder set up
+    /// like this, but it doesn't hurt at the end of a stream.
+    pub fn finish(&mut self) -> io::Result<Vec<Packet>> {
+        unsafe { self.encode_image(ptr::null()) }
+    }
+
+    unsafe fn encode_image(&mut self, image: *const vpx_image_t) -> io::Result<Vec<Packet>> {
+        let flags = if self.force_keyframe {
+            FORCE_KEYFRAME
+        } else {
+            0
+        };
+        check(
+            "vpx_codec_encode",
+            vpx_codec_encode(
+                &mut self.ctx,
+                image,
+                self.pts,
+                1,
+                flags as _,
+                VPX_DL_REALTIME as _,
+            ),
+        )
+        .map_err(|err| self.with_detail(err))?;
+        self.force_keyframe = false;
+        if !image.is_null() {
+            self.pts += 1;
+        }
+
+        let mut packets = Vec::new();
+        let mut iter: vpx_codec_iter_t = ptr::null();
+        loop {
+            let pkt = vpx_codec_get_cx_data(&mut self.ctx, &mut iter);
+            if pkt.is_null() {
+                break;
+            }
+            if (*pkt).kind != vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT {
+                continue;
+            }
+            let frame = &(*pkt).data.frame;
+            packets.push(Packet {
+                data: slice::from_raw_parts(frame.buf as *const u8, frame.sz as usize).to_vec(),
+                keyframe: frame.flags as u32 & FRAME_IS_KEY != 0,
+                pts: frame.pts,
+                timestamp: Duration::from_secs_f64(frame.pts as f64 / self.fps as f64),
+            });
+        }
+        Ok(packets)
+    }
+
+    fn with_detail(&self, err: io::Error) -> io::Error {
+        with_detail(&self.ctx, err)
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe {
+            vpx_codec_destroy(&mut self.ctx);
+        }
+    }
+}
+
+// libvpx contexts aren't tied to the thread that made them.
+unsafe impl Send for Encoder {}
+
+/// Decodes what an `Encoder` of the same codec made.
+pub struct Decoder {
+    ctx: vpx_codec_ctx_t,
+}
+
+impl Decoder {
+    pub fn new(codec: VideoCodec) -> io::Result<Decoder> {
+        unsafe {
+            let iface = match codec {
+                VideoCodec::Vp8 => vpx_codec_vp8_dx(),
+                VideoCodec::Vp9 => vpx_codec_vp9_dx(),
+            };
+            let mut ctx: vpx_codec_ctx_t = mem::zeroed();
+            check(
+                "vpx_codec_dec_init",
+                vpx_codec_dec_init_ver(
+                    &mut ctx,
+                    iface,
+                    ptr::null(),
+                    0,
+                    VPX_DECODER_ABI_VERSION as _,
+                ),
+            )?;
+            Ok(Decoder { ctx })
+        }
+    }
+
+    /// Decodes a packet into `out`. Returns whether it held a frame to show.
+    pub fn decode(&mut self, data: &[u8], out: &mut YuvBuffer) -> io::Result<bool> {
+        unsafe {
+            check(
+                "vpx_codec_decode",
+                vpx_codec_decode(
+                    &mut self.ctx,
+                    data.as_ptr(),
+                    data.len() as _,
+                    ptr::null_mut(),
+                    0,
+                ),
+            )
+            .map_err(|err| with_detail(&self.ctx, err))?;
+
+            let mut iter: vpx_codec_iter_t = ptr::null();
+            let image = vpx_codec_get_frame(&mut self.ctx, &mut iter);
+            if image.is_null() {
+                return Ok(false);
+            }
+            let image = &*image;
+            if image.fmt != vpx_img_fmt::VPX_IMG_FMT_I420 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("decoded a {:?} frame instead of I420", image.fmt),
+                ));
+            }
+
+            let (width, height) = (image.d_w as usize, image.d_h as usize);
+            out.resize(width, height);
+            let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+            copy_plane(image, 0, width, height, &mut out.y);
+            copy_plane(image, 1, cw, ch, &mut out.u);
+            copy_plane(image, 2, cw, ch, &mut out.v);
+            Ok(true)
+        }
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe {
+            vpx_codec_destroy(&mut self.ctx);
+        }
+    }
+}
+
+unsafe impl Send for Decoder {}
+
+/// Packs plane `i` of `image`, `width` by `height`, into `dst`.
+unsafe fn copy_plane(image: &vpx_image_t, i: usize, width: usize, height: usize, dst: &mut [u8]) {
+    let stride = image.stride[i] as isize;
+    for (y, row) in dst.chunks_exact_mut(width).take(height).enumerate() {
+        let src = image.planes[i].offset(y as isize * stride);
+        row.copy_from_slice(slice::from_raw_parts(src, width));
+    }
+}
+
+fn check(call: &'static str, err: vpx_codec_err_t) -> io::Result<()> {
+    if err == vpx_codec_err_t::VPX_CODEC_OK {
+        return Ok(());
+    }
+    let message = unsafe { string(vpx_codec_err_to_string(err)) };
+    Err(io::Error::other(format!("{} failed: {}", call, message)))
+}
+
+/// Adds libvpx's detail about the last error, if it has any.
+fn with_detail(ctx: &vpx_codec_ctx_t, err: io::Error) -> io::Error {
+    let detail = unsafe { vpx_codec_error_detail(ctx) };
+    if detail.is_null() {
+        return err;
+    }
+    io::Error::new(
+        err.kind(),
+        format!("{} ({})", err, unsafe { string(detail) }),
+    )
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 96;
+    const HEIGHT: usize = 64;
+
+    /// A gradient with a bright square moving across it.
+    fn pattern(frame: usize) -> Vec<u8> {
+        let mut bgra = Vec::with_capacity(WIDTH * HEIGHT * 4);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let left = frame * 3 % WIDTH;
+                let inside = (x + WIDTH - left) % WIDTH < 16 && (16..32).contains(&y);
+                let px = if inside {
+                    [40, 220, 250, 255]
+                } else {
+                    [(x * 2) as u8, (y * 3) as u8, (x + y) as u8, 255]
+                };
+                bgra.extend_from_slice(&px);
+            }
+        }
+        bgra
+    }
+
+    fn psnr(a: &[u8], b: &[u8]) -> f64 {
+        assert_eq!(a.len(), b.len());
+        let sum: f64 = a
+            .iter()
+            .zip(b)
+            .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+            .sum();
+        let mse = sum / a.len() as f64;
+        if mse == 0.0 {
+            return f64::INFINITY;
+        }
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    fn round_trip(codec: VideoCodec) {
+        let mut encoder = Encoder::new(WIDTH, HEIGHT, 30, 2000, codec).unwrap();
+        let mut decoder = Decoder::new(codec).unwrap();
+        let (mut expected, mut decoded) = (YuvBuffer::new(), YuvBuffer::new());
+
+        for i in 0..12 {
+            if i == 6 {
+                encoder.force_keyframe();
+                encoder.set_bitrate(1500).unwrap();
+            }
+            let bgra = pattern(i);
+            let stride = WIDTH * 4;
+            convert::bgra_to_i420(
+                &bgra,
+                stride,
+                WIDTH,
+                HEIGHT,
+                YuvMatrix::Bt601,
+                None,
+                &mut expected,
+            );
+            let packets = encoder.encode_bgra(&bgra, stride).unwrap();
+            assert_eq!(packets.len(), 1, "frame {}", i);
+            let packet = &packets[0];
+            assert_eq!(packet.pts, i as i64);
+            assert_eq!(packet.timestamp, Duration::from_secs_f64(i as f64 / 30.0));
+            assert_eq!(packet.keyframe, i == 0 || i == 6, "frame {}", i);
+
+            assert!(decoder.decode(&packet.data, &mut decoded).unwrap());
+            assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+            let quality = psnr(&expected.y, &decoded.y);
+            assert!(quality > 30.0, "frame {} has a PSNR of {:.1}", i, quality);
+        }
+        assert_eq!(encoder.bitrate(), 1500);
+        assert!(encoder.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn vp8_round_trip() {
+        round_trip(VideoCodec::Vp8);
+    }
+
+    #[test]
+    fn vp9_round_trip() {
+        round_trip(VideoCodec::Vp9);
+    }
+
+    #[test]
+    fn frames_must_be_the_encoders_size() {
+        let mut encoder = Encoder::new(WIDTH, HEIGHT, 30, 500, VideoCodec::Vp8).unwrap();
+        let mut yuv = YuvBuffer::new();
+        yuv.resize(WIDTH / 2, HEIGHT);
+        let err = encoder.encode(&yuv).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
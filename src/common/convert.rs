@@ -0,0 +1,981 @@
+use std::{cmp, fmt};
+
+/// Full-range BT.601 luma of a pixel.
+pub fn luma(b: u8, g: u8, r: u8) -> u8 {
+    ((77 * r as u32 + 150 * g as u32 + 29 * b as u32 + 128) >> 8) as u8
+}
+
+/// A brightness, contrast and gamma tweak to apply while converting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Adjustment {
+    /// Values are raised to `1 / gamma`, so above 1 brightens midtones.
+    pub gamma: f32,
+    /// Added last, from -1 to 1.
+    pub brightness: f32,
+    /// Stretches values around mid-grey. 1 leaves them alone.
+    pub contrast: f32,
+}
+
+impl Default for Adjustment {
+    fn default() -> Adjustment {
+        Adjustment {
+            gamma: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+        }
+    }
+}
+
+impl Adjustment {
+    /// The table for this adjustment, or `None` if it wouldn't change
+    /// anything, so that converting can skip it entirely.
+    pub fn lut(&self) -> Option<Lut> {
+        let mut table = [0; 256];
+        for (i, out) in table.iter_mut().enumerate() {
+            let v = (i as f32 / 255.0).powf(1.0 / self.gamma);
+            let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+            *out = (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+        }
+
+        if table.iter().enumerate().all(|(i, &v)| v as usize == i) {
+            None
+        } else {
+            Some(Lut(table))
+        }
+    }
+}
+
+/// Maps every color channel value to another, e.g. from `Adjustment::lut`.
+#[derive(Clone)]
+pub struct Lut(pub [u8; 256]);
+
+impl Lut {
+    pub fn map(&self, value: u8) -> u8 {
+        self.0[value as usize]
+    }
+
+    /// Maps every byte, for pixels without alpha.
+    pub fn apply(&self, bytes: &mut [u8]) {
+        for b in bytes {
+            *b = self.0[*b as usize];
+        }
+    }
+
+    /// Maps the color channels of packed BGRA pixels, leaving alpha alone.
+    pub fn apply_bgra(&self, pixels: &mut [u8]) {
+        for px in pixels.chunks_exact_mut(4) {
+            px[0] = self.0[px[0] as usize];
+            px[1] = self.0[px[1] as usize];
+            px[2] = self.0[px[2] as usize];
+        }
+    }
+}
+
+impl fmt::Debug for Lut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Lut").field(&&self.0[..]).finish()
+    }
+}
+
+/// Takes every `factor`th pixel of every `factor`th row of a BGRA image.
+///
+/// The result is `width / factor` by `height / factor` pixels, rounding
+/// down, so a partial block at the right or bottom edge is dropped.
+/// It is written to `dst` as tightly packed BGRA, mapped through `lut`.
+pub fn decimate(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    factor: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    assert!(factor > 0, "decimation factor must be positive");
+    let (w, h) = (width / factor, height / factor);
+
+    dst.clear();
+    if w == 0 || h == 0 {
+        return;
+    }
+    dst.resize(w * h * 4, 0);
+
+    for (y, out) in dst.chunks_exact_mut(w * 4).enumerate() {
+        let row = &src[y * factor * stride..][..w * factor * 4];
+        for (px, block) in out.chunks_exact_mut(4).zip(row.chunks_exact(factor * 4)) {
+            px.copy_from_slice(&block[..4]);
+        }
+        if let Some(lut) = lut {
+            lut.apply_bgra(out);
+        }
+    }
+}
+
+/// Like `decimate`, but writes one luma byte per pixel instead.
+pub fn decimate_luma(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    factor: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    assert!(factor > 0, "decimation factor must be positive");
+    let (w, h) = (width / factor, height / factor);
+
+    dst.clear();
+    if w == 0 || h == 0 {
+        return;
+    }
+    dst.resize(w * h, 0);
+
+    for (y, out) in dst.chunks_exact_mut(w).enumerate() {
+        let row = &src[y * factor * stride..][..w * factor * 4];
+        for (l, block) in out.iter_mut().zip(row.chunks_exact(factor * 4)) {
+            *l = luma(block[0], block[1], block[2]);
+        }
+        if let Some(lut) = lut {
+            for l in out.iter_mut() {
+                *l = lut.map(*l);
+            }
+        }
+    }
+}
+
+/// Converts a BGRA image to tightly packed RGBA, swapping red and blue.
+pub fn bgra_to_rgba(src: &[u8], stride: usize, width: usize, height: usize, dst: &mut Vec<u8>) {
+    dst.clear();
+    dst.reserve(width * height * 4);
+    for y in 0..height {
+        let row = &src[y * stride..][..width * 4];
+        dst.extend(
+            row.chunks_exact(4)
+                .flat_map(|px| [px[2], px[1], px[0], px[3]]),
+        );
+    }
+}
+
+/// Converts a BGRA image to tightly packed 24-bit RGB, dropping alpha and
+/// mapping colors through `lut`.
+pub fn bgra_to_rgb24(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    drop_alpha(src, stride, width, height, [2, 1, 0], lut, dst);
+}
+
+/// Converts a BGRA image to tightly packed 24-bit BGR, dropping alpha and
+/// mapping colors through `lut`.
+pub fn bgra_to_bgr24(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    drop_alpha(src, stride, width, height, [0, 1, 2], lut, dst);
+}
+
+/// Copies channels `order` of each pixel, mapped through `lut`.
+fn drop_alpha(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    order: [usize; 3],
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    // Without a table, the copies are kept plain so they can be vectorized.
+    match lut {
+        Some(lut) => copy_channels(src, stride, width, height, order, |v| lut.map(v), dst),
+        None => copy_channels(src, stride, width, height, order, |v| v, dst),
+    }
+}
+
+/// Copies channels `order` of each pixel through `c`. Works on four pixels
+/// at a time, in fixed-size blocks, so the copies can be vectorized.
+fn copy_channels<F>(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    order: [usize; 3],
+    c: F,
+    dst: &mut Vec<u8>,
+) where
+    F: Fn(u8) -> u8,
+{
+    dst.clear();
+    dst.resize(width * height * 3, 0);
+    if width == 0 {
+        return;
+    }
+
+    for (y, out) in dst.chunks_exact_mut(width * 3).enumerate() {
+        let row = &src[y * stride..][..width * 4];
+        let mut pixels = row.chunks_exact(16);
+        let mut outs = out.chunks_exact_mut(12);
+        for (px, out) in (&mut pixels).zip(&mut outs) {
+            for i in 0..4 {
+                out[i * 3] = c(px[i * 4 + order[0]]);
+                out[i * 3 + 1] = c(px[i * 4 + order[1]]);
+                out[i * 3 + 2] = c(px[i * 4 + order[2]]);
+            }
+        }
+        let rest = pixels.remainder().chunks_exact(4);
+        for (px, out) in rest.zip(outs.into_remainder().chunks_exact_mut(3)) {
+            out[0] = c(px[order[0]]);
+            out[1] = c(px[order[1]]);
+            out[2] = c(px[order[2]]);
+        }
+    }
+}
+
+/// Converts a BGRA image to tightly packed little-endian RGB565, keeping the
+/// top 5, 6 and 5 bits of red, green and blue after mapping them through
+/// `lut`.
+pub fn bgra_to_rgb565(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    dst.clear();
+    dst.reserve(width * height * 2);
+    let c = |v: u8| lut.map_or(v, |lut| lut.map(v)) as u16;
+    for y in 0..height {
+        let row = &src[y * stride..][..width * 4];
+        dst.extend(row.chunks_exact(4).flat_map(|px| {
+            let (b, g, r) = (c(px[0]), c(px[1]), c(px[2]));
+            ((r >> 3) << 11 | (g >> 2) << 5 | b >> 3).to_le_bytes()
+        }));
+    }
+}
+
+/// Like `bgra_to_rgba`, but sets alpha to opaque, for sources that leave
+/// it 0 or garbage, and maps colors through `lut`.
+pub fn bgra_to_rgba_opaque(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    dst.clear();
+    dst.reserve(width * height * 4);
+    let c = |v: u8| lut.map_or(v, |lut| lut.map(v));
+    for y in 0..height {
+        let row = &src[y * stride..][..width * 4];
+        dst.extend(
+            row.chunks_exact(4)
+                .flat_map(|px| [c(px[2]), c(px[1]), c(px[0]), 255]),
+        );
+    }
+}
+
+/// A 64-bit hash of an image's pixels, ignoring any padding between rows,
+/// for telling whether two frames are the same. It's FxHash-style: fast, but
+/// not meant to resist deliberate collisions. The size is hashed too.
+pub fn hash_pixels(src: &[u8], stride: usize, width: usize, height: usize, bpp: usize) -> u64 {
+    const K: u64 = 0x517c_c1b7_2722_0a95;
+    let add = |hash: u64, word: u64| (hash.rotate_left(5) ^ word).wrapping_mul(K);
+
+    let mut hash = add(add(0, width as u64), height as u64);
+    for y in 0..height {
+        let row = &src[y * stride..][..width * bpp];
+        let mut words = row.chunks_exact(8);
+        for word in &mut words {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(word);
+            hash = add(hash, u64::from_le_bytes(bytes));
+        }
+        let rest = words.remainder();
+        if !rest.is_empty() {
+            let mut bytes = [0; 8];
+            bytes[..rest.len()].copy_from_slice(rest);
+            hash = add(hash, u64::from_le_bytes(bytes));
+        }
+    }
+
+    // Spreads the last words' bits over the whole hash.
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ hash >> 33
+}
+
+/// How far a display is turned clockwise from its framebuffer.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Rotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// Whether the display's width and height are swapped.
+    pub fn is_sideways(self) -> bool {
+        self == Rotation::Rotate90 || self == Rotation::Rotate270
+    }
+
+    /// Where pixel (`x`, `y`) of the display is in a `width` by `height`
+    /// framebuffer.
+    pub fn source(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::Identity => (x, y),
+            Rotation::Rotate90 => (width - 1 - y, x),
+            Rotation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Rotation::Rotate270 => (y, height - 1 - x),
+        }
+    }
+}
+
+/// Turns a `width` by `height` framebuffer of `bpp`-byte pixels the way the
+/// display shows it, writing it to `dst` without padding. Returns the new
+/// width and height, which are swapped if the display is sideways.
+pub fn rotate(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    bpp: usize,
+    rotation: Rotation,
+    dst: &mut Vec<u8>,
+) -> (usize, usize) {
+    let (out_w, out_h) = if rotation.is_sideways() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    dst.clear();
+    dst.reserve(out_w * out_h * bpp);
+    for y in 0..out_h {
+        for x in 0..out_w {
+            let (sx, sy) = rotation.source(x, y, width, height);
+            dst.extend_from_slice(&src[sy * stride + sx * bpp..][..bpp]);
+        }
+    }
+    (out_w, out_h)
+}
+
+/// Which coefficients to convert to YUV with. Either way the result is
+/// limited range, as video encoders expect.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum YuvMatrix {
+    /// Standard definition.
+    #[default]
+    Bt601,
+    /// High definition.
+    Bt709,
+}
+
+impl YuvMatrix {
+    /// The Y, U and V rows, scaled by 256, for R, G and B.
+    fn coefficients(self) -> [[i32; 3]; 3] {
+        match self {
+            YuvMatrix::Bt601 => [[66, 129, 25], [-38, -74, 112], [112, -94, -18]],
+            YuvMatrix::Bt709 => [[47, 157, 16], [-26, -87, 113], [112, -102, -10]],
+        }
+    }
+}
+
+/// A planar I420 image: full-size Y, then U and V at half size in each
+/// direction, rounding up. Rows are packed, so the strides are the plane
+/// widths.
+#[derive(Clone, Debug, Default)]
+pub struct YuvBuffer {
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl YuvBuffer {
+    pub fn new() -> YuvBuffer {
+        YuvBuffer::default()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn y_stride(&self) -> usize {
+        self.width
+    }
+
+    /// The stride of both the U and V planes.
+    pub fn uv_stride(&self) -> usize {
+        self.width.div_ceil(2)
+    }
+
+    /// Bytes held by the planes, including spare capacity.
+    pub fn memory_usage(&self) -> usize {
+        self.y.capacity() + self.u.capacity() + self.v.capacity()
+    }
+
+    /// Sizes the planes for a `width` by `height` image.
+    pub(crate) fn resize(&mut self, width: usize, height: usize) {
+        let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+        self.width = width;
+        self.height = height;
+        self.y.resize(width * height, 0);
+        self.u.resize(cw * ch, 0);
+        self.v.resize(cw * ch, 0);
+    }
+}
+
+/// Converts a BGRA image to I420, mapping colors through `lut` first.
+/// Each chroma sample averages a 2x2 block, or what's left of one at an
+/// odd edge.
+pub fn bgra_to_i420(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    matrix: YuvMatrix,
+    lut: Option<&Lut>,
+    dst: &mut YuvBuffer,
+) {
+    let [ky, ku, kv] = matrix.coefficients();
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    dst.resize(width, height);
+
+    let rgb = |x: usize, y: usize| rgb_at(src, stride, x, y, lut);
+    for y in 0..height {
+        for x in 0..width {
+            dst.y[y * width + x] = limited_luma(ky, rgb(x, y));
+        }
+    }
+
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let mut sum = [0; 3];
+            let mut n = 0;
+            for y in cy * 2..cmp::min(cy * 2 + 2, height) {
+                for x in cx * 2..cmp::min(cx * 2 + 2, width) {
+                    let p = rgb(x, y);
+                    for i in 0..3 {
+                        sum[i] += p[i];
+                    }
+                    n += 1;
+                }
+            }
+            let avg = [sum[0] / n, sum[1] / n, sum[2] / n];
+            dst.u[cy * cw + cx] = limited_chroma(ku, avg);
+            dst.v[cy * cw + cx] = limited_chroma(kv, avg);
+        }
+    }
+}
+
+/// A planar YUV 4:4:4 image: Y, U and V all at full size, so text edges
+/// keep their color. Rows are packed.
+#[derive(Clone, Debug, Default)]
+pub struct Yuv444Buffer {
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl Yuv444Buffer {
+    pub fn new() -> Yuv444Buffer {
+        Yuv444Buffer::default()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The stride of every plane.
+    pub fn stride(&self) -> usize {
+        self.width
+    }
+
+    /// Bytes held by the planes, including spare capacity.
+    pub fn memory_usage(&self) -> usize {
+        self.y.capacity() + self.u.capacity() + self.v.capacity()
+    }
+}
+
+/// Converts a BGRA image to YUV 4:4:4, mapping colors through `lut` first.
+/// Uses the same coefficients as `bgra_to_i420`, without subsampling.
+pub fn bgra_to_yuv444(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    matrix: YuvMatrix,
+    lut: Option<&Lut>,
+    dst: &mut Yuv444Buffer,
+) {
+    let [ky, ku, kv] = matrix.coefficients();
+    dst.width = width;
+    dst.height = height;
+    for plane in [&mut dst.y, &mut dst.u, &mut dst.v].iter_mut() {
+        plane.resize(width * height, 0);
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = rgb_at(src, stride, x, y, lut);
+            let i = y * width + x;
+            dst.y[i] = limited_luma(ky, p);
+            dst.u[i] = limited_chroma(ku, p);
+            dst.v[i] = limited_chroma(kv, p);
+        }
+    }
+}
+
+/// The red, green and blue of a BGRA pixel, mapped through `lut`.
+fn rgb_at(src: &[u8], stride: usize, x: usize, y: usize, lut: Option<&Lut>) -> [i32; 3] {
+    let px = &src[y * stride + x * 4..][..3];
+    let c = |i: usize| lut.map_or(px[i], |lut| lut.map(px[i])) as i32;
+    [c(2), c(1), c(0)]
+}
+
+fn dot(k: [i32; 3], p: [i32; 3]) -> i32 {
+    k[0] * p[0] + k[1] * p[1] + k[2] * p[2]
+}
+
+/// Limited-range Y, from 16 to 235.
+fn limited_luma(k: [i32; 3], rgb: [i32; 3]) -> u8 {
+    (((dot(k, rgb) + 128) >> 8) + 16) as u8
+}
+
+/// Limited-range U or V, centered on 128.
+fn limited_chroma(k: [i32; 3], rgb: [i32; 3]) -> u8 {
+    (((dot(k, rgb) + 128) >> 8) + 128).clamp(0, 255) as u8
+}
+
+/// How `to_sdr` squeezes HDR into SDR.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tonemap {
+    /// Maps SDR white to full brightness and clips everything above it.
+    Clip,
+    /// Extended Reinhard, rolling off smoothly so that `peak` times SDR
+    /// white reaches full brightness. Dims SDR white somewhat in exchange.
+    Reinhard { peak: f32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TonemapParams {
+    /// The brightness of SDR white, in nits. Windows defaults to 80.
+    pub sdr_white_nits: f32,
+    pub tonemap: Tonemap,
+}
+
+impl Default for TonemapParams {
+    fn default() -> TonemapParams {
+        TonemapParams {
+            sdr_white_nits: 80.0,
+            tonemap: Tonemap::Clip,
+        }
+    }
+}
+
+/// Converts an FP16 scRGB (RGBA, linear, 1.0 = 80 nits) image to sRGB BGRA,
+/// mapping colors through `lut` last.
+pub fn to_sdr(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    params: TonemapParams,
+    lut: Option<&Lut>,
+    dst: &mut Vec<u8>,
+) {
+    let scale = 80.0 / params.sdr_white_nits;
+
+    // Every channel is one of 65536 halves, so map each just once.
+    let table: Vec<u8> = (0..=u16::MAX)
+        .map(|half| {
+            let l = f16_to_f32(half).max(0.0) * scale;
+            let l = match params.tonemap {
+                Tonemap::Clip => l,
+                Tonemap::Reinhard { peak } => l * (1.0 + l / (peak * peak)) / (1.0 + l),
+            };
+            let l = l.min(1.0);
+            let v = if l <= 0.003_130_8 {
+                12.92 * l
+            } else {
+                1.055 * l.powf(1.0 / 2.4) - 0.055
+            };
+            // NaN becomes 0 here.
+            let v = (v * 255.0 + 0.5) as u8;
+            lut.map_or(v, |lut| lut.map(v))
+        })
+        .collect();
+
+    dst.clear();
+    if width == 0 || height == 0 {
+        return;
+    }
+    dst.resize(width * height * 4, 0);
+
+    for (y, out) in dst.chunks_exact_mut(width * 4).enumerate() {
+        let row = &src[y * stride..][..width * 8];
+        for (px, half) in out.chunks_exact_mut(4).zip(row.chunks_exact(8)) {
+            let channel = |i: usize| u16::from_le_bytes([half[2 * i], half[2 * i + 1]]);
+            px[0] = table[channel(2) as usize];
+            px[1] = table[channel(1) as usize];
+            px[2] = table[channel(0) as usize];
+            px[3] = (f16_to_f32(channel(3)).clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+        }
+    }
+}
+
+fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let man = (h & 0x3ff) as u32;
+
+    let bits = match exp {
+        0 if man == 0 => sign,
+        0 => {
+            // Subnormal, i.e. man * 2^-24.
+            let v = man as f32 / 16_777_216.0;
+            return if sign != 0 { -v } else { v };
+        }
+        0x1f => sign | 0x7f80_0000 | (man << 13),
+        _ => sign | ((exp + 112) << 23) | (man << 13),
+    };
+    f32::from_bits(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_source() {
+        // A 3 by 2 framebuffer, shown 2 by 3 when sideways.
+        let cases = [
+            (Rotation::Identity, (0, 0), (0, 0)),
+            (Rotation::Identity, (2, 1), (2, 1)),
+            (Rotation::Rotate90, (0, 0), (2, 0)),
+            (Rotation::Rotate90, (1, 2), (0, 1)),
+            (Rotation::Rotate180, (0, 0), (2, 1)),
+            (Rotation::Rotate180, (2, 1), (0, 0)),
+            (Rotation::Rotate270, (0, 0), (0, 1)),
+            (Rotation::Rotate270, (1, 2), (2, 0)),
+        ];
+        for &(rotation, (x, y), expected) in &cases {
+            assert_eq!(rotation.source(x, y, 3, 2), expected, "{:?}", rotation);
+        }
+    }
+
+    #[test]
+    fn rotation_source_covers_the_framebuffer() {
+        let (width, height) = (4, 3);
+        for &rotation in &[
+            Rotation::Identity,
+            Rotation::Rotate90,
+            Rotation::Rotate180,
+            Rotation::Rotate270,
+        ] {
+            let (shown_w, shown_h) = if rotation.is_sideways() {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            let mut seen = vec![false; width * height];
+            for y in 0..shown_h {
+                for x in 0..shown_w {
+                    let (sx, sy) = rotation.source(x, y, width, height);
+                    assert!(sx < width && sy < height, "{:?}", rotation);
+                    seen[sy * width + sx] = true;
+                }
+            }
+            assert!(seen.iter().all(|&s| s), "{:?}", rotation);
+        }
+    }
+
+    #[test]
+    fn rotate_all_four_ways() {
+        // 3 by 2 one-byte pixels, with a byte of padding on each row.
+        let src = [0, 1, 2, 9, 3, 4, 5, 9];
+        let cases = [
+            (Rotation::Identity, (3, 2), [0, 1, 2, 3, 4, 5]),
+            (Rotation::Rotate90, (2, 3), [2, 5, 1, 4, 0, 3]),
+            (Rotation::Rotate180, (3, 2), [5, 4, 3, 2, 1, 0]),
+            (Rotation::Rotate270, (2, 3), [3, 0, 4, 1, 5, 2]),
+        ];
+        let mut dst = Vec::new();
+        for &(rotation, size, expected) in &cases {
+            assert_eq!(rotate(&src, 4, 3, 2, 1, rotation, &mut dst), size);
+            assert_eq!(dst, expected, "{:?}", rotation);
+        }
+    }
+
+    #[test]
+    fn rotate_keeps_pixels_whole() {
+        let src = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut dst = Vec::new();
+        assert_eq!(
+            rotate(&src, 8, 2, 1, 4, Rotation::Rotate90, &mut dst),
+            (1, 2)
+        );
+        assert_eq!(dst, [5, 6, 7, 8, 1, 2, 3, 4]);
+    }
+
+    /// Pixels of RGBA halves, with a pixel of padding at the end.
+    fn scrgb(pixels: &[[u16; 4]]) -> Vec<u8> {
+        let mut data: Vec<u8> = pixels
+            .iter()
+            .flatten()
+            .flat_map(|h| h.to_le_bytes())
+            .collect();
+        data.extend_from_slice(&[0xaa; 8]);
+        data
+    }
+
+    const ZERO: u16 = 0x0000;
+    const HALF: u16 = 0x3800;
+    const ONE: u16 = 0x3c00;
+    const TWO: u16 = 0x4000;
+
+    #[test]
+    fn sdr_at_the_default_white() {
+        // Channels at black, half and SDR white, twice it, then negative,
+        // NaN and infinite ones, with the alpha clear and half.
+        let src = scrgb(&[
+            [ZERO, HALF, ONE, ONE],
+            [TWO, 0xbc00, 0x7e00, ZERO],
+            [0x7c00, ONE, ONE, HALF],
+        ]);
+        let mut dst = Vec::new();
+        to_sdr(
+            &src,
+            src.len(),
+            3,
+            1,
+            TonemapParams::default(),
+            None,
+            &mut dst,
+        );
+        // Negative and NaN channels are black, infinite ones clip.
+        #[rustfmt::skip]
+        let expected = [
+            255, 188, 0, 255,
+            0, 0, 255, 0,
+            255, 255, 255, 128,
+        ];
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn sdr_white_level_and_reinhard() {
+        let src = scrgb(&[[ONE, TWO, HALF, ONE]]);
+        let mut dst = Vec::new();
+
+        // Twice 80 nits is white, so 1.0 is half as bright.
+        let brighter = TonemapParams {
+            sdr_white_nits: 160.0,
+            tonemap: Tonemap::Clip,
+        };
+        to_sdr(&src, src.len(), 1, 1, brighter, None, &mut dst);
+        assert_eq!(dst, [137, 255, 188, 255]);
+
+        // Rolls off so that twice SDR white is just white.
+        let reinhard = TonemapParams {
+            sdr_white_nits: 80.0,
+            tonemap: Tonemap::Reinhard { peak: 2.0 },
+        };
+        to_sdr(&src, src.len(), 1, 1, reinhard, None, &mut dst);
+        assert_eq!(dst, [165, 255, 207, 255]);
+    }
+
+    /// Maps every value to its opposite.
+    fn invert() -> Lut {
+        let mut table = [0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = 255 - i as u8;
+        }
+        Lut(table)
+    }
+
+    #[test]
+    fn identity_adjustment_has_no_lut() {
+        assert!(Adjustment::default().lut().is_none());
+    }
+
+    #[test]
+    fn adjustment_lut_entries() {
+        let lut = |gamma, brightness, contrast| {
+            Adjustment {
+                gamma,
+                brightness,
+                contrast,
+            }
+            .lut()
+            .expect("not the identity")
+        };
+        // The value in, and what each adjustment maps it to.
+        type Case = (Lut, &'static [(u8, u8)]);
+        let cases: [Case; 3] = [
+            // Brighter midtones, with the ends pinned.
+            (
+                lut(2.0, 0.0, 1.0),
+                &[(0, 0), (16, 64), (64, 128), (255, 255)],
+            ),
+            // Stretched around mid-grey and clipped.
+            (
+                lut(1.0, 0.0, 1.5),
+                &[(0, 0), (42, 0), (100, 86), (255, 255)],
+            ),
+            // Shifted down by a fifth and clipped.
+            (lut(1.0, -0.2, 1.0), &[(30, 0), (100, 49), (255, 204)]),
+        ];
+        for (lut, entries) in &cases {
+            for &(value, mapped) in *entries {
+                assert_eq!(lut.map(value), mapped, "{} in {:?}", value, lut);
+            }
+        }
+    }
+
+    #[test]
+    fn drop_alpha_matches_a_plain_loop() {
+        for width in 0..10 {
+            for &padding in &[0, 4, 12] {
+                let (stride, height) = (width * 4 + padding, 3);
+                // The last row stops at its last pixel, as mappings can.
+                let len = stride * (height - 1) + width * 4;
+                let src: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+                for &order in &[[2, 1, 0], [0, 1, 2]] {
+                    for lut in [None, Some(invert())] {
+                        let c = |v: u8| lut.as_ref().map_or(v, |lut| lut.map(v));
+                        let mut naive = Vec::new();
+                        for y in 0..height {
+                            for x in 0..width {
+                                let px = &src[y * stride + x * 4..];
+                                naive.extend(order.iter().map(|&i| c(px[i])));
+                            }
+                        }
+                        let mut dst = vec![0xaa; 5];
+                        drop_alpha(&src, stride, width, height, order, lut.as_ref(), &mut dst);
+                        assert_eq!(dst, naive, "width {}, stride {}", width, stride);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A `width` by `height` BGRA image whose pixels are their own
+    /// coordinates, in rows `stride` bytes apart padded with 0xee.
+    fn coordinates(width: usize, height: usize, stride: usize) -> Vec<u8> {
+        let mut src = vec![0xee; stride * height];
+        for y in 0..height {
+            for x in 0..width {
+                let px = &mut src[y * stride + x * 4..][..4];
+                px.copy_from_slice(&[x as u8, y as u8, 100, 255]);
+            }
+        }
+        src
+    }
+
+    #[test]
+    fn decimate_rounds_down() {
+        // 5 by 3 at half size is 2 by 1: the last column and row are a
+        // partial block and dropped. Padded rows read the same.
+        for &stride in &[5 * 4, 5 * 4 + 8] {
+            let src = coordinates(5, 3, stride);
+            let mut dst = vec![0xaa; 3];
+            decimate(&src, stride, 5, 3, 2, None, &mut dst);
+            assert_eq!(dst, [0, 0, 100, 255, 2, 0, 100, 255], "stride {}", stride);
+
+            decimate_luma(&src, stride, 5, 3, 2, None, &mut dst);
+            assert_eq!(dst, [luma(0, 0, 100), luma(2, 0, 100)], "stride {}", stride);
+        }
+
+        // Three rows apart, from a padded source.
+        let stride = 7 * 4 + 4;
+        let src = coordinates(7, 6, stride);
+        let mut dst = Vec::new();
+        decimate(&src, stride, 7, 6, 3, None, &mut dst);
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 100, 255, 3, 0, 100, 255,
+            0, 3, 100, 255, 3, 3, 100, 255,
+        ];
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn decimate_past_the_image_is_empty() {
+        let src = coordinates(5, 3, 5 * 4);
+        // Wider than the image, and only taller.
+        for &factor in &[6, 4] {
+            let mut dst = vec![0xaa; 8];
+            decimate(&src, 5 * 4, 5, 3, factor, None, &mut dst);
+            assert!(dst.is_empty(), "factor {}", factor);
+
+            let mut dst = vec![0xaa; 8];
+            decimate_luma(&src, 5 * 4, 5, 3, factor, None, &mut dst);
+            assert!(dst.is_empty(), "factor {}", factor);
+        }
+    }
+
+    #[test]
+    fn rgba_is_opaque() {
+        // Alpha as DXGI leaves it, and a pixel of padding on each row.
+        #[rustfmt::skip]
+        let src = [
+            1, 2, 3, 0, 4, 5, 6, 0x80, 9, 9, 9, 9,
+            7, 8, 9, 0xff, 10, 11, 12, 0, 9, 9, 9, 9,
+        ];
+        let mut dst = Vec::new();
+        bgra_to_rgba_opaque(&src, 12, 2, 2, None, &mut dst);
+        assert_eq!(
+            dst,
+            [3, 2, 1, 255, 6, 5, 4, 255, 9, 8, 7, 255, 12, 11, 10, 255]
+        );
+
+        // Colors are mapped, alpha isn't.
+        bgra_to_rgba_opaque(&src, 12, 2, 2, Some(&invert()), &mut dst);
+        assert_eq!(
+            dst,
+            [252, 253, 254, 255, 249, 250, 251, 255, 246, 247, 248, 255, 243, 244, 245, 255]
+        );
+    }
+
+    #[test]
+    fn rgb565_known_values() {
+        // Two rows of two pixels, with a pixel of padding on each row.
+        #[rustfmt::skip]
+        let src = [
+            0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, 0xAA, 0xAA, 0xAA, 0xAA,
+            0x00, 0xFF, 0x00, 0x00, 0x08, 0x82, 0x84, 0x00, 0xAA, 0xAA, 0xAA, 0xAA,
+        ];
+        let mut dst = Vec::new();
+        bgra_to_rgb565(&src, 12, 2, 2, None, &mut dst);
+        // White, red, green, and the top bits of each channel.
+        assert_eq!(dst, [0xFF, 0xFF, 0x00, 0xF8, 0xE0, 0x07, 0x01, 0x84]);
+
+        bgra_to_rgb565(&src[..8], 8, 2, 1, Some(&invert()), &mut dst);
+        // Black, then cyan.
+        assert_eq!(dst, [0x00, 0x00, 0xFF, 0x07]);
+    }
+}
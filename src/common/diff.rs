@@ -0,0 +1,90 @@
+use super::Rect;
+
+/// Finds which blocks of a frame differ from the frame before, by comparing
+/// their bytes.
+///
+/// Keeps a packed copy of the last frame, so memory use is about one frame.
+pub struct FrameDiff {
+    block: usize,
+    previous: Vec<u8>,
+    /// The previous frame's width and height, in pixels.
+    size: (usize, usize),
+    changed: Vec<Rect>,
+}
+
+impl FrameDiff {
+    /// Compares `block_size` by `block_size` pixel blocks. Smaller blocks
+    /// are more precise but slower. Panics if it's zero.
+    pub fn new(block_size: u32) -> FrameDiff {
+        assert!(block_size > 0, "block size must be positive");
+        FrameDiff {
+            block: block_size as usize,
+            previous: Vec::new(),
+            size: (0, 0),
+            changed: Vec::new(),
+        }
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block as u32
+    }
+
+    /// Compares a frame with the last one given and keeps it for next time.
+    /// Every block counts as changed on the first frame, or when the size or
+    /// pixel size changes. Blocks at the right and bottom edges are clipped
+    /// to the frame.
+    pub fn update(&mut self, src: &[u8], stride: usize, width: usize, height: usize, bpp: usize) {
+        let row = width * bpp;
+        let resized = self.size != (width, height) || self.previous.len() != row * height;
+        if resized {
+            self.size = (width, height);
+            self.previous.clear();
+            self.previous.resize(row * height, 0);
+        }
+
+        self.changed.clear();
+        for by in (0..height).step_by(self.block) {
+            let bh = self.block.min(height - by);
+            for bx in (0..width).step_by(self.block) {
+                let bw = self.block.min(width - bx);
+                let (start, len) = (bx * bpp, bw * bpp);
+
+                let mut differs = resized;
+                for y in by..by + bh {
+                    let new = &src[y * stride + start..][..len];
+                    let old = &mut self.previous[y * row + start..][..len];
+                    if differs || new != &old[..] {
+                        differs = true;
+                        old.copy_from_slice(new);
+                    }
+                }
+                if differs {
+                    self.changed
+                        .push(Rect::new(bx as i32, by as i32, bw as u32, bh as u32));
+                }
+            }
+        }
+    }
+
+    /// The blocks that differed in the last `update`, row by row.
+    pub fn changed(&self) -> &[Rect] {
+        &self.changed
+    }
+
+    /// Forgets the last frame, so every block counts as changed next time.
+    pub fn reset(&mut self) {
+        self.size = (0, 0);
+        self.previous.clear();
+    }
+
+    /// Bytes held, mostly the copy of the last frame.
+    pub fn memory_usage(&self) -> usize {
+        self.previous.capacity() + self.changed.capacity() * std::mem::size_of::<Rect>()
+    }
+
+    /// What `memory_usage` would be after an `update` with `len` bytes of
+    /// pixels, not counting the changed blocks it finds.
+    pub fn memory_needed(&self, len: usize) -> usize {
+        self.memory_usage() - self.previous.capacity() + self.previous.capacity().max(len)
+    }
+}
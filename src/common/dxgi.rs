@@ -1,49 +1,102 @@
+use crate::convert::{Adjustment, Lut};
 use crate::dxgi;
 use std::io::ErrorKind::{NotFound, TimedOut, WouldBlock};
+use std::time::Duration;
 use std::{io, ops};
 
 pub struct Capturer {
     inner: dxgi::Capturer,
-    width: usize,
-    height: usize,
+    lut: Option<Lut>,
 }
 
 impl Capturer {
     pub fn new(display: Display, capture_mouse: bool) -> io::Result<Capturer> {
-        let width = display.width();
-        let height = display.height();
         let inner = dxgi::Capturer::new(&display.0, capture_mouse)?;
-        Ok(Capturer {
-            inner,
-            width,
-            height,
-        })
+        Ok(Capturer { inner, lut: None })
     }
 
+    /// Follows display mode changes, so check it after every frame.
     pub fn width(&self) -> usize {
-        self.width
+        self.inner.width()
     }
 
     pub fn height(&self) -> usize {
-        self.height
+        self.inner.height()
+    }
+
+    /// How many bytes each pixel of the frames takes.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.inner.bytes_per_pixel()
+    }
+
+    /// Applies `adjustment` whenever frames are converted, e.g. by
+    /// `Frame::decimate`. The raw frame data is left alone.
+    pub fn set_adjustment(&mut self, adjustment: Adjustment) {
+        self.lut = adjustment.lut();
+        self.inner.set_adjustment(adjustment);
+    }
+
+    /// Caps the capturer's CPU buffers, as `dxgi::Config::max_buffer_memory`
+    /// does. Helpers like `ConstantRate` count their own buffers against it.
+    pub fn set_max_buffer_memory(&mut self, bytes: Option<usize>) {
+        self.inner.set_max_buffer_memory(bytes);
+    }
+
+    /// Fails with `dxgi::BufferLimitExceeded` unless `needed` more bytes fit
+    /// under the cap.
+    pub(crate) fn reserve_buffer(&self, buffer: &'static str, needed: usize) -> io::Result<()> {
+        self.inner.reserve(buffer, 0, needed)
     }
 
     pub fn frame<'a>(&'a mut self) -> io::Result<Frame<'a>> {
-        const MILLISECONDS_PER_FRAME: u32 = 0;
-        match self.inner.frame(MILLISECONDS_PER_FRAME) {
-            Ok(frame) => Ok(Frame(frame)),
+        self.frame_within(Duration::ZERO)
+    }
+
+    /// Like `frame`, but waits up to `timeout` for the screen to change.
+    pub(crate) fn frame_within<'a>(&'a mut self, timeout: Duration) -> io::Result<Frame<'a>> {
+        // `INFINITE` is `u32::MAX`, so stay under it.
+        let timeout = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        match self.inner.frame(timeout) {
+            Ok(frame) => Ok(Frame {
+                data: frame.data(),
+                width: frame.width(),
+                height: frame.height(),
+                stride: frame.stride(),
+                lut: self.lut.as_ref(),
+            }),
             Err(ref error) if error.kind() == TimedOut => Err(WouldBlock.into()),
             Err(error) => Err(error),
         }
     }
 }
 
-pub struct Frame<'a>(&'a [u8]);
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    pub(super) lut: Option<&'a Lut>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
 
 impl<'a> ops::Deref for Frame<'a> {
     type Target = [u8];
     fn deref(&self) -> &[u8] {
-        self.0
+        self.data
     }
 }
 
@@ -12,3 +12,46 @@ cfg_if! {
         //TODO: Fallback implementation.
     }
 }
+
+#[cfg(feature = "vpx")]
+pub mod codec;
+pub mod convert;
+pub mod snapshot;
+
+mod accumulate;
+mod activity;
+mod cancel;
+mod cfr;
+mod diff;
+mod raw_video;
+mod rect;
+mod screenshot;
+mod tiles;
+mod watchdog;
+
+pub use self::accumulate::*;
+pub use self::activity::*;
+pub use self::cancel::*;
+pub use self::cfr::*;
+pub use self::diff::*;
+pub use self::raw_video::*;
+pub use self::rect::*;
+pub use self::screenshot::*;
+pub use self::tiles::*;
+pub use self::watchdog::*;
+
+#[cfg(any(quartz, x11, dxgi))]
+impl<'a> Frame<'a> {
+    /// Every `factor`th pixel of every `factor`th row, as packed BGRA,
+    /// with the capturer's adjustment applied. See `convert::decimate`.
+    pub fn decimate(&self, factor: usize, out: &mut Vec<u8>) {
+        let (stride, width, height) = (self.stride(), self.width(), self.height());
+        convert::decimate(self, stride, width, height, factor, self.lut, out);
+    }
+
+    /// Like `decimate`, but to one luma byte per pixel.
+    pub fn decimate_luma(&self, factor: usize, out: &mut Vec<u8>) {
+        let (stride, width, height) = (self.stride(), self.width(), self.height());
+        convert::decimate_luma(self, stride, width, height, factor, self.lut, out);
+    }
+}
@@ -1,3 +1,4 @@
+use convert::{Adjustment, Lut};
 use quartz;
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex, TryLockError};
@@ -6,6 +7,7 @@ use std::{io, mem, ops};
 pub struct Capturer {
     inner: quartz::Capturer,
     frame: Arc<Mutex<Option<quartz::Frame>>>,
+    lut: Option<Lut>,
 }
 
 impl Capturer {
@@ -27,7 +29,11 @@ impl Capturer {
         )
         .map_err(|_| io::Error::from(io::ErrorKind::Other))?;
 
-        Ok(Capturer { inner, frame })
+        Ok(Capturer {
+            inner,
+            frame,
+            lut: None,
+        })
     }
 
     pub fn width(&self) -> usize {
@@ -38,6 +44,17 @@ impl Capturer {
         self.inner.height()
     }
 
+    /// How many bytes each pixel of the frames takes.
+    pub fn bytes_per_pixel(&self) -> usize {
+        4
+    }
+
+    /// Applies `adjustment` whenever frames are converted, e.g. by
+    /// `Frame::decimate`. The raw frame data is left alone.
+    pub fn set_adjustment(&mut self, adjustment: Adjustment) {
+        self.lut = adjustment.lut();
+    }
+
     pub fn frame<'a>(&'a mut self) -> io::Result<Frame<'a>> {
         match self.frame.try_lock() {
             Ok(mut handle) => {
@@ -45,7 +62,14 @@ impl Capturer {
                 mem::swap(&mut frame, &mut handle);
 
                 match frame {
-                    Some(frame) => Ok(Frame(frame, PhantomData)),
+                    Some(frame) => Ok(Frame {
+                        stride: frame.stride(),
+                        inner: frame,
+                        width: self.width(),
+                        height: self.height(),
+                        lut: self.lut.as_ref(),
+                        _marker: PhantomData,
+                    }),
 
                     None => Err(io::ErrorKind::WouldBlock.into()),
                 }
@@ -58,12 +82,34 @@ impl Capturer {
     }
 }
 
-pub struct Frame<'a>(quartz::Frame, PhantomData<&'a [u8]>);
+pub struct Frame<'a> {
+    inner: quartz::Frame,
+    width: usize,
+    height: usize,
+    stride: usize,
+    pub(super) lut: Option<&'a Lut>,
+    _marker: PhantomData<&'a [u8]>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
 
 impl<'a> ops::Deref for Frame<'a> {
     type Target = [u8];
     fn deref(&self) -> &[u8] {
-        &*self.0
+        &*self.inner
     }
 }
 
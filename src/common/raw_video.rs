@@ -0,0 +1,120 @@
+use super::{Capturer, ConstantRate};
+use std::io::ErrorKind::WouldBlock;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+use std::{cmp, thread};
+
+/// Reads a capturer as a raw video stream: packed BGRA frames back to back,
+/// `width * height * 4` bytes each, at a constant frame rate. Suits
+/// `ffmpeg -f rawvideo -pixel_format bgra`, e.g. through `io::copy`.
+///
+/// Frames are paced like `ConstantRate`, so the last one repeats when
+/// nothing changed. Capture errors are returned from `read`, as is
+/// `InvalidData` if the frame size changes, as the stream can't say so.
+pub struct RawVideoReader {
+    capturer: Capturer,
+    rate: ConstantRate,
+    timeout: Duration,
+    size: Option<(usize, usize)>,
+    frame: Vec<u8>,
+    /// How much of `frame` was read.
+    pos: usize,
+}
+
+impl RawVideoReader {
+    /// Waits up to `timeout` for the first frame before failing with
+    /// `TimedOut`. Panics unless `fps` is positive.
+    pub fn new(capturer: Capturer, fps: f64, timeout: Duration) -> RawVideoReader {
+        RawVideoReader {
+            capturer,
+            rate: ConstantRate::new(fps),
+            timeout,
+            size: None,
+            frame: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// The frames' width and height, once the first one was read.
+    pub fn size(&self) -> Option<(usize, usize)> {
+        self.size
+    }
+
+    pub fn into_inner(self) -> Capturer {
+        self.capturer
+    }
+
+    /// The bytes held for the frame being read and the one `ConstantRate`
+    /// repeats.
+    pub fn memory_usage(&self) -> usize {
+        self.rate.memory_usage() + self.frame.capacity()
+    }
+
+    /// Packs the next tick's frame into `frame`.
+    fn next_frame(&mut self) -> io::Result<()> {
+        // This frame and the one `ConstantRate` keeps.
+        #[cfg(dxgi)]
+        {
+            let len = self.capturer.width() * self.capturer.height() * 4;
+            let needed = self.frame.capacity().max(len) + self.rate.memory_usage().max(len);
+            self.capturer.reserve_buffer("raw video frames", needed)?;
+        }
+        let start = Instant::now();
+        let frame = loop {
+            match self.rate.next_frame(&mut self.capturer) {
+                Ok(frame) => break frame,
+                // Only until the first frame; after that, ticks repeat it.
+                Err(ref err) if err.kind() == WouldBlock => {
+                    if start.elapsed() >= self.timeout {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "no frame arrived in time",
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let (width, height) = (frame.width(), frame.height());
+        match self.size {
+            None => self.size = Some((width, height)),
+            Some(size) if size != (width, height) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the frame size changed from {}x{} to {}x{}",
+                        size.0, size.1, width, height
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+
+        let row = width * 4;
+        self.frame.clear();
+        if height > 0 {
+            for src in frame.chunks(frame.stride()).take(height) {
+                self.frame.extend_from_slice(&src[..row]);
+            }
+        }
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for RawVideoReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos == self.frame.len() {
+            self.next_frame()?;
+        }
+        let n = cmp::min(buf.len(), self.frame.len() - self.pos);
+        buf[..n].copy_from_slice(&self.frame[self.pos..][..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
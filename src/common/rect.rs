@@ -0,0 +1,83 @@
+use std::cmp;
+
+/// Content that moved within a frame: `destination` now shows what was at
+/// `source`, its top-left corner before the move.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct MoveRect {
+    pub source: (i32, i32),
+    pub destination: Rect,
+}
+
+/// A rectangle in frame coordinates.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, w: u32, h: u32) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    /// Builds a rectangle from its edges, treating inverted edges as empty.
+    pub fn from_edges(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            x: left,
+            y: top,
+            w: cmp::max(right - left, 0) as u32,
+            h: cmp::max(bottom - top, 0) as u32,
+        }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.w as i32
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.h as i32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+
+    /// The smallest rectangle containing both. Empty rectangles are ignored.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if other.is_empty() {
+            return *self;
+        }
+        if self.is_empty() {
+            return *other;
+        }
+        Rect::from_edges(
+            cmp::min(self.x, other.x),
+            cmp::min(self.y, other.y),
+            cmp::max(self.right(), other.right()),
+            cmp::max(self.bottom(), other.bottom()),
+        )
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let rect = Rect::from_edges(
+            cmp::max(self.x, other.x),
+            cmp::max(self.y, other.y),
+            cmp::min(self.right(), other.right()),
+            cmp::min(self.bottom(), other.bottom()),
+        );
+        if rect.is_empty() {
+            None
+        } else {
+            Some(rect)
+        }
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+}
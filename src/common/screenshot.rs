@@ -0,0 +1,134 @@
+use super::{Capturer, Display};
+use std::io::ErrorKind::WouldBlock;
+use std::time::{Duration, Instant};
+use std::{io, thread};
+
+/// Which display a screenshot is of.
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayInfo {
+    /// The display's position in `Display::all`.
+    pub index: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// An owned frame, as tightly packed BGRA.
+#[derive(Clone, Debug)]
+pub struct Screenshot {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Takes one screenshot of every display, all at about the same time.
+///
+/// Every display is captured on its own thread, waiting up to `timeout`
+/// for a frame. A display that fails only fails its own entry.
+pub fn capture_all_displays(
+    timeout: Duration,
+) -> io::Result<Vec<(DisplayInfo, io::Result<Screenshot>)>> {
+    let threads: Vec<_> = Display::all()?
+        .into_iter()
+        .enumerate()
+        .map(|(index, display)| {
+            let info = DisplayInfo {
+                index,
+                width: display.width(),
+                height: display.height(),
+            };
+            (info, spawn_capture(index, display, timeout))
+        })
+        .collect();
+
+    Ok(threads
+        .into_iter()
+        .map(|(info, thread)| {
+            let shot = thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("the capture thread panicked")));
+            (info, shot)
+        })
+        .collect())
+}
+
+#[cfg(not(x11))]
+fn spawn_capture(
+    _index: usize,
+    display: Display,
+    timeout: Duration,
+) -> thread::JoinHandle<io::Result<Screenshot>> {
+    thread::spawn(move || screenshot(display, timeout))
+}
+
+/// X11 displays share their connection through an `Rc`, so they can't be
+/// sent. Each thread opens its own and finds the display again, which can
+/// pick the wrong one if displays come and go in between.
+#[cfg(x11)]
+fn spawn_capture(
+    index: usize,
+    display: Display,
+    timeout: Duration,
+) -> thread::JoinHandle<io::Result<Screenshot>> {
+    drop(display);
+    thread::spawn(move || {
+        let display = Display::all()?
+            .into_iter()
+            .nth(index)
+            .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+        screenshot(display, timeout)
+    })
+}
+
+/// How often to look for a frame where there's no way to wait for one.
+#[cfg(not(dxgi))]
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Takes one screenshot, waiting up to `timeout` for a frame.
+///
+/// Nothing is kept around afterwards: the capturer and everything it holds,
+/// like a DXGI duplication and device, is released before this returns.
+pub fn screenshot(display: Display, timeout: Duration) -> io::Result<Screenshot> {
+    let deadline = Instant::now() + timeout;
+
+    #[cfg(dxgi)]
+    let mut capturer = Capturer::new(display, false)?;
+    #[cfg(not(dxgi))]
+    let mut capturer = Capturer::new(display)?;
+
+    let shot = loop {
+        let bytes_per_pixel = capturer.bytes_per_pixel();
+        // DXGI can wait for a frame; the others can only be polled.
+        #[cfg(dxgi)]
+        let result = capturer.frame_within(deadline.saturating_duration_since(Instant::now()));
+        #[cfg(not(dxgi))]
+        let result = capturer.frame();
+
+        match result {
+            Ok(frame) => {
+                let (width, height) = (frame.width(), frame.height());
+                let row = width * bytes_per_pixel;
+                let mut data = Vec::with_capacity(row * height);
+                if height > 0 {
+                    for src in frame.chunks(frame.stride()).take(height) {
+                        data.extend_from_slice(&src[..row]);
+                    }
+                }
+                break Screenshot {
+                    width,
+                    height,
+                    data,
+                };
+            }
+            Err(ref error) if error.kind() == WouldBlock && Instant::now() < deadline => {
+                #[cfg(not(dxgi))]
+                thread::sleep(
+                    POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())),
+                );
+            }
+            Err(error) => return Err(error),
+        }
+    };
+
+    drop(capturer);
+    Ok(shot)
+}
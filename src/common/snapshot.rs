@@ -0,0 +1,171 @@
+//! Writing frames to image files.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What kind of file to save a frame as.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ImageFormat {
+    /// Uncompressed 32-bit BMP. Always available.
+    #[default]
+    Bmp,
+    /// Opaque RGBA PNG, with the `png` feature.
+    #[cfg(feature = "png")]
+    Png,
+}
+
+/// The error payload of a snapshot that captured fine but couldn't be
+/// written. Its kind is the file system's. Check for it with
+/// `is_save_error`.
+#[derive(Debug)]
+pub struct SaveError {
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't save {}: {}", self.path.display(), self.source)
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<SaveError> for io::Error {
+    fn from(err: SaveError) -> io::Error {
+        io::Error::new(err.source.kind(), err)
+    }
+}
+
+/// Whether an error came from writing a snapshot, rather than capturing it.
+pub fn is_save_error(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<SaveError>())
+}
+
+/// Saves a BGRA image to `path`. Failures are `SaveError`s.
+pub fn save(
+    path: &Path,
+    format: ImageFormat,
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    let write = || -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Bmp => write_bmp(&mut out, src, stride, width, height)?,
+            #[cfg(feature = "png")]
+            ImageFormat::Png => write_png(&mut out, src, stride, width, height)?,
+        }
+        out.flush()
+    };
+    write().map_err(|source| {
+        SaveError {
+            path: path.to_owned(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// Writes a BGRA image as a 32-bit BMP. BMP rows go bottom up, so they're
+/// written in reverse.
+pub fn write_bmp<W: Write>(
+    mut out: W,
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    const HEADERS: u32 = 14 + 40;
+    let image = (width * height * 4) as u32;
+
+    // BITMAPFILEHEADER
+    out.write_all(b"BM")?;
+    out.write_all(&(HEADERS + image).to_le_bytes())?;
+    out.write_all(&[0; 4])?;
+    out.write_all(&HEADERS.to_le_bytes())?;
+
+    // BITMAPINFOHEADER, uncompressed.
+    out.write_all(&40u32.to_le_bytes())?;
+    out.write_all(&(width as i32).to_le_bytes())?;
+    out.write_all(&(height as i32).to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&32u16.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&image.to_le_bytes())?;
+    // 96 DPI, and no palette.
+    out.write_all(&3780i32.to_le_bytes())?;
+    out.write_all(&3780i32.to_le_bytes())?;
+    out.write_all(&[0; 8])?;
+
+    for y in (0..height).rev() {
+        out.write_all(&src[y * stride..][..width * 4])?;
+    }
+    Ok(())
+}
+
+/// Encodes a BGRA image as a JPEG, ignoring its alpha. `quality` is
+/// clamped to 1 to 100. Fails with `InvalidInput` if a side is over 65535
+/// pixels, which JPEG can't hold.
+#[cfg(feature = "jpeg")]
+pub fn encode_jpeg(
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    quality: u8,
+) -> io::Result<Vec<u8>> {
+    use jpeg_encoder::{ColorType, Encoder};
+
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}x{} is too big for a JPEG", width, height),
+        ));
+    }
+
+    // The encoder wants rows without padding.
+    let row = width * 4;
+    let packed;
+    let pixels = if stride == row {
+        &src[..row * height]
+    } else {
+        packed = src
+            .chunks(stride)
+            .take(height)
+            .flat_map(|r| &r[..row])
+            .copied()
+            .collect::<Vec<u8>>();
+        &packed[..]
+    };
+
+    let mut out = Vec::new();
+    Encoder::new(&mut out, quality.clamp(1, 100))
+        .encode(pixels, width as u16, height as u16, ColorType::Bgra)
+        .map_err(io::Error::other)?;
+    Ok(out)
+}
+
+/// Writes a BGRA image as an RGBA PNG, ignoring its alpha, which capture
+/// rarely fills in.
+#[cfg(feature = "png")]
+pub fn write_png<W: Write>(
+    out: W,
+    src: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    let mut rgba = Vec::new();
+    super::convert::bgra_to_rgba_opaque(src, stride, width, height, None, &mut rgba);
+    ::repng::encode(out, width as u32, height as u32, &rgba)
+}
@@ -0,0 +1,145 @@
+use super::convert::hash_pixels;
+use super::Rect;
+
+/// A tile of a frame, from `ChangeTracker::changed_tiles`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TileRect {
+    /// The tile's column and row in the grid.
+    pub column: usize,
+    pub row: usize,
+    /// Its pixels, clipped to the frame at the right and bottom edges.
+    pub rect: Rect,
+}
+
+/// Finds which tiles of a frame differ from the frame before, by hashing
+/// them.
+///
+/// Unlike `FrameDiff`, it keeps only a hash per tile rather than the last
+/// frame, and can be told which parts of the frame may have changed so it
+/// only hashes tiles touching them.
+pub struct ChangeTracker {
+    tile: usize,
+    /// The previous frame's width, height and bytes per pixel.
+    size: (usize, usize, usize),
+    hashes: Vec<u64>,
+    /// Tiles to hash this update.
+    pending: Vec<bool>,
+    changed: Vec<usize>,
+}
+
+impl ChangeTracker {
+    /// Splits frames into `tile_size` by `tile_size` pixel tiles. Panics if
+    /// it's zero.
+    pub fn new(tile_size: u32) -> ChangeTracker {
+        assert!(tile_size > 0, "tile size must be positive");
+        ChangeTracker {
+            tile: tile_size as usize,
+            size: (0, 0, 0),
+            hashes: Vec::new(),
+            pending: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        self.tile as u32
+    }
+
+    /// How many tiles there are across and down.
+    pub fn grid_size(&self) -> (usize, usize) {
+        let (width, height, _) = self.size;
+        (width.div_ceil(self.tile), height.div_ceil(self.tile))
+    }
+
+    /// Hashes a frame's tiles and compares them with the last frame's.
+    ///
+    /// With `hints`, only tiles touching those rects are hashed, so they must
+    /// cover everything that changed since the last update. Without them,
+    /// or on the first frame, or when the size or pixel size changes, every
+    /// tile is hashed. Tiles always count as changed after a resize.
+    pub fn update(
+        &mut self,
+        src: &[u8],
+        stride: usize,
+        width: usize,
+        height: usize,
+        bpp: usize,
+        hints: Option<&[Rect]>,
+    ) {
+        let resized = self.size != (width, height, bpp) || self.hashes.is_empty();
+        if resized {
+            self.size = (width, height, bpp);
+        }
+        let (columns, rows) = self.grid_size();
+        if resized {
+            self.hashes.clear();
+            self.hashes.resize(columns * rows, 0);
+        }
+
+        self.pending.clear();
+        match hints {
+            Some(hints) if !resized => {
+                self.pending.resize(columns * rows, false);
+                let bounds = Rect::new(0, 0, width as u32, height as u32);
+                for hint in hints.iter().filter_map(|r| r.intersection(&bounds)) {
+                    let (left, top) = (hint.x as usize / self.tile, hint.y as usize / self.tile);
+                    let right = (hint.right() as usize).div_ceil(self.tile);
+                    let bottom = (hint.bottom() as usize).div_ceil(self.tile);
+                    for row in top..bottom {
+                        self.pending[row * columns + left..row * columns + right].fill(true);
+                    }
+                }
+            }
+            _ => self.pending.resize(columns * rows, true),
+        }
+
+        self.changed.clear();
+        for index in 0..self.pending.len() {
+            if !self.pending[index] {
+                continue;
+            }
+            let rect = self.tile_rect(index).rect;
+            let start = rect.y as usize * stride + rect.x as usize * bpp;
+            let hash = hash_pixels(&src[start..], stride, rect.w as usize, rect.h as usize, bpp);
+            if resized || hash != self.hashes[index] {
+                self.hashes[index] = hash;
+                self.changed.push(index);
+            }
+        }
+    }
+
+    /// The tiles that differed in the last `update`, row by row.
+    pub fn changed_tiles(&self) -> impl Iterator<Item = TileRect> + '_ {
+        self.changed.iter().map(move |&index| self.tile_rect(index))
+    }
+
+    /// Forgets the last frame, so every tile counts as changed next time.
+    pub fn reset(&mut self) {
+        self.size = (0, 0, 0);
+        self.hashes.clear();
+    }
+
+    /// Bytes held, mostly the tile hashes.
+    pub fn memory_usage(&self) -> usize {
+        self.hashes.capacity() * std::mem::size_of::<u64>()
+            + self.pending.capacity()
+            + self.changed.capacity() * std::mem::size_of::<usize>()
+    }
+
+    fn tile_rect(&self, index: usize) -> TileRect {
+        let (width, height, _) = self.size;
+        let columns = self.grid_size().0;
+        let (column, row) = (index % columns, index / columns);
+        let (x, y) = (column * self.tile, row * self.tile);
+        TileRect {
+            column,
+            row,
+            rect: Rect::new(
+                x as i32,
+                y as i32,
+                self.tile.min(width - x) as u32,
+                self.tile.min(height - y) as u32,
+            ),
+        }
+    }
+}
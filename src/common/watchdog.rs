@@ -0,0 +1,209 @@
+use super::cancel::is_cancelled;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub enum WatchdogEvent {
+    /// Nothing healthy has happened since `since`.
+    Stalled {
+        since: Instant,
+        last_error: Option<String>,
+    },
+    /// Frames are coming again, after none healthy for `stalled_for`.
+    Recovered { stalled_for: Duration },
+}
+
+/// Notices when capturing stops working, as opposed to the screen just not
+/// changing.
+///
+/// Capture calls are reported with `begin` and `record`. A frame, a plain
+/// timeout or a cancelled call counts as healthy; any other error doesn't,
+/// including other `Interrupted` ones, which DXGI gives when it can't
+/// capture at all for now. The watchdog stalls
+/// when nothing healthy happened for `stall_after`, or when a call has been
+/// running that long, so calls should use shorter timeouts than that. Call
+/// `check` from another thread to notice calls that never return.
+///
+/// Clones share the same state.
+#[derive(Clone)]
+pub struct Watchdog(Arc<Inner>);
+
+struct Inner {
+    stall_after: Duration,
+    on_event: Box<dyn Fn(WatchdogEvent) + Send + Sync>,
+    state: Mutex<State>,
+}
+
+struct State {
+    healthy_at: Instant,
+    in_flight: Option<Instant>,
+    last_error: Option<String>,
+    stalled_since: Option<Instant>,
+}
+
+impl Watchdog {
+    /// `on_event` is called on whichever thread noticed the change.
+    pub fn new<F>(stall_after: Duration, on_event: F) -> Watchdog
+    where
+        F: Fn(WatchdogEvent) + Send + Sync + 'static,
+    {
+        Watchdog(Arc::new(Inner {
+            stall_after,
+            on_event: Box::new(on_event),
+            state: Mutex::new(State {
+                healthy_at: Instant::now(),
+                in_flight: None,
+                last_error: None,
+                stalled_since: None,
+            }),
+        }))
+    }
+
+    /// A capture call is starting.
+    pub fn begin(&self) {
+        self.state().in_flight = Some(Instant::now());
+    }
+
+    /// A capture call returned.
+    pub fn record<T>(&self, result: &io::Result<T>) {
+        let now = Instant::now();
+        let event = {
+            let mut state = self.state();
+            state.in_flight = None;
+            match *result {
+                Ok(_) => {
+                    state.healthy_at = now;
+                    state.last_error = None;
+                    state
+                        .stalled_since
+                        .take()
+                        .map(|since| WatchdogEvent::Recovered {
+                            stalled_for: now.duration_since(since),
+                        })
+                }
+                Err(ref err) => {
+                    if is_cancelled(err) || matches!(err.kind(), WouldBlock | TimedOut) {
+                        state.healthy_at = now;
+                    } else {
+                        state.last_error = Some(err.to_string());
+                    }
+                    self.stall(&mut state, now)
+                }
+            }
+        };
+        self.emit(event);
+    }
+
+    /// Checks for a stall without a call returning.
+    pub fn check(&self) {
+        let event = self.stall(&mut self.state(), Instant::now());
+        self.emit(event);
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.state().stalled_since.is_some()
+    }
+
+    fn stall(&self, state: &mut State, now: Instant) -> Option<WatchdogEvent> {
+        if state.stalled_since.is_some() {
+            return None;
+        }
+
+        let hung = state
+            .in_flight
+            .is_some_and(|start| now.duration_since(start) > self.0.stall_after);
+        if !hung && now.duration_since(state.healthy_at) <= self.0.stall_after {
+            return None;
+        }
+
+        state.stalled_since = Some(state.healthy_at);
+        let last_error = if hung {
+            Some("a capture call hasn't returned".into())
+        } else {
+            state.last_error.clone()
+        };
+        Some(WatchdogEvent::Stalled {
+            since: state.healthy_at,
+            last_error,
+        })
+    }
+
+    fn emit(&self, event: Option<WatchdogEvent>) {
+        // Outside the lock, so the callback may use the watchdog.
+        if let Some(event) = event {
+            (self.0.on_event)(event);
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("stall_after", &self.0.stall_after)
+            .field("stalled", &self.is_stalled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cancelled;
+    use std::io::ErrorKind::Interrupted;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A watchdog that stalls at the first unhealthy record, and a count of
+    /// its stalls.
+    fn watchdog() -> (Watchdog, Arc<AtomicUsize>) {
+        let stalls = Arc::new(AtomicUsize::new(0));
+        let counted = stalls.clone();
+        let watchdog = Watchdog::new(Duration::ZERO, move |event| {
+            if let WatchdogEvent::Stalled { .. } = event {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        (watchdog, stalls)
+    }
+
+    fn record(err: io::Error) -> bool {
+        let (watchdog, _) = watchdog();
+        // Let the zero stall time pass.
+        std::thread::sleep(Duration::from_millis(1));
+        watchdog.record::<()>(&Err(err));
+        watchdog.is_stalled()
+    }
+
+    #[test]
+    fn timeouts_and_cancellation_are_healthy() {
+        assert!(!record(WouldBlock.into()));
+        assert!(!record(TimedOut.into()));
+        assert!(!record(Cancelled.into()));
+    }
+
+    #[test]
+    fn other_interruptions_are_failures() {
+        assert!(record(io::Error::new(
+            Interrupted,
+            "not currently available"
+        )));
+        assert!(record(io::ErrorKind::Other.into()));
+    }
+
+    #[test]
+    fn recovers_on_a_frame() {
+        let (watchdog, stalls) = watchdog();
+        std::thread::sleep(Duration::from_millis(1));
+        watchdog.record::<()>(&Err(Interrupted.into()));
+        watchdog.record::<()>(&Err(Interrupted.into()));
+        assert_eq!(stalls.load(Ordering::SeqCst), 1);
+        watchdog.record(&Ok(()));
+        assert!(!watchdog.is_stalled());
+    }
+}
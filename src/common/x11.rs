@@ -1,33 +1,78 @@
+use convert::{Adjustment, Lut};
 use std::rc::Rc;
 use std::{io, ops};
 use x11;
 
-pub struct Capturer(x11::Capturer);
+pub struct Capturer {
+    inner: x11::Capturer,
+    lut: Option<Lut>,
+}
 
 impl Capturer {
     pub fn new(display: Display) -> io::Result<Capturer> {
-        x11::Capturer::new(display.0).map(Capturer)
+        let inner = x11::Capturer::new(display.0)?;
+        Ok(Capturer { inner, lut: None })
     }
 
     pub fn width(&self) -> usize {
-        self.0.display().rect().w as usize
+        self.inner.display().rect().w as usize
     }
 
     pub fn height(&self) -> usize {
-        self.0.display().rect().h as usize
+        self.inner.display().rect().h as usize
+    }
+
+    /// How many bytes each pixel of the frames takes.
+    pub fn bytes_per_pixel(&self) -> usize {
+        4
+    }
+
+    /// Applies `adjustment` whenever frames are converted, e.g. by
+    /// `Frame::decimate`. The raw frame data is left alone.
+    pub fn set_adjustment(&mut self, adjustment: Adjustment) {
+        self.lut = adjustment.lut();
     }
 
     pub fn frame<'a>(&'a mut self) -> io::Result<Frame<'a>> {
-        Ok(Frame(self.0.frame()))
+        let (width, height) = (self.width(), self.height());
+        Ok(Frame {
+            data: self.inner.frame(),
+            width,
+            height,
+            // The image is fetched packed, four bytes a pixel.
+            stride: width * 4,
+            lut: self.lut.as_ref(),
+        })
     }
 }
 
-pub struct Frame<'a>(&'a [u8]);
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    pub(super) lut: Option<&'a Lut>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
 
 impl<'a> ops::Deref for Frame<'a> {
     type Target = [u8];
     fn deref(&self) -> &[u8] {
-        self.0
+        self.data
     }
 }
 
@@ -0,0 +1,102 @@
+use std::error::Error;
+use std::{fmt, io, mem, ptr, slice};
+use winapi::shared::minwindef::HGLOBAL;
+use winapi::um::winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GHND};
+use winapi::um::wingdi::{BITMAPINFOHEADER, BI_RGB};
+use winapi::um::winuser::{
+    CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData, CF_DIB,
+};
+
+/// The error payload of a clipboard copy that failed because another
+/// program had the clipboard open. Trying again shortly usually works.
+/// Check for it with `is_clipboard_busy`.
+#[derive(Copy, Clone, Debug)]
+pub struct ClipboardBusy;
+
+impl fmt::Display for ClipboardBusy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("another program has the clipboard open")
+    }
+}
+
+impl Error for ClipboardBusy {}
+
+impl From<ClipboardBusy> for io::Error {
+    fn from(busy: ClipboardBusy) -> io::Error {
+        io::Error::new(io::ErrorKind::WouldBlock, busy)
+    }
+}
+
+/// Whether an error came from finding the clipboard in use.
+pub fn is_clipboard_busy(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<ClipboardBusy>())
+}
+
+/// Puts a BGRA image on the clipboard as a 24-bit `CF_DIB`, replacing what
+/// was there.
+pub(crate) fn copy_dib(src: &[u8], stride: usize, width: usize, height: usize) -> io::Result<()> {
+    unsafe {
+        if OpenClipboard(ptr::null_mut()) == 0 {
+            return Err(ClipboardBusy.into());
+        }
+        let result = set_dib(src, stride, width, height);
+        CloseClipboard();
+        result
+    }
+}
+
+unsafe fn set_dib(src: &[u8], stride: usize, width: usize, height: usize) -> io::Result<()> {
+    // DIB rows are padded to 4 bytes and go bottom up.
+    let row = (width * 3).next_multiple_of(4);
+    let header = mem::size_of::<BITMAPINFOHEADER>();
+    let size = header + row * height;
+
+    // Zeroed, which takes care of the padding.
+    let memory = GlobalAlloc(GHND, size);
+    if memory.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let dib = GlobalLock(memory) as *mut u8;
+    if dib.is_null() {
+        return Err(free(memory));
+    }
+    let dib = slice::from_raw_parts_mut(dib, size);
+
+    let info = BITMAPINFOHEADER {
+        biSize: header as u32,
+        biWidth: width as i32,
+        biHeight: height as i32,
+        biPlanes: 1,
+        biBitCount: 24,
+        biCompression: BI_RGB,
+        biSizeImage: (row * height) as u32,
+        biXPelsPerMeter: 0,
+        biYPelsPerMeter: 0,
+        biClrUsed: 0,
+        biClrImportant: 0,
+    };
+    ptr::copy_nonoverlapping(&info as *const _ as *const u8, dib.as_mut_ptr(), header);
+
+    for y in 0..height {
+        let out = &mut dib[header + y * row..][..row];
+        let pixels = &src[(height - 1 - y) * stride..][..width * 4];
+        for (out, px) in out.chunks_exact_mut(3).zip(pixels.chunks_exact(4)) {
+            out.copy_from_slice(&px[..3]);
+        }
+    }
+    GlobalUnlock(memory);
+
+    if EmptyClipboard() == 0 || SetClipboardData(CF_DIB, memory).is_null() {
+        return Err(free(memory));
+    }
+    // The clipboard owns it now.
+    Ok(())
+}
+
+/// Frees memory the clipboard didn't take, returning the error that
+/// stopped it.
+unsafe fn free(memory: HGLOBAL) -> io::Error {
+    let err = io::Error::last_os_error();
+    GlobalFree(memory);
+    err
+}
@@ -0,0 +1,225 @@
+use crate::CancellationToken;
+use std::ops;
+use std::time::Duration;
+use winapi::shared::minwindef::UINT;
+use winapi::um::d3d11::{
+    D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_CREATE_DEVICE_DEBUG,
+    D3D11_CREATE_DEVICE_SINGLETHREADED, D3D11_CREATE_DEVICE_VIDEO_SUPPORT,
+};
+
+/// A set of flags for creating the D3D11 device.
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceFlags(UINT);
+
+impl DeviceFlags {
+    /// Needed for Direct2D interop on the same device.
+    pub const BGRA_SUPPORT: DeviceFlags = DeviceFlags(D3D11_CREATE_DEVICE_BGRA_SUPPORT);
+    /// Enables the debug layer. Dropped if the layer isn't installed.
+    pub const DEBUG: DeviceFlags = DeviceFlags(D3D11_CREATE_DEVICE_DEBUG);
+    /// Skips the device's internal locking. Never combine with multithread protection.
+    pub const SINGLETHREADED: DeviceFlags = DeviceFlags(D3D11_CREATE_DEVICE_SINGLETHREADED);
+    /// Needed for the video processor.
+    pub const VIDEO_SUPPORT: DeviceFlags = DeviceFlags(D3D11_CREATE_DEVICE_VIDEO_SUPPORT);
+
+    /// Every flag above.
+    pub fn all() -> DeviceFlags {
+        DeviceFlags::BGRA_SUPPORT
+            | DeviceFlags::DEBUG
+            | DeviceFlags::SINGLETHREADED
+            | DeviceFlags::VIDEO_SUPPORT
+    }
+
+    pub fn empty() -> DeviceFlags {
+        DeviceFlags(0)
+    }
+
+    pub fn from_bits(bits: UINT) -> DeviceFlags {
+        DeviceFlags(bits)
+    }
+
+    pub fn bits(self) -> UINT {
+        self.0
+    }
+
+    pub fn contains(self, other: DeviceFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn without(self, other: DeviceFlags) -> DeviceFlags {
+        DeviceFlags(self.0 & !other.0)
+    }
+}
+
+impl ops::BitOr for DeviceFlags {
+    type Output = DeviceFlags;
+    fn bitor(self, other: DeviceFlags) -> DeviceFlags {
+        DeviceFlags(self.0 | other.0)
+    }
+}
+
+impl ops::BitOrAssign for DeviceFlags {
+    fn bitor_assign(&mut self, other: DeviceFlags) {
+        self.0 |= other.0;
+    }
+}
+
+/// What to do when DXGI reports a pointer shape type we don't know.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnknownShapePolicy {
+    /// Skip drawing the cursor.
+    #[default]
+    Ignore,
+    /// Skip drawing the cursor, but log a warning.
+    Warn,
+    /// Fail the frame with `InvalidData`.
+    Error,
+}
+
+/// What to do with the cursor. See `Capturer::set_cursor_mode`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CursorMode {
+    /// Leave it out.
+    #[default]
+    None,
+    /// Draw it on frames.
+    Composite,
+    /// Leave frames untouched and offer it from `Capturer::cursor_overlay`.
+    Overlay,
+}
+
+/// How color pointer shapes' alpha is applied when drawing them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CursorBlend {
+    /// The colors aren't multiplied by alpha, as DXGI documents.
+    #[default]
+    Straight,
+    /// The colors are already multiplied by alpha, as some programs set
+    /// them. Blending those as straight leaves a dark halo.
+    Premultiplied,
+    /// Premultiplied unless some pixel's color is more than its alpha,
+    /// which premultiplied colors can't be. Checked for each shape.
+    Auto,
+}
+
+/// How frames from `Capturer::frame_shared_handle` are handed over. See
+/// `SharedFrameHandle`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SharedSync {
+    /// A keyed mutex on the texture, for D3D11 consumers.
+    #[default]
+    KeyedMutex,
+    /// A shared fence next to the texture, for consumers that can't use a
+    /// keyed mutex, like D3D12. Needs Windows 10 1703 or later.
+    Fence,
+}
+
+/// A translucent circle drawn around the cursor after a click, for
+/// recordings. Only drawn along with the cursor, in `CursorMode::Composite`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ClickHighlight {
+    /// In desktop pixels, so it scales with the frame.
+    pub radius: u32,
+    /// BGRA for left and middle clicks, with alpha as the opacity.
+    pub color: [u8; 4],
+    /// BGRA for right clicks.
+    pub right_color: [u8; 4],
+    /// How long after a press frames show it.
+    pub duration: Duration,
+    /// Checks the buttons with `GetAsyncKeyState` as each frame arrives. Off,
+    /// only `Capturer::notify_click` starts it.
+    pub poll_buttons: bool,
+}
+
+impl Default for ClickHighlight {
+    fn default() -> ClickHighlight {
+        ClickHighlight {
+            radius: 24,
+            color: [0x00, 0xFF, 0xFF, 0x80],
+            right_color: [0x00, 0x00, 0xFF, 0x80],
+            duration: Duration::from_millis(300),
+            poll_buttons: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Config {
+    /// Flags for `D3D11CreateDevice`.
+    pub device_flags: DeviceFlags,
+    /// Whether to enable multithread protection on the device context.
+    pub multithread_protected: bool,
+    /// What to do with pointer shapes of an unknown type.
+    pub unknown_shape: UnknownShapePolicy,
+    /// How color pointer shapes are blended onto frames.
+    pub cursor_blend: CursorBlend,
+    /// Until DXGI first reports the pointer, which it only does once the
+    /// mouse moves, asks `GetCursorInfo` where it is and whether it shows.
+    /// A pointer on another output counts as hidden. The shape still has to
+    /// come from DXGI, `restore_cursor` or `set_custom_cursor`. On scaled
+    /// displays the positions only line up in DPI aware processes.
+    pub cursor_fallback: bool,
+    /// Highlights clicks around the drawn cursor.
+    pub click_highlight: Option<ClickHighlight>,
+    /// Asks for FP16 scRGB frames on HDR displays instead of 8-bit BGRA, on
+    /// Windows 10 1703 and later. Check `Capturer::pixel_format`.
+    pub hdr: bool,
+    /// Sets every pixel's alpha to opaque in copies made by `frame_into`,
+    /// `frame_packed` and `frame_owned`, as DXGI often leaves it 0.
+    pub opaque_alpha: bool,
+    /// Turns copies made by `frame_into`, `frame_packed` and `frame_owned`
+    /// upside down. Together with `flip_horizontal`, that's a half turn.
+    pub flip_vertical: bool,
+    /// Mirrors copies made by `frame_into`, `frame_packed` and
+    /// `frame_owned` left to right.
+    pub flip_horizontal: bool,
+    /// Converts scRGB frames to 8-bit sRGB BGRA as they're read back, tone
+    /// mapped for the display's SDR white level like `frame_sdr`, so they
+    /// can be used like any other. Only applies when
+    /// `Capturer::color_space` is `ScRgb`, i.e. with `hdr` on an HDR
+    /// display.
+    pub to_srgb: bool,
+    /// How `Capturer::frame_shared_handle` synchronizes with consumers.
+    pub shared_sync: SharedSync,
+    /// Caps the CPU memory the capturer's own buffers may use, in bytes:
+    /// accumulated frames, the packed, rotated, top-down and sRGB copies,
+    /// `frame_owned` buffers, the copy kept for `track_changes`, and the
+    /// outputs of the conversions. A call that would grow them past the cap
+    /// fails with `BufferLimitExceeded` (`OutOfMemory`) before allocating,
+    /// after dropping spare `frame_owned` buffers to make room. GPU textures
+    /// aren't capped.
+    pub max_buffer_memory: Option<usize>,
+    /// Interrupts waits. Share one token to stop several capturers at once.
+    /// Never saved; a restored config gets a fresh token.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: CancellationToken,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            device_flags: DeviceFlags::empty(),
+            multithread_protected: false,
+            unknown_shape: UnknownShapePolicy::default(),
+            cursor_blend: CursorBlend::default(),
+            cursor_fallback: false,
+            click_highlight: None,
+            hdr: false,
+            opaque_alpha: false,
+            flip_vertical: false,
+            flip_horizontal: false,
+            to_srgb: false,
+            shared_sync: SharedSync::default(),
+            max_buffer_memory: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+}
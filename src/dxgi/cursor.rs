@@ -0,0 +1,743 @@
+use super::CursorInfo;
+use crate::Rect;
+use std::{fmt, mem};
+use winapi::shared::dxgi1_2::{
+    DXGI_OUTDUPL_POINTER_SHAPE_TYPE_COLOR, DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MASKED_COLOR,
+    DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MONOCHROME,
+};
+use winapi::um::winuser::{
+    GetAsyncKeyState, GetCursorInfo, CURSORINFO, CURSOR_SHOWING, VK_LBUTTON, VK_MBUTTON, VK_RBUTTON,
+};
+
+/// How a pointer shape is encoded.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum CursorShapeKind {
+    /// 32-bit BGRA with alpha.
+    Color,
+    /// A 1-bit AND mask stacked on top of a 1-bit XOR mask,
+    /// so the reported height is twice the visible height.
+    Monochrome,
+    /// 32-bit BGR where the alpha byte says whether to replace or XOR.
+    MaskedColor,
+    /// A type this version doesn't know about.
+    Unknown(u32),
+}
+
+impl From<u32> for CursorShapeKind {
+    fn from(raw: u32) -> CursorShapeKind {
+        match raw {
+            DXGI_OUTDUPL_POINTER_SHAPE_TYPE_COLOR => CursorShapeKind::Color,
+            DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MONOCHROME => CursorShapeKind::Monochrome,
+            DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MASKED_COLOR => CursorShapeKind::MaskedColor,
+            raw => CursorShapeKind::Unknown(raw),
+        }
+    }
+}
+
+/// The pointer as last reported, from `Capturer::cursor`.
+#[derive(Copy, Clone, Debug)]
+pub struct CursorState<'a> {
+    /// Where the hot spot is, relative to the output's top-left corner.
+    pub position: (i32, i32),
+    pub visible: bool,
+    /// `None` until a shape that fits its buffer was received.
+    pub shape: Option<CursorShape<'a>>,
+}
+
+/// A pointer shape as DXGI gives it.
+#[derive(Copy, Clone, Debug)]
+pub struct CursorShape<'a> {
+    pub kind: CursorShapeKind,
+    /// Where the hot spot is in the shape.
+    pub hot_spot: (i32, i32),
+    pub width: u32,
+    /// For `Monochrome` shapes, twice the visible height.
+    pub height: u32,
+    /// The distance between rows of `data`, in bytes.
+    pub pitch: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> CursorShape<'a> {
+    /// The width and height on screen, which for `Monochrome` shapes is
+    /// half the height of the two masks.
+    pub fn visible_size(&self) -> (u32, u32) {
+        match self.kind {
+            CursorShapeKind::Monochrome => (self.width, self.height / 2),
+            _ => (self.width, self.height),
+        }
+    }
+
+    /// Decodes it into a straight alpha BGRA image, or `None` for an
+    /// `Unknown` kind or data too short for the reported size.
+    ///
+    /// Pixels that invert the screen, and masked color pixels that XOR it,
+    /// can't be drawn with alpha alone: they come out opaque gray and are
+    /// flagged in `CursorImage::invert`.
+    pub fn to_bgra(&self) -> Option<CursorImage> {
+        if let CursorShapeKind::Unknown(_) = self.kind {
+            return None;
+        }
+        if !shape_fits(
+            self.kind,
+            self.width,
+            self.height,
+            self.pitch,
+            self.data.len(),
+        ) {
+            return None;
+        }
+
+        let (width, height) = self.visible_size();
+        let (w, h, pitch) = (width as usize, height as usize, self.pitch as usize);
+        // A shape with no width fits however many rows it claims, but has
+        // nothing in them.
+        let h = if w == 0 { 0 } else { h };
+        let mut data = Vec::with_capacity(w * h * 4);
+        let mut invert = vec![false; w * h];
+        for y in 0..h {
+            for x in 0..w {
+                let pixel = match self.kind {
+                    CursorShapeKind::Color => {
+                        let i = y * pitch + x * 4;
+                        [
+                            self.data[i],
+                            self.data[i + 1],
+                            self.data[i + 2],
+                            self.data[i + 3],
+                        ]
+                    }
+                    CursorShapeKind::Monochrome => {
+                        let i = y * pitch + x / 8;
+                        let bit = 7 - x % 8;
+                        let and = (self.data[i] >> bit) & 1;
+                        let xor = (self.data[i + h * pitch] >> bit) & 1;
+                        match (and, xor) {
+                            (0, 0) => [0, 0, 0, 0xFF],
+                            (0, _) => [0xFF, 0xFF, 0xFF, 0xFF],
+                            (_, 0) => [0, 0, 0, 0],
+                            _ => {
+                                invert[y * w + x] = true;
+                                INVERT_GRAY
+                            }
+                        }
+                    }
+                    _ => {
+                        let i = y * pitch + x * 4;
+                        let color = [self.data[i], self.data[i + 1], self.data[i + 2]];
+                        if self.data[i + 3] == 0 {
+                            [color[0], color[1], color[2], 0xFF]
+                        } else if color == [0, 0, 0] {
+                            // XORing with black leaves the screen alone.
+                            [0, 0, 0, 0]
+                        } else {
+                            invert[y * w + x] = true;
+                            INVERT_GRAY
+                        }
+                    }
+                };
+                data.extend_from_slice(&pixel);
+            }
+        }
+
+        Some(CursorImage {
+            width,
+            height,
+            hot_spot: self.hot_spot,
+            data,
+            invert,
+        })
+    }
+}
+
+/// What pixels that invert the screen look like in `CursorImage::data`.
+const INVERT_GRAY: [u8; 4] = [0x80, 0x80, 0x80, 0xFF];
+
+/// A pointer shape decoded to BGRA, from `CursorShape::to_bgra`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    /// Where the hot spot is in the image.
+    pub hot_spot: (i32, i32),
+    /// Straight alpha BGRA, `width * 4` bytes a row.
+    pub data: Vec<u8>,
+    /// Per pixel, row by row, whether it inverts what's under it rather
+    /// than covering it. Such pixels are opaque gray in `data`.
+    pub invert: Vec<bool>,
+}
+
+impl CursorImage {
+    /// The image as a color shape to draw, or `None` if its data is too
+    /// short for its size.
+    pub(crate) fn as_shape(&self) -> Option<CursorShape<'_>> {
+        let shape = CursorShape {
+            kind: CursorShapeKind::Color,
+            hot_spot: self.hot_spot,
+            width: self.width,
+            height: self.height,
+            // Too wide to fit then, rather than an overflow.
+            pitch: self.width.saturating_mul(4),
+            data: &self.data,
+        };
+        let fits = shape_fits(
+            shape.kind,
+            shape.width,
+            shape.height,
+            shape.pitch,
+            shape.data.len(),
+        );
+        if fits {
+            Some(shape)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a color shape's pixels look premultiplied: none has a color
+/// channel greater than its alpha.
+pub(crate) fn looks_premultiplied(shape: &CursorShape) -> bool {
+    let (width, pitch) = (shape.width as usize * 4, shape.pitch as usize);
+    if pitch == 0 || width == 0 {
+        return false;
+    }
+    shape
+        .data
+        .chunks(pitch)
+        .take(shape.height as usize)
+        .all(|row| {
+            row[..width.min(row.len())]
+                .chunks_exact(4)
+                .all(|px| px[0] <= px[3] && px[1] <= px[3] && px[2] <= px[3])
+        })
+}
+
+/// Blends a channel of a straight alpha pixel over `dst`.
+pub(crate) fn blend_straight(src: u8, alpha: u8, dst: u8) -> u8 {
+    let (src, alpha, dst) = (src as u16, alpha as u16, dst as u16);
+    ((alpha * src + (255 - alpha) * dst) / 255) as u8
+}
+
+/// Blends a channel of a premultiplied pixel over `dst`.
+pub(crate) fn blend_premultiplied(src: u8, alpha: u8, dst: u8) -> u8 {
+    let (src, alpha, dst) = (src as u16, alpha as u16, dst as u16);
+    (src + (255 - alpha) * dst / 255).min(255) as u8
+}
+
+/// Blends a color pixel onto the frame a channel at a time with `blend`.
+pub(crate) fn draw_color_cursor(
+    frame: &mut [u8],
+    frame_index: usize,
+    shape: &[u8],
+    cursor_index: usize,
+    blend: fn(u8, u8, u8) -> u8,
+) {
+    if cursor_index + 3 < shape.len() {
+        let alpha = shape[cursor_index + 3];
+        if alpha > 0 {
+            for i in 0..3 {
+                if frame_index + i < frame.len() {
+                    frame[frame_index + i] =
+                        blend(shape[cursor_index + i], alpha, frame[frame_index + i]);
+                }
+            }
+            if frame_index + 3 < frame.len() {
+                frame[frame_index + 3] = 255; // Full opacity
+            }
+        }
+    }
+}
+
+/// Applies pixel `(x, y)` of a monochrome shape: a 1 bpp AND mask with the
+/// XOR mask below it, `pitch` bytes a row, leftmost pixel in the high bit.
+pub(crate) fn draw_monochrome_cursor(
+    frame: &mut [u8],
+    frame_index: usize,
+    shape: &CursorShape,
+    x: usize,
+    y: usize,
+) {
+    let pitch = shape.pitch as usize;
+    let and_index = y * pitch + x / 8;
+    let xor_index = and_index + (shape.height as usize / 2) * pitch;
+    let bit_index = 7 - x % 8;
+    if xor_index < shape.data.len() {
+        let and_mask = (shape.data[and_index] >> bit_index) & 1;
+        let xor_mask = (shape.data[xor_index] >> bit_index) & 1;
+
+        // The screen is ANDed with the first mask then XORed with the
+        // second: 0/0 is black, 0/1 white, 1/0 clear and 1/1 inverts.
+        for i in 0..3 {
+            if frame_index + i < frame.len() {
+                let keep = if and_mask == 1 {
+                    frame[frame_index + i]
+                } else {
+                    0
+                };
+                frame[frame_index + i] = if xor_mask == 1 { !keep } else { keep };
+            }
+        }
+    }
+}
+
+/// Applies a masked color pixel, whose top byte is a mask rather than
+/// alpha: 0 replaces the screen with the color, 0xFF XORs it.
+pub(crate) fn draw_masked_color_cursor(
+    frame: &mut [u8],
+    frame_index: usize,
+    shape: &[u8],
+    cursor_index: usize,
+) {
+    if cursor_index + 3 < shape.len() && frame_index + 3 < frame.len() {
+        let xor = shape[cursor_index + 3] != 0;
+        for i in 0..3 {
+            let color = shape[cursor_index + i];
+            if xor {
+                frame[frame_index + i] ^= color;
+            } else {
+                frame[frame_index + i] = color;
+            }
+        }
+        if !xor {
+            frame[frame_index + 3] = 255; // Full opacity
+        }
+    }
+}
+
+/// Where the system cursor is on the virtual desktop and whether it's
+/// showing, or `None` if Windows won't say, e.g. on the secure desktop.
+pub(crate) fn system_cursor() -> Option<((i32, i32), bool)> {
+    unsafe {
+        let mut info: CURSORINFO = mem::zeroed();
+        info.cbSize = mem::size_of::<CURSORINFO>() as u32;
+        if GetCursorInfo(&mut info) == 0 {
+            return None;
+        }
+        let position = (info.ptScreenPos.x, info.ptScreenPos.y);
+        Some((position, info.flags & CURSOR_SHOWING != 0))
+    }
+}
+
+/// A mouse button, for `Capturer::notify_click`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Whether a physical mouse button is down right now.
+pub(crate) fn button_down(button: MouseButton) -> bool {
+    let key = match button {
+        MouseButton::Left => VK_LBUTTON,
+        MouseButton::Right => VK_RBUTTON,
+        MouseButton::Middle => VK_MBUTTON,
+    };
+    unsafe { GetAsyncKeyState(key) as u16 & 0x8000 != 0 }
+}
+
+/// The pointer as one capturer last saw it, from `Capturer::save_cursor`.
+#[derive(Clone)]
+pub struct SavedCursor(pub(crate) CursorInfo);
+
+impl fmt::Debug for SavedCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SavedCursor")
+            .field("position", &self.0.position)
+            .field("visible", &self.0.visible)
+            .field("shape_valid", &self.0.shape_valid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether a shape's reported dimensions fit in a buffer of `len` bytes.
+/// Monochrome heights count both masks. Unknown kinds can't be checked.
+pub(crate) fn shape_fits(
+    kind: CursorShapeKind,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    len: usize,
+) -> bool {
+    let row = match kind {
+        CursorShapeKind::Color | CursorShapeKind::MaskedColor => width as u64 * 4,
+        CursorShapeKind::Monochrome => (width as u64).div_ceil(8),
+        CursorShapeKind::Unknown(_) => return true,
+    };
+    row <= pitch as u64 && pitch as u64 * height as u64 <= len as u64
+}
+
+/// Where a position local to `output` is on the virtual desktop.
+pub(crate) fn virtual_position(local: (i32, i32), output: Rect) -> (i32, i32) {
+    (output.x + local.0, output.y + local.1)
+}
+
+/// Where a cursor's top-left corner lands in an image of `region`.
+///
+/// DXGI reports the hot spot relative to the output it's on, wherever that
+/// output sits on the virtual desktop, so this goes through desktop
+/// coordinates: `output` and `region` are both in them. For a capture of
+/// the whole output they're the same, and the output's origin cancels out
+/// even when it's negative.
+pub(crate) fn cursor_origin(
+    position: (i32, i32),
+    hot_spot: (i32, i32),
+    output: Rect,
+    region: Rect,
+) -> (i32, i32) {
+    (
+        output.x + position.0 - hot_spot.0 - region.x,
+        output.y + position.1 - hot_spot.1 - region.y,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(
+        kind: CursorShapeKind,
+        width: u32,
+        height: u32,
+        pitch: u32,
+        data: &[u8],
+    ) -> CursorShape {
+        CursorShape {
+            kind,
+            hot_spot: (1, 0),
+            width,
+            height,
+            pitch,
+            data,
+        }
+    }
+
+    /// Outputs at the origin, left of it, and right of it and raised.
+    fn outputs() -> [Rect; 3] {
+        [
+            Rect::new(0, 0, 1920, 1080),
+            Rect::new(-1920, 0, 1920, 1080),
+            Rect::new(1920, -500, 1920, 1080),
+        ]
+    }
+
+    #[test]
+    fn cursor_origin_on_its_own_output() {
+        // However the output sits on the desktop, a capture of it puts the
+        // cursor at its local position less the hot spot.
+        for &output in &outputs() {
+            assert_eq!(cursor_origin((100, 50), (4, 2), output, output), (96, 48));
+            assert_eq!(cursor_origin((0, 0), (4, 2), output, output), (-4, -2));
+        }
+    }
+
+    #[test]
+    fn virtual_positions() {
+        let expected = [(100, 50), (-1820, 50), (2020, -450)];
+        for (&output, &expected) in outputs().iter().zip(&expected) {
+            assert_eq!(virtual_position((100, 50), output), expected);
+            // A capture of the whole desktop draws it there too.
+            let desktop = Rect::new(-1920, -500, 5760, 1580);
+            let origin = cursor_origin((100, 50), (0, 0), output, desktop);
+            assert_eq!((origin.0 + desktop.x, origin.1 + desktop.y), expected);
+        }
+    }
+
+    #[test]
+    fn cursor_origin_in_a_desktop_capture() {
+        // A capture spanning all three, from the top left corner.
+        let desktop = Rect::new(-1920, -500, 5760, 1580);
+        let expected = [(2016, 548), (96, 548), (3936, 48)];
+        for (&output, &expected) in outputs().iter().zip(&expected) {
+            assert_eq!(cursor_origin((100, 50), (4, 2), output, desktop), expected);
+        }
+
+        // Part of one output, which the cursor may be outside of.
+        let region = Rect::new(-1000, 200, 400, 300);
+        assert_eq!(
+            cursor_origin((1000, 300), (0, 0), outputs()[1], region),
+            (80, 100)
+        );
+        assert_eq!(
+            cursor_origin((100, 50), (0, 0), outputs()[1], region),
+            (-820, -150)
+        );
+    }
+
+    #[test]
+    fn shape_kinds_from_raw_types() {
+        assert_eq!(CursorShapeKind::from(1), CursorShapeKind::Monochrome);
+        assert_eq!(CursorShapeKind::from(2), CursorShapeKind::Color);
+        assert_eq!(CursorShapeKind::from(4), CursorShapeKind::MaskedColor);
+        assert_eq!(CursorShapeKind::from(0), CursorShapeKind::Unknown(0));
+        assert_eq!(CursorShapeKind::from(3), CursorShapeKind::Unknown(3));
+        assert_eq!(CursorShapeKind::from(8), CursorShapeKind::Unknown(8));
+    }
+
+    #[test]
+    fn color_shapes_decode_as_they_are() {
+        // Two pixels and a pixel of padding.
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 9, 9, 9];
+        let image = shape(CursorShapeKind::Color, 2, 1, 12, &data)
+            .to_bgra()
+            .unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.hot_spot, (1, 0));
+        assert_eq!(image.data, data[..8]);
+        assert_eq!(image.invert, [false, false]);
+    }
+
+    #[test]
+    fn monochrome_shapes_decode_each_mask_pair() {
+        // A 3 by 2 shape, 2 bytes a row. AND then XOR per pixel:
+        // 0/0, 1/0, 1/1 on top and 0/1, 0/0, 1/0 below.
+        let data = [0x60, 0xff, 0x20, 0xff, 0x20, 0xff, 0x80, 0xff];
+        let shape = shape(CursorShapeKind::Monochrome, 3, 4, 2, &data);
+        assert_eq!(shape.visible_size(), (3, 2));
+        let image = shape.to_bgra().unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 0, 0xff, 0, 0, 0, 0, 0x80, 0x80, 0x80, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0xff, 0, 0, 0, 0,
+        ];
+        assert_eq!(image.data, expected);
+        assert_eq!(image.invert, [false, false, true, false, false, false]);
+    }
+
+    #[test]
+    fn masked_color_shapes_replace_or_invert() {
+        // Replace with a color, XOR with black, XOR with a color.
+        let data = [10, 20, 30, 0, 0, 0, 0, 0xff, 1, 2, 3, 0xff];
+        let image = shape(CursorShapeKind::MaskedColor, 3, 1, 12, &data)
+            .to_bgra()
+            .unwrap();
+        assert_eq!(
+            image.data,
+            [10, 20, 30, 0xff, 0, 0, 0, 0, 0x80, 0x80, 0x80, 0xff]
+        );
+        assert_eq!(image.invert, [false, false, true]);
+    }
+
+    #[test]
+    fn straight_blending() {
+        assert_eq!(blend_straight(200, 255, 10), 200);
+        assert_eq!(blend_straight(200, 0, 10), 10);
+        assert_eq!(blend_straight(255, 128, 0), 128);
+        assert_eq!(blend_straight(0, 128, 255), 127);
+    }
+
+    #[test]
+    fn premultiplied_blending() {
+        assert_eq!(blend_premultiplied(100, 128, 200), 199);
+        assert_eq!(blend_premultiplied(0, 255, 77), 0);
+        assert_eq!(blend_premultiplied(128, 128, 0), 128);
+        // Bad data, with color over alpha, saturates.
+        assert_eq!(blend_premultiplied(255, 0, 255), 255);
+    }
+
+    #[test]
+    fn color_pixels_blend_unless_clear() {
+        let shape = [128, 64, 0, 128, 9, 9, 9, 0];
+        let mut frame = [200, 200, 200, 0, 1, 2, 3, 4];
+        draw_color_cursor(&mut frame, 0, &shape, 0, blend_premultiplied);
+        draw_color_cursor(&mut frame, 4, &shape, 4, blend_premultiplied);
+        assert_eq!(frame, [227, 163, 99, 255, 1, 2, 3, 4]);
+
+        let mut frame = [200, 200, 200, 0];
+        draw_color_cursor(&mut frame, 0, &shape, 0, blend_straight);
+        assert_eq!(frame, [163, 131, 99, 255]);
+    }
+
+    /// Sizes and pitches that are zero, small, odd, and too big to fit or
+    /// multiply.
+    const EDGES: [u32; 10] = [
+        0,
+        1,
+        2,
+        3,
+        8,
+        9,
+        16,
+        0x4000_0000,
+        u32::MAX / 4 + 1,
+        u32::MAX,
+    ];
+
+    /// Draws `shape` with each of the draw functions into an 8 by 8 frame
+    /// with padded rows, at every pixel it covers there, then once more
+    /// just past the frame's end.
+    fn draw_everywhere(shape: &CursorShape) {
+        const SIZE: usize = 8;
+        let pitch = SIZE * 4 + 4;
+        let mut frame = vec![0x55; SIZE * pitch];
+        let (width, height) = shape.visible_size();
+        let (width, height) = (SIZE.min(width as usize), SIZE.min(height as usize));
+        let mut pixels: Vec<_> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y, y * pitch + x * 4)))
+            .collect();
+        pixels.push((0, 0, frame.len() - 2));
+        pixels.push((0, 0, frame.len() + 4));
+        for &(x, y, frame_index) in &pixels {
+            let cursor_index = y * shape.pitch as usize + x * 4;
+            draw_color_cursor(
+                &mut frame,
+                frame_index,
+                shape.data,
+                cursor_index,
+                blend_straight,
+            );
+            draw_color_cursor(
+                &mut frame,
+                frame_index,
+                shape.data,
+                cursor_index,
+                blend_premultiplied,
+            );
+            draw_monochrome_cursor(&mut frame, frame_index, shape, x, y);
+            draw_masked_color_cursor(&mut frame, frame_index, shape.data, cursor_index);
+        }
+    }
+
+    #[test]
+    fn odd_shapes_dont_panic() {
+        let kinds = [
+            CursorShapeKind::Color,
+            CursorShapeKind::Monochrome,
+            CursorShapeKind::MaskedColor,
+            CursorShapeKind::Unknown(3),
+        ];
+        // Noise, so every mask and alpha case comes up.
+        let mut seed = 0x2545_f491_u32;
+        let data: Vec<u8> = (0..1024)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 24) as u8
+            })
+            .collect();
+
+        for &kind in &kinds {
+            for &width in &EDGES {
+                for &height in &EDGES {
+                    for &pitch in &EDGES {
+                        for &len in &[0, 1, 4, 7, 16, 64, 1024] {
+                            let shape = shape(kind, width, height, pitch, &data[..len]);
+                            let fits = shape_fits(kind, width, height, pitch, len);
+                            if let Some(image) = shape.to_bgra() {
+                                assert!(fits);
+                                let pixels = image.width as usize * image.height as usize;
+                                assert_eq!(image.data.len(), pixels * 4);
+                                assert_eq!(image.invert.len(), pixels);
+                            }
+                            looks_premultiplied(&shape);
+                            draw_everywhere(&shape);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn odd_custom_images_dont_panic() {
+        for &width in &EDGES {
+            for &height in &EDGES {
+                for &len in &[0, 4, 36, 256] {
+                    let image = CursorImage {
+                        width,
+                        height,
+                        hot_spot: (0, 0),
+                        data: vec![0x80; len],
+                        invert: Vec::new(),
+                    };
+                    // Rows wider than a pitch can say never fit.
+                    let row = width as u64 * 4;
+                    let fits = row <= u32::MAX as u64 && row * height as u64 <= len as u64;
+                    match image.as_shape() {
+                        Some(shape) => {
+                            assert!(fits, "{}x{} in {}", width, height, len);
+                            assert!(shape.to_bgra().is_some());
+                            draw_everywhere(&shape);
+                        }
+                        None => assert!(!fits, "{}x{} in {}", width, height, len),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn premultiplied_detection() {
+        // Padding that breaks the rule doesn't count.
+        let data = [10, 20, 30, 40, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0];
+        let premultiplied = shape(CursorShapeKind::Color, 2, 1, 12, &data);
+        assert!(looks_premultiplied(&premultiplied));
+        let straight = shape(CursorShapeKind::Color, 3, 1, 12, &data);
+        assert!(!looks_premultiplied(&straight));
+
+        // Nothing to look at, rather than a panic.
+        let no_pitch = shape(CursorShapeKind::Color, 2, 1, 0, &data);
+        assert!(!looks_premultiplied(&no_pitch));
+        let no_width = shape(CursorShapeKind::Color, 0, 1, 12, &data);
+        assert!(!looks_premultiplied(&no_width));
+    }
+
+    #[test]
+    fn monochrome_pixels_use_the_pitch_and_bit() {
+        // A 10 by 2 shape, 4 bytes a row: the AND mask's two rows, then
+        // the XOR mask's. Pixels 8 and 9 are in the second byte of a row.
+        #[rustfmt::skip]
+        let data = [
+            0x00, 0x00, 0xaa, 0xaa,
+            0x00, 0xc0, 0xaa, 0xaa,
+            0x40, 0x00, 0xaa, 0xaa,
+            0x00, 0x40, 0xaa, 0xaa,
+        ];
+        let shape = shape(CursorShapeKind::Monochrome, 10, 4, 4, &data);
+        let mut frame = [0x12, 0x34, 0x56, 0x40].repeat(4);
+        // AND then XOR: 0/0, 0/1, 1/0 and 1/1.
+        for (i, &(x, y)) in [(0, 0), (1, 0), (8, 1), (9, 1)].iter().enumerate() {
+            draw_monochrome_cursor(&mut frame, i * 4, &shape, x, y);
+        }
+        #[rustfmt::skip]
+        let expected = [
+            0x00, 0x00, 0x00, 0x40,
+            0xff, 0xff, 0xff, 0x40,
+            0x12, 0x34, 0x56, 0x40,
+            0xed, 0xcb, 0xa9, 0x40,
+        ];
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn masked_color_pixels_replace_or_xor() {
+        let shape = [10, 20, 30, 0, 0x0f, 0xf0, 0xff, 0xff];
+        let mut frame = [0x55, 0x55, 0x55, 0x40, 0x55, 0x55, 0x55, 0x40];
+        draw_masked_color_cursor(&mut frame, 0, &shape, 0);
+        draw_masked_color_cursor(&mut frame, 4, &shape, 4);
+        // Replaced and made opaque, then XORed with alpha left alone.
+        assert_eq!(frame, [10, 20, 30, 0xff, 0x5a, 0xa5, 0xaa, 0x40]);
+
+        // Pixels off the end of either buffer are skipped.
+        let mut frame = [0x55; 6];
+        draw_masked_color_cursor(&mut frame, 4, &shape, 0);
+        draw_masked_color_cursor(&mut frame, 0, &shape, 6);
+        assert_eq!(frame, [0x55; 6]);
+    }
+
+    #[test]
+    fn bad_shapes_dont_decode() {
+        let data = [0; 15];
+        assert!(shape(CursorShapeKind::Color, 2, 2, 8, &data)
+            .to_bgra()
+            .is_none());
+        assert!(shape(CursorShapeKind::Color, 3, 1, 8, &data)
+            .to_bgra()
+            .is_none());
+        assert!(shape(CursorShapeKind::Unknown(9), 1, 1, 4, &data)
+            .to_bgra()
+            .is_none());
+    }
+}
@@ -0,0 +1,103 @@
+use super::handle::FrameSync;
+use super::wrap_hresult;
+use std::{io, ptr};
+use winapi::shared::guiddef::GUID;
+use winapi::um::d3d12::{ID3D12Device, ID3D12Fence, ID3D12Resource};
+use winapi::um::winnt::HANDLE;
+
+const IID_ID3D12RESOURCE: GUID = GUID {
+    Data1: 0x696442be,
+    Data2: 0xa72e,
+    Data3: 0x4059,
+    Data4: [0xbc, 0x79, 0x5b, 0x5c, 0x98, 0x04, 0x0f, 0xad],
+};
+
+const IID_ID3D12FENCE: GUID = GUID {
+    Data1: 0x0a753dcf,
+    Data2: 0xc4d8,
+    Data3: 0x4b91,
+    Data4: [0xad, 0xf6, 0xbe, 0x5a, 0x60, 0xd9, 0x5a, 0x76],
+};
+
+/// A shared frame opened on a D3D12 device, from `open_shared_texture`.
+pub struct D3D12SharedTexture {
+    /// The texture, in `D3D12_RESOURCE_STATE_COMMON`.
+    pub resource: *mut ID3D12Resource,
+    /// The capturer's fence.
+    pub fence: *mut ID3D12Fence,
+    /// Wait for the fence to reach this before reading `resource`.
+    pub ready: u64,
+    /// Signal this on the fence once done with `resource`.
+    pub release: u64,
+}
+
+/// Opens a shared frame from `Capturer::frame_shared_handle` on a D3D12
+/// device, given its texture and fence handles and its `sync`.
+///
+/// D3D12 only accepts NT handles, i.e. ones from `CreateSharedHandle` on a
+/// texture created with `D3D11_RESOURCE_MISC_SHARED_NTHANDLE`, which the
+/// capturer makes. Legacy handles from `IDXGIResource::GetSharedHandle` are
+/// rejected. It can't acquire a keyed mutex either, so the capturer has to
+/// use `SharedSync::Fence`, which shares the texture with
+/// `D3D11_RESOURCE_MISC_SHARED` and a fence made with
+/// `D3D11_FENCE_FLAG_SHARED`. With `FrameSync::KeyedMutex` this fails with
+/// `Unsupported`.
+///
+/// For each frame, on the queue that reads it:
+///
+/// 1. `Wait(fence, ready)`, so the reads wait for the capturer's copy.
+/// 2. Execute the reads of `resource`.
+/// 3. `Signal(fence, release)`, so the capturer can copy the next frame in.
+///
+/// The capturer waits on the CPU for `release` before its next copy, and
+/// `frame_shared_handle` fails with `TimedOut` if it doesn't come in time.
+/// The values grow with every frame, so take them from each frame's
+/// `SharedFrameHandle::sync`. `examples/d3d12_readback.rs` goes through it
+/// end to end.
+///
+/// # Safety
+///
+/// `device` must be a valid device, and `texture` and `fence` valid NT handles
+/// from a capturer on the same adapter. The caller owns the returned
+/// interfaces and must release them.
+pub unsafe fn open_shared_texture(
+    device: *mut ID3D12Device,
+    texture: HANDLE,
+    fence: HANDLE,
+    sync: FrameSync,
+) -> io::Result<D3D12SharedTexture> {
+    let (ready, release) = match sync {
+        FrameSync::Fence { ready, release } => (ready, release),
+        FrameSync::KeyedMutex { .. } => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "D3D12 can't acquire a keyed mutex; capture with SharedSync::Fence",
+            ))
+        }
+    };
+
+    let mut resource: *mut ID3D12Resource = ptr::null_mut();
+    wrap_hresult((*device).OpenSharedHandle(
+        texture,
+        &IID_ID3D12RESOURCE,
+        &mut resource as *mut *mut _ as *mut *mut _,
+    ))?;
+
+    let mut shared_fence: *mut ID3D12Fence = ptr::null_mut();
+    let res = (*device).OpenSharedHandle(
+        fence,
+        &IID_ID3D12FENCE,
+        &mut shared_fence as *mut *mut _ as *mut *mut _,
+    );
+    if let Err(err) = wrap_hresult(res) {
+        (*resource).Release();
+        return Err(err);
+    }
+
+    Ok(D3D12SharedTexture {
+        resource,
+        fence: shared_fence,
+        ready,
+        release,
+    })
+}
@@ -0,0 +1,128 @@
+use super::{wrap_hresult, WAIT_SLICE};
+use crate::{CancellationToken, Cancelled};
+use std::error::Error;
+use std::time::{Duration, Instant};
+use std::{cmp, fmt, io};
+use winapi::shared::minwindef::UINT;
+use winapi::shared::winerror::DXGI_ERROR_WAIT_TIMEOUT;
+use winapi::um::winbase::INFINITE;
+
+/// The error payload of a `frame` call that ran out of time after acquiring
+/// a frame, e.g. while waiting for the GPU copy. The frame is dropped, and
+/// the next call starts afresh. Check for it with `is_deadline_exceeded`.
+#[derive(Copy, Clone, Debug)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the frame deadline was exceeded")
+    }
+}
+
+impl Error for DeadlineExceeded {}
+
+impl From<DeadlineExceeded> for io::Error {
+    fn from(exceeded: DeadlineExceeded) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, exceeded)
+    }
+}
+
+/// Whether an error came from a `frame` call that ran out of time.
+pub fn is_deadline_exceeded(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<DeadlineExceeded>())
+}
+
+/// When a wait of `timeout` milliseconds starting now ends, or `None` if
+/// it's `INFINITE`.
+pub(super) fn deadline_after(timeout: UINT) -> Option<Instant> {
+    if timeout == INFINITE {
+        None
+    } else {
+        Some(Instant::now() + Duration::from_millis(timeout as u64))
+    }
+}
+
+/// The milliseconds left until `deadline`, rounded up so a wait only gets 0
+/// once it's passed. `INFINITE` without one.
+pub(super) fn millis_left(deadline: Option<Instant>) -> UINT {
+    deadline.map_or(INFINITE, |deadline| {
+        let left = deadline.saturating_duration_since(Instant::now());
+        cmp::min(left.as_nanos().div_ceil(1_000_000), INFINITE as u128 - 1) as UINT
+    })
+}
+
+/// Calls `try_acquire` with waits of at most `WAIT_SLICE` milliseconds
+/// until it has a frame, `timeout` runs out or `cancel` is cancelled, so
+/// that cancellation is noticed while blocked. `try_acquire` says whether
+/// it got a frame, and its errors end the wait.
+pub(super) fn wait_in_slices<F>(
+    timeout: UINT,
+    cancel: &CancellationToken,
+    mut try_acquire: F,
+) -> io::Result<()>
+where
+    F: FnMut(UINT) -> io::Result<bool>,
+{
+    let deadline = deadline_after(timeout);
+    loop {
+        if cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+
+        let wait = cmp::min(millis_left(deadline), WAIT_SLICE);
+        if try_acquire(wait)? {
+            return Ok(());
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return wrap_hresult(DXGI_ERROR_WAIT_TIMEOUT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::is_cancelled;
+    use std::thread;
+
+    /// Blocks for the whole wait without a frame, like an idle desktop.
+    fn idle(wait: UINT) -> io::Result<bool> {
+        thread::sleep(Duration::from_millis(wait as u64));
+        Ok(false)
+    }
+
+    #[test]
+    fn cancelled_mid_wait() {
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            canceller.cancel();
+        });
+
+        let res = wait_in_slices(INFINITE, &cancel, idle);
+        let elapsed = start.elapsed();
+        handle.join().unwrap();
+
+        assert!(res.as_ref().is_err_and(is_cancelled), "{:?}", res);
+        let limit = Duration::from_millis(30 + WAIT_SLICE as u64 + 200);
+        assert!(elapsed < limit, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn times_out_or_gets_a_frame() {
+        let cancel = CancellationToken::new();
+        let res = wait_in_slices(20, &cancel, idle);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let mut calls = 0;
+        let res = wait_in_slices(INFINITE, &cancel, |_| {
+            calls += 1;
+            Ok(calls == 3)
+        });
+        assert!(res.is_ok());
+        assert_eq!(calls, 3);
+    }
+}
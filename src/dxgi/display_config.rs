@@ -0,0 +1,146 @@
+use super::ffi::*;
+use std::{mem, ptr};
+use winapi::shared::ntdef::LUID;
+use winapi::shared::winerror::{ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS};
+use winapi::um::wingdi::{
+    DEVMODEW, DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+    DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+    DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_DEVICE_INFO_TYPE,
+    DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO, DISPLAYCONFIG_PATH_INFO,
+    DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME, QDC_ONLY_ACTIVE_PATHS,
+};
+use winapi::um::winnt::LONG;
+
+/// The active display paths, i.e. which GDI sources drive which monitors.
+pub(crate) unsafe fn active_paths() -> Vec<DISPLAYCONFIG_PATH_INFO> {
+    loop {
+        let (mut npaths, mut nmodes) = (0, 0);
+        if GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut npaths, &mut nmodes)
+            != ERROR_SUCCESS as LONG
+        {
+            return Vec::new();
+        }
+
+        let mut paths = vec![mem::zeroed(); npaths as usize];
+        let mut modes = vec![mem::zeroed(); nmodes as usize];
+        let res = QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut npaths,
+            paths.as_mut_ptr(),
+            &mut nmodes,
+            modes.as_mut_ptr(),
+            ptr::null_mut(),
+        );
+
+        // The topology can change between the two calls.
+        if res == ERROR_INSUFFICIENT_BUFFER as LONG {
+            continue;
+        }
+        if res != ERROR_SUCCESS as LONG {
+            return Vec::new();
+        }
+
+        paths.truncate(npaths as usize);
+        return paths;
+    }
+}
+
+/// The path whose source is the GDI device `name`, like `\\.\DISPLAY1`.
+pub(crate) unsafe fn find_path(name: &[u16]) -> Option<DISPLAYCONFIG_PATH_INFO> {
+    let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+
+    active_paths().into_iter().find(|path| {
+        let mut source: DISPLAYCONFIG_SOURCE_DEVICE_NAME = mem::zeroed();
+        source.header = header(
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+            mem::size_of_val(&source),
+            path.sourceInfo.adapterId,
+            path.sourceInfo.id,
+        );
+        if DisplayConfigGetDeviceInfo(&mut source.header) != ERROR_SUCCESS as LONG {
+            return false;
+        }
+
+        let gdi = &source.viewGdiDeviceName;
+        let len = gdi.iter().position(|&c| c == 0).unwrap_or(gdi.len());
+        &gdi[..len] == name
+    })
+}
+
+/// The size of the desktop mode saved for the GDI device `name`, which
+/// must be NUL-terminated. Full-screen apps can switch to other modes.
+pub(crate) unsafe fn desktop_mode(name: &[u16]) -> Option<(u32, u32)> {
+    let mut mode: DEVMODEW = mem::zeroed();
+    mode.dmSize = mem::size_of::<DEVMODEW>() as u16;
+    if EnumDisplaySettingsW(name.as_ptr(), ENUM_REGISTRY_SETTINGS, &mut mode) == 0 {
+        return None;
+    }
+    Some((mode.dmPelsWidth, mode.dmPelsHeight))
+}
+
+/// The SDR white level of the monitor on a path, in nits,
+/// or `None` unless HDR is enabled there.
+pub(crate) unsafe fn sdr_white_level(path: &DISPLAYCONFIG_PATH_INFO) -> Option<f32> {
+    let target = &path.targetInfo;
+
+    let mut color: DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO = mem::zeroed();
+    color.header = header(
+        DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+        mem::size_of_val(&color),
+        target.adapterId,
+        target.id,
+    );
+    if DisplayConfigGetDeviceInfo(&mut color.header) != ERROR_SUCCESS as LONG
+        || color.advancedColorEnabled() == 0
+    {
+        return None;
+    }
+
+    let mut white: DISPLAYCONFIG_SDR_WHITE_LEVEL = mem::zeroed();
+    white.header = header(
+        DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL,
+        mem::size_of_val(&white),
+        target.adapterId,
+        target.id,
+    );
+    if DisplayConfigGetDeviceInfo(&mut white.header) != ERROR_SUCCESS as LONG {
+        return None;
+    }
+
+    Some(white.SDRWhiteLevel as f32 / 1000.0 * 80.0)
+}
+
+/// The device interface path of the monitor on a path, which stays the
+/// same across reboots and reconnects.
+pub(crate) unsafe fn monitor_path(path: &DISPLAYCONFIG_PATH_INFO) -> Option<String> {
+    let target = &path.targetInfo;
+
+    let mut name: DISPLAYCONFIG_TARGET_DEVICE_NAME = mem::zeroed();
+    name.header = header(
+        DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+        mem::size_of_val(&name),
+        target.adapterId,
+        target.id,
+    );
+    if DisplayConfigGetDeviceInfo(&mut name.header) != ERROR_SUCCESS as LONG {
+        return None;
+    }
+
+    let s = &name.monitorDevicePath;
+    let len = s.iter().position(|&c| c == 0).unwrap_or(s.len());
+    Some(String::from_utf16_lossy(&s[..len]))
+}
+
+fn header(
+    kind: DISPLAYCONFIG_DEVICE_INFO_TYPE,
+    size: usize,
+    adapter: LUID,
+    id: u32,
+) -> DISPLAYCONFIG_DEVICE_INFO_HEADER {
+    DISPLAYCONFIG_DEVICE_INFO_HEADER {
+        _type: kind,
+        size: size as u32,
+        adapterId: adapter,
+        id,
+    }
+}
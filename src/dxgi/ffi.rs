@@ -1,16 +1,24 @@
+#![allow(non_snake_case)]
+
+use winapi::ctypes::c_void;
 use winapi::shared::{
     dxgi::{IDXGIAdapter1, IDXGIFactory1},
     guiddef::{GUID, REFIID},
-    minwindef::{HMODULE, UINT},
+    minwindef::{BOOL, DWORD, HMODULE, UINT, ULONG},
 };
 use winapi::um::{
-    d3d11::{ID3D11Device, ID3D11DeviceContext},
+    d3d11::{ID3D11Device, ID3D11DeviceChild, ID3D11DeviceChildVtbl, ID3D11DeviceContext},
+    d3d11_2::{ID3D11Device2, ID3D11Device2Vtbl, ID3D11DeviceContext2, ID3D11DeviceContext2Vtbl},
     d3dcommon::{D3D_DRIVER_TYPE, D3D_FEATURE_LEVEL},
-    winnt::HRESULT,
+    minwinbase::SECURITY_ATTRIBUTES,
+    unknwnbase::{IUnknown, IUnknownVtbl},
+    wingdi::{
+        DEVMODEW, DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_DEVICE_INFO_TYPE,
+        DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_TOPOLOGY_ID,
+    },
+    winnt::{HANDLE, HRESULT, LONG, LPCWSTR},
 };
 
-pub const DXGI_MAP_READ: UINT = 1;
-
 pub const IID_IDXGIFACTORY1: GUID = GUID {
     Data1: 0x770aae78,
     Data2: 0xf26f,
@@ -25,11 +33,39 @@ pub const IID_IDXGIOUTPUT1: GUID = GUID {
     Data4: [0xa3, 0x40, 0xa6, 0x85, 0x22, 0x66, 0x66, 0xcc],
 };
 
-pub const IID_IDXGISURFACE: GUID = GUID {
-    Data1: 3405559148,
-    Data2: 27331,
-    Data3: 18569,
-    Data4: [191, 71, 158, 35, 187, 210, 96, 236],
+pub const IID_IDXGIADAPTER1: GUID = GUID {
+    Data1: 0x29038f61,
+    Data2: 0x3839,
+    Data3: 0x4626,
+    Data4: [0x91, 0xfd, 0x08, 0x68, 0x79, 0x01, 0x1a, 0x05],
+};
+
+pub const IID_IDXGIOUTPUT5: GUID = GUID {
+    Data1: 0x80a07424,
+    Data2: 0xab52,
+    Data3: 0x42eb,
+    Data4: [0x83, 0x3c, 0x0c, 0x42, 0xfd, 0x28, 0x2d, 0x98],
+};
+
+pub const IID_IDXGIOUTPUT6: GUID = GUID {
+    Data1: 0x068346e8,
+    Data2: 0xaaec,
+    Data3: 0x4b84,
+    Data4: [0xad, 0xd7, 0x13, 0x7f, 0x51, 0x3f, 0x77, 0xa1],
+};
+
+pub const IID_IDXGIADAPTER3: GUID = GUID {
+    Data1: 0x645967a4,
+    Data2: 0x1392,
+    Data3: 0x4310,
+    Data4: [0xa7, 0x98, 0x80, 0x53, 0xce, 0x3e, 0x93, 0xfd],
+};
+
+pub const IID_IDXGIDEVICE: GUID = GUID {
+    Data1: 0x54ec77fa,
+    Data2: 0x1377,
+    Data3: 0x44e6,
+    Data4: [0x8c, 0x32, 0x88, 0xfd, 0x5f, 0x44, 0xc8, 0x4c],
 };
 
 pub const IID_ID3D11TEXTURE2D: GUID = GUID {
@@ -39,6 +75,217 @@ pub const IID_ID3D11TEXTURE2D: GUID = GUID {
     Data4: [154, 180, 72, 149, 53, 211, 79, 156],
 };
 
+pub const IID_ID3D11MULTITHREAD: GUID = GUID {
+    Data1: 0x9b7e4e00,
+    Data2: 0x342c,
+    Data3: 0x4106,
+    Data4: [0xa1, 0x9f, 0x4f, 0x27, 0x04, 0xf6, 0x89, 0xf0],
+};
+
+// From d3d11_4.h, which winapi doesn't cover.
+RIDL! {#[uuid(0x9b7e4e00, 0x342c, 0x4106, 0xa1, 0x9f, 0x4f, 0x27, 0x04, 0xf6, 0x89, 0xf0)]
+interface ID3D11Multithread(ID3D11MultithreadVtbl): IUnknown(IUnknownVtbl) {
+    fn Enter() -> (),
+    fn Leave() -> (),
+    fn SetMultithreadProtected(
+        bMTProtect: BOOL,
+    ) -> BOOL,
+    fn GetMultithreadProtected() -> BOOL,
+}}
+
+pub const IID_ID3D11DEVICE5: GUID = GUID {
+    Data1: 0x8ffde202,
+    Data2: 0xa0e7,
+    Data3: 0x45df,
+    Data4: [0x9e, 0x01, 0xe8, 0x37, 0x80, 0x1b, 0x5e, 0xa0],
+};
+
+pub const IID_ID3D11DEVICECONTEXT4: GUID = GUID {
+    Data1: 0x917600da,
+    Data2: 0xf58c,
+    Data3: 0x4c33,
+    Data4: [0x98, 0xd8, 0x3e, 0x15, 0xb3, 0x90, 0xfa, 0x24],
+};
+
+pub const IID_ID3D11FENCE: GUID = GUID {
+    Data1: 0xaffde9d1,
+    Data2: 0x1df7,
+    Data3: 0x4bb7,
+    Data4: [0x8a, 0x34, 0x0f, 0x46, 0x25, 0x1d, 0xab, 0x80],
+};
+
+pub const D3D11_FENCE_FLAG_SHARED: UINT = 0x2;
+
+// From d3d11_3.h and d3d11_4.h. Only the fence methods are called; the rest
+// are there for the vtable layout, with the descriptor types they take left
+// as `c_void`.
+RIDL! {#[uuid(0xa05c8c37, 0xd2c6, 0x4732, 0xb3, 0xa0, 0x9c, 0xe0, 0xb0, 0xdc, 0x9a, 0xe6)]
+interface ID3D11Device3(ID3D11Device3Vtbl): ID3D11Device2(ID3D11Device2Vtbl) {
+    fn CreateTexture2D1(
+        pDesc1: *const c_void,
+        pInitialData: *const c_void,
+        ppTexture2D: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateTexture3D1(
+        pDesc1: *const c_void,
+        pInitialData: *const c_void,
+        ppTexture3D: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateRasterizerState2(
+        pRasterizerDesc: *const c_void,
+        ppRasterizerState: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateShaderResourceView1(
+        pResource: *mut c_void,
+        pDesc1: *const c_void,
+        ppSRView1: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateUnorderedAccessView1(
+        pResource: *mut c_void,
+        pDesc1: *const c_void,
+        ppUAView1: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateRenderTargetView1(
+        pResource: *mut c_void,
+        pDesc1: *const c_void,
+        ppRTView1: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateQuery1(
+        pQueryDesc1: *const c_void,
+        ppQuery1: *mut *mut c_void,
+    ) -> HRESULT,
+    fn GetImmediateContext3(
+        ppImmediateContext: *mut *mut c_void,
+    ) -> (),
+    fn CreateDeferredContext3(
+        ContextFlags: UINT,
+        ppDeferredContext: *mut *mut c_void,
+    ) -> HRESULT,
+    fn WriteToSubresource(
+        pDstResource: *mut c_void,
+        DstSubresource: UINT,
+        pDstBox: *const c_void,
+        pSrcData: *const c_void,
+        SrcRowPitch: UINT,
+        SrcDepthPitch: UINT,
+    ) -> (),
+    fn ReadFromSubresource(
+        pDstData: *mut c_void,
+        DstRowPitch: UINT,
+        DstDepthPitch: UINT,
+        pSrcResource: *mut c_void,
+        SrcSubresource: UINT,
+        pSrcBox: *const c_void,
+    ) -> (),
+}}
+
+RIDL! {#[uuid(0x8992ab71, 0x02e6, 0x4b8d, 0xba, 0x48, 0xb0, 0x56, 0xdc, 0xda, 0x42, 0xc4)]
+interface ID3D11Device4(ID3D11Device4Vtbl): ID3D11Device3(ID3D11Device3Vtbl) {
+    fn RegisterDeviceRemovedEvent(
+        hEvent: HANDLE,
+        pdwCookie: *mut DWORD,
+    ) -> HRESULT,
+    fn UnregisterDeviceRemoved(
+        dwCookie: DWORD,
+    ) -> (),
+}}
+
+RIDL! {#[uuid(0x8ffde202, 0xa0e7, 0x45df, 0x9e, 0x01, 0xe8, 0x37, 0x80, 0x1b, 0x5e, 0xa0)]
+interface ID3D11Device5(ID3D11Device5Vtbl): ID3D11Device4(ID3D11Device4Vtbl) {
+    fn OpenSharedFence(
+        hFence: HANDLE,
+        ReturnedInterface: REFIID,
+        ppFence: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateFence(
+        InitialValue: u64,
+        Flags: UINT,
+        ReturnedInterface: REFIID,
+        ppFence: *mut *mut c_void,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xb4e3c01d, 0xe79e, 0x4637, 0x91, 0xb2, 0x51, 0x0e, 0x9f, 0x4c, 0x9b, 0x8f)]
+interface ID3D11DeviceContext3(ID3D11DeviceContext3Vtbl):
+    ID3D11DeviceContext2(ID3D11DeviceContext2Vtbl) {
+    fn Flush1(
+        ContextType: UINT,
+        hEvent: HANDLE,
+    ) -> (),
+    fn SetHardwareProtectionState(
+        HwProtectionEnable: BOOL,
+    ) -> (),
+    fn GetHardwareProtectionState(
+        pHwProtectionEnable: *mut BOOL,
+    ) -> (),
+}}
+
+RIDL! {#[uuid(0x917600da, 0xf58c, 0x4c33, 0x98, 0xd8, 0x3e, 0x15, 0xb3, 0x90, 0xfa, 0x24)]
+interface ID3D11DeviceContext4(ID3D11DeviceContext4Vtbl):
+    ID3D11DeviceContext3(ID3D11DeviceContext3Vtbl) {
+    fn Signal(
+        pFence: *mut ID3D11Fence,
+        Value: u64,
+    ) -> HRESULT,
+    fn Wait(
+        pFence: *mut ID3D11Fence,
+        Value: u64,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xaffde9d1, 0x1df7, 0x4bb7, 0x8a, 0x34, 0x0f, 0x46, 0x25, 0x1d, 0xab, 0x80)]
+interface ID3D11Fence(ID3D11FenceVtbl): ID3D11DeviceChild(ID3D11DeviceChildVtbl) {
+    fn CreateSharedHandle(
+        pAttributes: *const SECURITY_ATTRIBUTES,
+        dwAccess: DWORD,
+        lpName: LPCWSTR,
+        pHandle: *mut HANDLE,
+    ) -> HRESULT,
+    fn GetCompletedValue() -> u64,
+    fn SetEventOnCompletion(
+        Value: u64,
+        hEvent: HANDLE,
+    ) -> HRESULT,
+}}
+
+pub const DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL: DISPLAYCONFIG_DEVICE_INFO_TYPE = 11;
+
+#[repr(C)]
+pub struct DISPLAYCONFIG_SDR_WHITE_LEVEL {
+    pub header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+    /// In thousandths of 80 nits.
+    pub SDRWhiteLevel: ULONG,
+}
+
+pub const ENUM_REGISTRY_SETTINGS: DWORD = 0xFFFF_FFFE;
+
+#[link(name = "user32")]
+extern "system" {
+    pub fn EnumDisplaySettingsW(
+        lpszDeviceName: LPCWSTR,
+        iModeNum: DWORD,
+        lpDevMode: *mut DEVMODEW,
+    ) -> BOOL;
+
+    pub fn GetDisplayConfigBufferSizes(
+        flags: UINT,
+        numPathArrayElements: *mut UINT,
+        numModeInfoArrayElements: *mut UINT,
+    ) -> LONG;
+
+    pub fn QueryDisplayConfig(
+        flags: UINT,
+        numPathArrayElements: *mut UINT,
+        pathArray: *mut DISPLAYCONFIG_PATH_INFO,
+        numModeInfoArrayElements: *mut UINT,
+        modeInfoArray: *mut DISPLAYCONFIG_MODE_INFO,
+        currentTopologyId: *mut DISPLAYCONFIG_TOPOLOGY_ID,
+    ) -> LONG;
+
+    pub fn DisplayConfigGetDeviceInfo(requestPacket: *mut DISPLAYCONFIG_DEVICE_INFO_HEADER)
+        -> LONG;
+}
+
 #[link(name = "dxgi")]
 #[link(name = "d3d11")]
 extern "system" {
@@ -49,7 +296,7 @@ extern "system" {
         DriverType: D3D_DRIVER_TYPE,
         Software: HMODULE,
         Flags: UINT,
-        pFeatureLevels: *mut D3D_FEATURE_LEVEL,
+        pFeatureLevels: *const D3D_FEATURE_LEVEL,
         FeatureLevels: UINT,
         SDKVersion: UINT,
         ppDevice: *mut *mut ID3D11Device,
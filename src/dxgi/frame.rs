@@ -0,0 +1,246 @@
+use super::{bytes_per_pixel, PixelFormat};
+use std::{ops, ptr};
+use winapi::shared::dxgiformat::DXGI_FORMAT;
+
+/// A captured frame, valid until the capturer is used again.
+///
+/// Rows may be padded, so step through them by `stride`, or use `rows`.
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: DXGI_FORMAT,
+}
+
+impl<'a> Frame<'a> {
+    pub(crate) fn new(
+        data: &'a [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: DXGI_FORMAT,
+    ) -> Frame<'a> {
+        Frame {
+            data,
+            width,
+            height,
+            stride,
+            format,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Usually `DXGI_FORMAT_B8G8R8A8_UNORM`, or FP16 for HDR outputs.
+    pub fn format(&self) -> DXGI_FORMAT {
+        self.format
+    }
+
+    /// All of it, padding included.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Each row's pixels, without the padding.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> {
+        let row = self.width * bytes_per_pixel(self.format);
+        self.data
+            .chunks(self.stride)
+            .take(self.height)
+            .map(move |r| &r[..row])
+    }
+}
+
+/// Copies `frame` into `dst` without padding, mirrored across the axes set in
+/// `flip` (horizontal, vertical), and made opaque as `opaque` if it's set.
+pub(super) fn copy_rows(
+    frame: &Frame,
+    dst: &mut [u8],
+    (flip_horizontal, flip_vertical): (bool, bool),
+    opaque: Option<PixelFormat>,
+) {
+    let bpp = bytes_per_pixel(frame.format);
+    let row = frame.width * bpp;
+    for (y, src) in frame.rows().enumerate() {
+        let y = if flip_vertical {
+            frame.height - 1 - y
+        } else {
+            y
+        };
+        let dst = &mut dst[y * row..][..row];
+        if flip_horizontal {
+            for (dst, src) in dst.chunks_exact_mut(bpp).zip(src.chunks_exact(bpp).rev()) {
+                dst.copy_from_slice(src);
+            }
+        } else {
+            dst.copy_from_slice(src);
+        }
+        if let Some(format) = opaque {
+            format.make_opaque(dst);
+        }
+    }
+}
+
+/// Copies `height` rows of `row` bytes into `dst`, top down and unpadded,
+/// from a surface whose rows start at `top` and are `pitch` bytes apart.
+/// `pitch` is negative for surfaces mapped bottom up.
+///
+/// # Safety
+///
+/// Each of the rows must be readable.
+pub(super) unsafe fn copy_bottom_up(
+    top: *const u8,
+    pitch: isize,
+    row: usize,
+    height: usize,
+    dst: &mut Vec<u8>,
+) {
+    dst.clear();
+    dst.resize(row * height, 0);
+    for y in 0..height {
+        let src = top.offset(y as isize * pitch);
+        ptr::copy_nonoverlapping(src, dst.as_mut_ptr().add(y * row), row);
+    }
+}
+
+impl<'a> ops::Deref for Frame<'a> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+/// The shape of a frame copied out by `Capturer::frame_into`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FrameLayout {
+    pub width: usize,
+    pub height: usize,
+    /// Always `width` times the bytes per pixel, as the copy has no padding.
+    pub stride: usize,
+    pub format: DXGI_FORMAT,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{rotate, Rotation};
+    use winapi::shared::dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM;
+
+    /// A 3 by 2 frame with a pixel of padding on each row. Every byte is
+    /// different, so a misplaced one shows.
+    fn padded() -> Vec<u8> {
+        (0..32).collect()
+    }
+
+    #[test]
+    fn rows_leave_out_padding() {
+        let data = padded();
+        let frame = Frame::new(&data, 3, 2, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        let rows: Vec<_> = frame.rows().collect();
+        assert_eq!(rows, [&data[..12], &data[16..28]]);
+
+        // The last row may stop short of the padding, as mappings can.
+        let frame = Frame::new(&data[..28], 3, 2, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        assert_eq!(frame.rows().count(), 2);
+        assert_eq!(frame.rows().last(), Some(&data[16..28]));
+
+        // Rows past the height aren't part of the frame.
+        let frame = Frame::new(&data, 3, 1, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        assert_eq!(frame.rows().count(), 1);
+    }
+
+    #[test]
+    fn both_flips_turn_it_half_way() {
+        let data = padded();
+        let frame = Frame::new(&data, 3, 2, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        let mut flipped = vec![0; 24];
+        copy_rows(&frame, &mut flipped, (true, true), None);
+
+        let mut turned = Vec::new();
+        rotate(&data, 16, 3, 2, 4, Rotation::Rotate180, &mut turned);
+        assert_eq!(flipped, turned);
+    }
+
+    #[test]
+    fn flips_mirror_whole_pixels() {
+        let data = padded();
+        let frame = Frame::new(&data, 3, 2, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        let pixel = |dst: &[u8], x: usize, y: usize| dst[(y * 3 + x) * 4..][..4].to_vec();
+        let src = |x: usize, y: usize| data[y * 16 + x * 4..][..4].to_vec();
+
+        let mut dst = vec![0; 24];
+        copy_rows(&frame, &mut dst, (true, false), None);
+        assert_eq!(pixel(&dst, 0, 0), src(2, 0));
+        assert_eq!(pixel(&dst, 2, 1), src(0, 1));
+        copy_rows(&frame, &mut dst, (false, true), None);
+        assert_eq!(pixel(&dst, 0, 0), src(0, 1));
+        assert_eq!(pixel(&dst, 2, 1), src(2, 0));
+    }
+
+    #[test]
+    fn opaque_copies_have_full_alpha() {
+        let data = padded();
+        let frame = Frame::new(&data, 3, 2, 16, DXGI_FORMAT_B8G8R8A8_UNORM);
+        let mut dst = vec![0; 24];
+        copy_rows(&frame, &mut dst, (true, false), Some(PixelFormat::Bgra8));
+        assert!(dst.chunks(4).all(|px| px[3] == 0xff));
+        // Only the alpha changed.
+        assert_eq!(dst[..3], data[8..11]);
+
+        copy_rows(&frame, &mut dst, (false, false), None);
+        assert_eq!(dst[3], 3);
+    }
+
+    #[test]
+    fn opaque_in_every_format() {
+        let mut px = [0u8; 8];
+        PixelFormat::Rgba8.make_opaque(&mut px);
+        assert_eq!(px, [0, 0, 0, 0xff, 0, 0, 0, 0xff]);
+
+        let mut px = [0x12, 0x34, 0x56, 0x3f];
+        PixelFormat::Rgb10A2.make_opaque(&mut px);
+        assert_eq!(px, [0x12, 0x34, 0x56, 0xff]);
+
+        let mut px = [1, 2, 3, 4, 5, 6, 0, 0];
+        PixelFormat::Rgba16Float.make_opaque(&mut px);
+        assert_eq!(px, [1, 2, 3, 4, 5, 6, 0x00, 0x3c]);
+
+        let mut px = [1, 2, 3, 4];
+        PixelFormat::Unknown(0).make_opaque(&mut px);
+        assert_eq!(px, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn bottom_up_surfaces_come_out_top_down() {
+        // Three rows of 6 bytes, 8 apart, with the top row last in memory.
+        #[rustfmt::skip]
+        let surface = [
+            20, 21, 22, 23, 24, 25, 0, 0,
+            10, 11, 12, 13, 14, 15, 0, 0,
+            0, 1, 2, 3, 4, 5, 0, 0,
+        ];
+        let mut dst = vec![0xaa; 100];
+        unsafe { copy_bottom_up(surface.as_ptr().add(16), -8, 6, 3, &mut dst) };
+        assert_eq!(dst.len(), 18);
+        assert_eq!(
+            dst,
+            [0, 1, 2, 3, 4, 5, 10, 11, 12, 13, 14, 15, 20, 21, 22, 23, 24, 25]
+        );
+
+        // The same copy works for a surface mapped the usual way.
+        unsafe { copy_bottom_up(surface.as_ptr(), 8, 6, 2, &mut dst) };
+        assert_eq!(dst, [20, 21, 22, 23, 24, 25, 10, 11, 12, 13, 14, 15]);
+    }
+}
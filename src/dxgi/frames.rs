@@ -0,0 +1,87 @@
+use super::{Capturer, OwnedFrame};
+use std::io;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::UINT;
+
+/// Captured frames as an iterator, from `Capturer::frames`.
+///
+/// Timeouts are skipped unless `skip_timeouts(false)`. Any other error is
+/// yielded once and ends the iteration.
+pub struct Frames<'a> {
+    capturer: &'a mut Capturer,
+    timeout: UINT,
+    skip_timeouts: bool,
+    remaining: Option<usize>,
+    end: Option<Instant>,
+    done: bool,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(capturer: &'a mut Capturer, timeout: UINT) -> Frames<'a> {
+        Frames {
+            capturer,
+            timeout,
+            skip_timeouts: true,
+            remaining: None,
+            end: None,
+            done: false,
+        }
+    }
+
+    /// Whether to keep waiting after a timeout, rather than yield it. On by
+    /// default. Yielded timeouts don't end the iteration.
+    pub fn skip_timeouts(mut self, skip: bool) -> Frames<'a> {
+        self.skip_timeouts = skip;
+        self
+    }
+
+    /// Stops after `n` frames. Errors don't count.
+    pub fn max_frames(mut self, n: usize) -> Frames<'a> {
+        self.remaining = Some(n);
+        self
+    }
+
+    /// Stops once `duration` has passed from now, cutting the last wait
+    /// short if need be.
+    pub fn max_duration(mut self, duration: Duration) -> Frames<'a> {
+        self.end = Some(Instant::now() + duration);
+        self
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = io::Result<OwnedFrame>;
+
+    fn next(&mut self) -> Option<io::Result<OwnedFrame>> {
+        loop {
+            if self.done || self.remaining == Some(0) {
+                return None;
+            }
+            let timeout = match self.end {
+                Some(end) => {
+                    let left = end.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return None;
+                    }
+                    let left = left.as_millis().min(UINT::MAX as u128) as UINT;
+                    self.timeout.min(left)
+                }
+                None => self.timeout,
+            };
+
+            match self.capturer.frame_owned(timeout) {
+                Ok(frame) => {
+                    if let Some(ref mut remaining) = self.remaining {
+                        *remaining -= 1;
+                    }
+                    return Some(Ok(frame));
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut && self.skip_timeouts => {}
+                Err(err) => {
+                    self.done = err.kind() != io::ErrorKind::TimedOut;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use super::check;
+use super::config::SharedSync;
+use super::ffi::{
+    ID3D11Device5, ID3D11DeviceContext4, ID3D11Fence, D3D11_FENCE_FLAG_SHARED, IID_ID3D11DEVICE5,
+    IID_ID3D11DEVICECONTEXT4, IID_ID3D11FENCE,
+};
+use std::{io, mem, ptr};
+use winapi::shared::dxgi::IDXGIKeyedMutex;
+use winapi::shared::dxgi1_2::{
+    IDXGIResource1, DXGI_SHARED_RESOURCE_READ, DXGI_SHARED_RESOURCE_WRITE,
+};
+use winapi::shared::dxgiformat::DXGI_FORMAT;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::{FALSE, UINT};
+use winapi::shared::winerror::{HRESULT, WAIT_TIMEOUT};
+use winapi::um::d3d11::{
+    ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+    D3D11_BIND_SHADER_RESOURCE, D3D11_RESOURCE_MISC_SHARED, D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX,
+    D3D11_RESOURCE_MISC_SHARED_NTHANDLE, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::synchapi::{CreateEventW, WaitForSingleObject};
+use winapi::um::winbase::WAIT_OBJECT_0;
+use winapi::um::winnt::{GENERIC_ALL, HANDLE};
+
+const IID_IDXGIKEYEDMUTEX: GUID = GUID {
+    Data1: 0x9d8e1289,
+    Data2: 0xd7b3,
+    Data3: 0x465f,
+    Data4: [0x81, 0x26, 0x25, 0x0e, 0x34, 0x9a, 0xf8, 0x5d],
+};
+
+const IID_IDXGIRESOURCE1: GUID = GUID {
+    Data1: 0x30961379,
+    Data2: 0x4609,
+    Data3: 0x4a41,
+    Data4: [0x99, 0x8e, 0x54, 0xfe, 0x56, 0x7e, 0xe0, 0xc1],
+};
+
+/// The key the capturer acquires the shared texture's keyed mutex with,
+/// and consumers release it to.
+pub const CAPTURER_KEY: u64 = 0;
+/// The key consumers acquire the shared texture's keyed mutex with, and the
+/// capturer releases it to after each copy.
+pub const CONSUMER_KEY: u64 = 1;
+
+/// How a consumer waits for a shared frame and hands it back.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FrameSync {
+    /// Acquire the texture's keyed mutex with `acquire` before reading,
+    /// and release it with `release` after.
+    KeyedMutex { acquire: u64, release: u64 },
+    /// Wait until the shared fence reaches `ready` before reading, and
+    /// signal `release` on it after.
+    Fence { ready: u64, release: u64 },
+}
+
+enum Sync {
+    KeyedMutex(*mut IDXGIKeyedMutex),
+    Fence {
+        fence: *mut ID3D11Fence,
+        context: *mut ID3D11DeviceContext4,
+        /// Set when the consumer releases a frame.
+        event: HANDLE,
+        /// How many frames were copied in.
+        frames: u64,
+    },
+}
+
+/// A texture other processes can open, which frames are copied into.
+pub(crate) struct SharedTexture {
+    texture: *mut ID3D11Texture2D,
+    sync: Sync,
+    desc: D3D11_TEXTURE2D_DESC,
+    /// Tells consumers apart textures the capturer made over its life.
+    id: u64,
+}
+
+impl SharedTexture {
+    /// Makes a texture that can hold copies of `source`.
+    pub unsafe fn new(
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+        source: &D3D11_TEXTURE2D_DESC,
+        id: u64,
+        sync: SharedSync,
+    ) -> io::Result<SharedTexture> {
+        let mut desc = *source;
+        desc.MipLevels = 1;
+        desc.ArraySize = 1;
+        desc.Usage = D3D11_USAGE_DEFAULT;
+        desc.BindFlags = D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET;
+        desc.CPUAccessFlags = 0;
+        desc.MiscFlags = D3D11_RESOURCE_MISC_SHARED_NTHANDLE
+            | match sync {
+                SharedSync::KeyedMutex => D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX,
+                SharedSync::Fence => D3D11_RESOURCE_MISC_SHARED,
+            };
+
+        let mut texture = ptr::null_mut();
+        check(
+            "CreateTexture2D",
+            (*device).CreateTexture2D(&desc, ptr::null(), &mut texture),
+        )?;
+
+        let res = match sync {
+            SharedSync::KeyedMutex => Sync::new_keyed_mutex(texture),
+            SharedSync::Fence => Sync::new_fence(device, context),
+        };
+        match res {
+            Ok(sync) => Ok(SharedTexture {
+                texture,
+                sync,
+                desc,
+                id,
+            }),
+            Err(err) => {
+                (*texture).Release();
+                Err(err)
+            }
+        }
+    }
+
+    /// Whether copies of `source` fit.
+    pub fn fits(&self, source: &D3D11_TEXTURE2D_DESC) -> bool {
+        (self.desc.Width, self.desc.Height, self.desc.Format)
+            == (source.Width, source.Height, source.Format)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn desc(&self) -> &D3D11_TEXTURE2D_DESC {
+        &self.desc
+    }
+
+    /// How consumers synchronize with the last copy.
+    pub fn frame_sync(&self) -> FrameSync {
+        match self.sync {
+            Sync::KeyedMutex(_) => FrameSync::KeyedMutex {
+                acquire: CONSUMER_KEY,
+                release: CAPTURER_KEY,
+            },
+            // Frame n is ready at 2n - 1 and released at 2n.
+            Sync::Fence { frames, .. } => FrameSync::Fence {
+                ready: frames * 2 - 1,
+                release: frames * 2,
+            },
+        }
+    }
+
+    /// Copies `source` in, waiting up to `timeout` milliseconds for the
+    /// consumer to release the last frame. Fails with `TimedOut` if it
+    /// doesn't.
+    pub unsafe fn copy(
+        &mut self,
+        context: *mut ID3D11DeviceContext,
+        source: *mut ID3D11Texture2D,
+        timeout: UINT,
+    ) -> io::Result<()> {
+        match self.sync {
+            Sync::KeyedMutex(mutex) => {
+                let res = (*mutex).AcquireSync(CAPTURER_KEY, timeout);
+                if res == WAIT_TIMEOUT as HRESULT {
+                    return Err(still_held());
+                }
+                check("AcquireSync", res)?;
+
+                (*context).CopyResource(
+                    self.texture as *mut ID3D11Resource,
+                    source as *mut ID3D11Resource,
+                );
+                check("ReleaseSync", (*mutex).ReleaseSync(CONSUMER_KEY))
+            }
+            Sync::Fence {
+                fence,
+                context: context4,
+                event,
+                ref mut frames,
+            } => {
+                let released = *frames * 2;
+                if (*fence).GetCompletedValue() < released {
+                    check(
+                        "SetEventOnCompletion",
+                        (*fence).SetEventOnCompletion(released, event),
+                    )?;
+                    match WaitForSingleObject(event, timeout) {
+                        WAIT_OBJECT_0 => {}
+                        WAIT_TIMEOUT => return Err(still_held()),
+                        _ => return Err(io::Error::last_os_error()),
+                    }
+                }
+
+                (*context).CopyResource(
+                    self.texture as *mut ID3D11Resource,
+                    source as *mut ID3D11Resource,
+                );
+                check("Signal", (*context4).Signal(fence, released + 1))?;
+                // The consumer is on another device, so the copy and the
+                // signal have to reach the GPU without waiting for more work.
+                (*context).Flush();
+                *frames += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// A new NT handle to the fence, if there is one.
+    pub unsafe fn create_fence_handle(&self) -> io::Result<Option<HANDLE>> {
+        match self.sync {
+            Sync::KeyedMutex(_) => Ok(None),
+            Sync::Fence { fence, .. } => {
+                let mut handle = ptr::null_mut();
+                check(
+                    "CreateSharedHandle",
+                    (*fence).CreateSharedHandle(ptr::null(), GENERIC_ALL, ptr::null(), &mut handle),
+                )?;
+                Ok(Some(handle))
+            }
+        }
+    }
+
+    /// A new NT handle to the texture.
+    pub unsafe fn create_handle(&self) -> io::Result<HANDLE> {
+        let mut resource: *mut IDXGIResource1 = ptr::null_mut();
+        check(
+            "QueryInterface",
+            (*self.texture).QueryInterface(
+                &IID_IDXGIRESOURCE1,
+                &mut resource as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+
+        let mut handle = ptr::null_mut();
+        let res = (*resource).CreateSharedHandle(
+            ptr::null(),
+            DXGI_SHARED_RESOURCE_READ | DXGI_SHARED_RESOURCE_WRITE,
+            ptr::null(),
+            &mut handle,
+        );
+        (*resource).Release();
+        check("CreateSharedHandle", res)?;
+        Ok(handle)
+    }
+}
+
+impl Drop for SharedTexture {
+    fn drop(&mut self) {
+        unsafe {
+            match self.sync {
+                Sync::KeyedMutex(mutex) => {
+                    (*mutex).Release();
+                }
+                Sync::Fence {
+                    fence,
+                    context,
+                    event,
+                    ..
+                } => {
+                    (*fence).Release();
+                    (*context).Release();
+                    CloseHandle(event);
+                }
+            }
+            (*self.texture).Release();
+        }
+    }
+}
+
+impl Sync {
+    unsafe fn new_keyed_mutex(texture: *mut ID3D11Texture2D) -> io::Result<Sync> {
+        let mut mutex: *mut IDXGIKeyedMutex = ptr::null_mut();
+        check(
+            "QueryInterface",
+            (*texture).QueryInterface(
+                &IID_IDXGIKEYEDMUTEX,
+                &mut mutex as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+        Ok(Sync::KeyedMutex(mutex))
+    }
+
+    unsafe fn new_fence(
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+    ) -> io::Result<Sync> {
+        let mut device5: *mut ID3D11Device5 = ptr::null_mut();
+        let res = (*device).QueryInterface(
+            &IID_ID3D11DEVICE5,
+            &mut device5 as *mut *mut _ as *mut *mut _,
+        );
+        if res < 0 {
+            return Err(fences_unsupported());
+        }
+        let mut fence: *mut ID3D11Fence = ptr::null_mut();
+        let res = (*device5).CreateFence(
+            0,
+            D3D11_FENCE_FLAG_SHARED,
+            &IID_ID3D11FENCE,
+            &mut fence as *mut *mut _ as *mut *mut _,
+        );
+        (*device5).Release();
+        check("CreateFence", res)?;
+
+        let mut context4: *mut ID3D11DeviceContext4 = ptr::null_mut();
+        let res = (*context).QueryInterface(
+            &IID_ID3D11DEVICECONTEXT4,
+            &mut context4 as *mut *mut _ as *mut *mut _,
+        );
+        if res < 0 {
+            (*fence).Release();
+            return Err(fences_unsupported());
+        }
+
+        let event = CreateEventW(ptr::null_mut(), FALSE, FALSE, ptr::null());
+        if event.is_null() {
+            let err = io::Error::last_os_error();
+            (*context4).Release();
+            (*fence).Release();
+            return Err(err);
+        }
+
+        Ok(Sync::Fence {
+            fence,
+            context: context4,
+            event,
+            frames: 0,
+        })
+    }
+}
+
+fn still_held() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "the consumer still holds the shared texture",
+    )
+}
+
+fn fences_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "shared fences need Windows 10 1703 or later",
+    )
+}
+
+/// A frame in a texture other processes can open, from
+/// `Capturer::frame_shared_handle`.
+///
+/// To read it, duplicate `handle` into the consumer's process and open it
+/// with `ID3D11Device1::OpenSharedResource1`. How each frame is handed over
+/// depends on `Config::shared_sync`, and `sync` gives the details.
+///
+/// With `SharedSync::KeyedMutex`, query the texture for `IDXGIKeyedMutex`.
+/// Then for each frame:
+///
+/// 1. `AcquireSync(CONSUMER_KEY, ...)`, which waits for the copy.
+/// 2. Read the texture.
+/// 3. `ReleaseSync(CAPTURER_KEY)`, so the next frame can be copied in.
+///
+/// With `SharedSync::Fence`, there's no keyed mutex. Open `fence_handle` too,
+/// with `ID3D11Device5::OpenSharedFence` or `ID3D12Device::OpenSharedHandle`.
+/// Then for each frame, wait on the GPU for the fence to reach `ready`, read
+/// the texture, and signal `release`. `d3d12::open_shared_texture` does the
+/// opening for D3D12.
+///
+/// Until the consumer releases it, the capturer's next copy waits. Textures
+/// are reused until the size or format changes, which `texture_id` tells,
+/// so consumers can keep one open instead of opening every handle.
+///
+/// The handles are closed when this is dropped.
+pub struct SharedFrameHandle {
+    handle: HANDLE,
+    /// Null with a keyed mutex.
+    fence: HANDLE,
+    sync: FrameSync,
+    width: usize,
+    height: usize,
+    format: DXGI_FORMAT,
+    texture_id: u64,
+}
+
+unsafe impl Send for SharedFrameHandle {}
+
+impl SharedFrameHandle {
+    pub(crate) unsafe fn new(texture: &SharedTexture) -> io::Result<SharedFrameHandle> {
+        let desc = texture.desc();
+        let handle = texture.create_handle()?;
+        let fence = match texture.create_fence_handle() {
+            Ok(fence) => fence.unwrap_or(ptr::null_mut()),
+            Err(err) => {
+                CloseHandle(handle);
+                return Err(err);
+            }
+        };
+        Ok(SharedFrameHandle {
+            handle,
+            fence,
+            sync: texture.frame_sync(),
+            width: desc.Width as usize,
+            height: desc.Height as usize,
+            format: desc.Format,
+            texture_id: texture.id(),
+        })
+    }
+
+    /// An NT handle to the texture, valid in this process.
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// An NT handle to the shared fence, with `SharedSync::Fence`.
+    pub fn fence_handle(&self) -> Option<HANDLE> {
+        if self.fence.is_null() {
+            None
+        } else {
+            Some(self.fence)
+        }
+    }
+
+    /// How to wait for this frame and hand it back.
+    pub fn sync(&self) -> FrameSync {
+        self.sync
+    }
+
+    /// The key to acquire the keyed mutex with before reading. Meaningless
+    /// with `SharedSync::Fence`.
+    pub fn key(&self) -> u64 {
+        CONSUMER_KEY
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> DXGI_FORMAT {
+        self.format
+    }
+
+    /// Which of the capturer's shared textures this is.
+    pub fn texture_id(&self) -> u64 {
+        self.texture_id
+    }
+
+    /// Keeps the texture handle open, for the caller to close. The fence
+    /// handle, if any, is closed; use `into_raw_parts` to keep both.
+    pub fn into_raw(self) -> HANDLE {
+        let (handle, fence) = self.into_raw_parts();
+        if let Some(fence) = fence {
+            unsafe {
+                CloseHandle(fence);
+            }
+        }
+        handle
+    }
+
+    /// Keeps the texture and fence handles open, for the caller to close.
+    pub fn into_raw_parts(self) -> (HANDLE, Option<HANDLE>) {
+        let parts = (self.handle, self.fence_handle());
+        mem::forget(self);
+        parts
+    }
+}
+
+impl Drop for SharedFrameHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+            if !self.fence.is_null() {
+                CloseHandle(self.fence);
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::error::Error;
+use std::fmt;
+use winapi::shared::winerror::HRESULT;
+
+/// The error payload of a failed DXGI or D3D11 call.
+/// Get it with `io::Error::get_ref` and `downcast_ref`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HresultError {
+    /// The call that failed, like `AcquireNextFrame`.
+    pub stage: &'static str,
+    pub code: HRESULT,
+}
+
+impl fmt::Display for HresultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {:#010x}", self.stage, self.code)
+    }
+}
+
+impl Error for HresultError {}
@@ -0,0 +1,154 @@
+use std::error::Error;
+use std::{fmt, io};
+
+/// Memory held by a capturer's buffers, in bytes.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BufferMemory {
+    pub cursor_shape: usize,
+    /// Dirty rectangles.
+    pub metadata: usize,
+    /// Frames kept by `Capturer::accumulate`.
+    pub accumulator: usize,
+    /// The copy kept by `Capturer::frame_packed`.
+    pub packed: usize,
+    /// The upright copy kept by `Capturer::set_correct_rotation`.
+    pub rotated: usize,
+    /// The top-down copy of desktop surfaces some drivers map bottom up.
+    pub top_down: usize,
+    /// The sRGB copy made by `Config::to_srgb`.
+    pub srgb: usize,
+    /// Frames from `Capturer::frame_owned` that are still around, and
+    /// buffers of dropped ones waiting to be reused.
+    pub owned_frames: usize,
+    /// The last frame kept by `Capturer::track_changes`.
+    pub diff: usize,
+    /// The tile hashes kept by `Capturer::track_tiles`.
+    pub tiles: usize,
+    /// The I420 and NV12 copies `Capturer::frame_nv12` makes on the CPU
+    /// while there's an adjustment.
+    pub conversions: usize,
+    /// The staging texture, which lives in video memory.
+    pub staging: usize,
+}
+
+impl BufferMemory {
+    /// Everything in system memory, i.e. what `Config::max_buffer_memory` caps.
+    pub fn cpu(&self) -> usize {
+        self.cursor_shape
+            + self.metadata
+            + self.accumulator
+            + self.packed
+            + self.rotated
+            + self.top_down
+            + self.srgb
+            + self.owned_frames
+            + self.diff
+            + self.tiles
+            + self.conversions
+    }
+}
+
+/// The error payload when a buffer would take a capturer over
+/// `Config::max_buffer_memory`. Nothing was allocated. Check for it with
+/// `is_buffer_limit_exceeded`.
+#[derive(Copy, Clone, Debug)]
+pub struct BufferLimitExceeded {
+    /// What the buffer is for.
+    pub buffer: &'static str,
+    /// The bytes the capturer's buffers would have held.
+    pub needed: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for BufferLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the {} would take the buffers to {} bytes, over the {} byte limit",
+            self.buffer, self.needed, self.limit
+        )
+    }
+}
+
+impl Error for BufferLimitExceeded {}
+
+impl From<BufferLimitExceeded> for io::Error {
+    fn from(exceeded: BufferLimitExceeded) -> io::Error {
+        io::Error::new(io::ErrorKind::OutOfMemory, exceeded)
+    }
+}
+
+/// Whether an error came from a buffer that would have gone over the limit.
+pub fn is_buffer_limit_exceeded(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<BufferLimitExceeded>())
+}
+
+/// Fails if buffers holding `used` bytes can't grow by `growth` more
+/// without going over `limit`. Not growing is always fine, even over it.
+pub(super) fn check_limit(
+    buffer: &'static str,
+    limit: Option<usize>,
+    used: usize,
+    growth: usize,
+) -> io::Result<()> {
+    let limit = match limit {
+        Some(limit) if growth > 0 => limit,
+        _ => return Ok(()),
+    };
+    let needed = used.saturating_add(growth);
+    if needed > limit {
+        return Err(BufferLimitExceeded {
+            buffer,
+            needed,
+            limit,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_leaves_out_video_memory() {
+        let usage = BufferMemory {
+            cursor_shape: 1,
+            metadata: 2,
+            accumulator: 4,
+            packed: 8,
+            rotated: 16,
+            top_down: 32,
+            srgb: 64,
+            owned_frames: 128,
+            diff: 256,
+            tiles: 512,
+            conversions: 1024,
+            staging: 2048,
+        };
+        assert_eq!(usage.cpu(), 2047);
+    }
+
+    #[test]
+    fn growth_up_to_the_limit() {
+        assert!(check_limit("frame", Some(100), 60, 40).is_ok());
+        assert!(check_limit("frame", None, usize::MAX, usize::MAX).is_ok());
+        // Already over, but not growing.
+        assert!(check_limit("frame", Some(100), 150, 0).is_ok());
+    }
+
+    #[test]
+    fn growth_over_the_limit() {
+        let err = check_limit("packed frame", Some(100), 60, 41).unwrap_err();
+        assert!(is_buffer_limit_exceeded(&err));
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+        assert_eq!(
+            err.to_string(),
+            "the packed frame would take the buffers to 101 bytes, over the 100 byte limit"
+        );
+
+        let err = check_limit("frame", Some(100), usize::MAX, 1).unwrap_err();
+        assert!(is_buffer_limit_exceeded(&err));
+    }
+}
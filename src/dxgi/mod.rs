@@ -1,228 +1,1096 @@
 use self::ffi::*;
-use std::{io, mem, ptr, slice};
+use crate::convert::{
+    self, Adjustment, Lut, Rotation, TonemapParams, Yuv444Buffer, YuvBuffer, YuvMatrix,
+};
+use crate::snapshot::{self, ImageFormat};
+use crate::{
+    Accumulator, Activity, ActivityTracker, CancellationToken, ChangeTracker, FrameDiff, MoveRect,
+    Rect, TileRect, Watchdog,
+};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{cmp, io, mem, ptr, slice};
 use winapi::shared::{
     dxgi::{
-        IDXGIAdapter1, IDXGIFactory1, IDXGIResource, IDXGISurface, DXGI_OUTPUT_DESC,
-        DXGI_RESOURCE_PRIORITY_MAXIMUM,
+        IDXGIAdapter1, IDXGIDevice, IDXGIFactory1, IDXGIResource, DXGI_ADAPTER_DESC1,
+        DXGI_OUTPUT_DESC, DXGI_RESOURCE_PRIORITY_MAXIMUM,
     },
     dxgi1_2::{
-        IDXGIOutput1, IDXGIOutputDuplication,
-        DXGI_OUTDUPL_POINTER_SHAPE_INFO, DXGI_OUTDUPL_POINTER_SHAPE_TYPE_COLOR,
-        DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MASKED_COLOR, DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MONOCHROME,
+        IDXGIOutput1, IDXGIOutputDuplication, DXGI_OUTDUPL_FRAME_INFO, DXGI_OUTDUPL_MOVE_RECT,
+        DXGI_OUTDUPL_POINTER_SHAPE_INFO,
+    },
+    dxgi1_4::{
+        IDXGIAdapter3, DXGI_MEMORY_SEGMENT_GROUP, DXGI_MEMORY_SEGMENT_GROUP_LOCAL,
+        DXGI_MEMORY_SEGMENT_GROUP_NON_LOCAL, DXGI_QUERY_VIDEO_MEMORY_INFO,
+    },
+    dxgi1_5::IDXGIOutput5,
+    dxgi1_6::{IDXGIOutput6, DXGI_OUTPUT_DESC1},
+    dxgiformat::{
+        DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_NV12, DXGI_FORMAT_R16G16B16A16_FLOAT,
     },
-    dxgitype::DXGI_MODE_ROTATION,
+    dxgitype::{DXGI_MODE_ROTATION, DXGI_SAMPLE_DESC},
     minwindef::{TRUE, UINT},
+    windef::RECT,
     winerror::{
-        DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_INVALID_CALL, DXGI_ERROR_NOT_CURRENTLY_AVAILABLE,
+        DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_INVALID_CALL, DXGI_ERROR_MORE_DATA,
+        DXGI_ERROR_NOT_CURRENTLY_AVAILABLE, DXGI_ERROR_SDK_COMPONENT_MISSING,
         DXGI_ERROR_SESSION_DISCONNECTED, DXGI_ERROR_UNSUPPORTED, DXGI_ERROR_WAIT_TIMEOUT,
-        E_ACCESSDENIED, HRESULT, S_OK,
+        DXGI_ERROR_WAS_STILL_DRAWING, E_ACCESSDENIED, E_INVALIDARG, E_OUTOFMEMORY, HRESULT, S_OK,
     },
 };
 use winapi::um::{
     d3d11::{
         ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, D3D11_CPU_ACCESS_READ,
-        D3D11_SDK_VERSION, D3D11_USAGE_STAGING,
+        D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_FLAG_DO_NOT_WAIT, D3D11_MAP_READ, D3D11_SDK_VERSION,
+        D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
     },
-    d3dcommon::{D3D_DRIVER_TYPE_UNKNOWN, D3D_FEATURE_LEVEL_9_1},
+    d3dcommon::{
+        D3D_DRIVER_TYPE_UNKNOWN, D3D_FEATURE_LEVEL, D3D_FEATURE_LEVEL_10_0, D3D_FEATURE_LEVEL_10_1,
+        D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_9_1,
+        D3D_FEATURE_LEVEL_9_2, D3D_FEATURE_LEVEL_9_3,
+    },
+    profileapi::QueryPerformanceFrequency,
     unknwnbase::IUnknown,
-    winnt::LONG,
+    winnt::{LARGE_INTEGER, LONG},
 };
 
+mod clipboard;
+mod config;
+mod cursor;
+#[cfg(feature = "d3d12")]
+mod d3d12;
+mod deadline;
+mod display_config;
 mod ffi;
+mod frame;
+mod frames;
+mod handle;
+mod hresult;
+mod memory;
+mod mode;
+mod owned;
+mod saved;
+mod session;
+mod shared;
+mod stats;
+mod texture;
+mod video;
+
+pub use self::clipboard::{is_clipboard_busy, ClipboardBusy};
+pub use self::config::{
+    ClickHighlight, Config, CursorBlend, CursorMode, DeviceFlags, SharedSync, UnknownShapePolicy,
+};
+pub use self::cursor::{
+    CursorImage, CursorShape, CursorShapeKind, CursorState, MouseButton, SavedCursor,
+};
+#[cfg(feature = "d3d12")]
+pub use self::d3d12::{open_shared_texture, D3D12SharedTexture};
+use self::deadline::{deadline_after, millis_left, wait_in_slices};
+pub use self::deadline::{is_deadline_exceeded, DeadlineExceeded};
+pub use self::frame::{Frame, FrameLayout};
+pub use self::frames::Frames;
+use self::handle::SharedTexture;
+pub use self::handle::{FrameSync, SharedFrameHandle, CAPTURER_KEY, CONSUMER_KEY};
+pub use self::hresult::HresultError;
+pub use self::memory::{is_buffer_limit_exceeded, BufferLimitExceeded, BufferMemory};
+pub use self::mode::{ColorSpace, DuplicationDesc, Mode, ModeChange, PixelFormat};
+use self::owned::FramePool;
+pub use self::owned::OwnedFrame;
+pub use self::saved::{CapturerOptions, DisplayId, RestoreError};
+pub use self::session::CaptureSession;
+pub use self::shared::SharedCapturer;
+pub use self::stats::CaptureStats;
+use self::stats::Counters;
+pub use self::texture::FrameTexture;
+use self::video::VideoConverter;
+pub use self::video::{is_video_processor_unsupported, Nv12Frame, VideoProcessorUnsupported};
+
+/// A `frame` timeout that never expires.
+pub const INFINITE: UINT = 0xFFFF_FFFF;
+
+/// The longest a single wait blocks, in milliseconds.
+pub const WAIT_SLICE: UINT = 100;
 
 #[repr(C)]
-struct CursorInfo {
+#[derive(Clone)]
+pub(crate) struct CursorInfo {
+    /// Relative to the output's top-left corner.
     position: (i32, i32),
     shape: Vec<u8>,
     shape_info: DXGI_OUTDUPL_POINTER_SHAPE_INFO,
+    /// `shape_info.Type`, once a shape was received.
+    kind: Option<CursorShapeKind>,
     visible: bool,
+    /// Whether `shape_info` fits in `shape`.
+    shape_valid: bool,
     who_updated_position_last: u32,
     last_time_stamp: i64,
+    /// Whether the last frame moved, showed, hid or reshaped the pointer.
+    updated: bool,
+    /// How many pointer shapes were received. Kept across `recreate` and
+    /// `restore_cursor`.
+    shape_generation: u64,
+}
+
+impl CursorInfo {
+    fn new() -> CursorInfo {
+        CursorInfo {
+            position: (0, 0),
+            shape: Vec::new(),
+            shape_info: unsafe { mem::zeroed() },
+            kind: None,
+            visible: false,
+            shape_valid: false,
+            who_updated_position_last: 0,
+            last_time_stamp: 0,
+            updated: false,
+            shape_generation: 0,
+        }
+    }
+
+    /// Takes the pointer saved from another capturer, which has nothing new
+    /// to report yet.
+    fn restore(&mut self, saved: SavedCursor) {
+        *self = saved.0;
+        self.updated = false;
+    }
+
+    fn state(&self) -> CursorState<'_> {
+        let shape = match self.kind {
+            Some(kind) if self.shape_valid => Some(CursorShape {
+                kind,
+                hot_spot: (self.shape_info.HotSpot.x, self.shape_info.HotSpot.y),
+                width: self.shape_info.Width,
+                height: self.shape_info.Height,
+                pitch: self.shape_info.Pitch,
+                data: &self.shape,
+            }),
+            _ => None,
+        };
+        CursorState {
+            position: self.position,
+            visible: self.visible,
+            shape,
+        }
+    }
 }
 
+/// The pointer as a `CaptureSession`'s capturers last saw it, with the
+/// position on the virtual desktop. DXGI only reports the pointer to the
+/// output it's on and only sends a shape when it changes, so each capturer
+/// takes the rest from here.
+pub(crate) type SharedCursor = Arc<Mutex<CursorInfo>>;
+
 pub struct Capturer {
+    adapter: *mut IDXGIAdapter1,
     device: *mut ID3D11Device,
+    device_flags: DeviceFlags,
+    feature_level: D3D_FEATURE_LEVEL,
     context: *mut ID3D11DeviceContext,
     duplication: *mut IDXGIOutputDuplication,
-    capture_mouse: bool,
+    cursor_mode: CursorMode,
     cursor_info: CursorInfo,
-    fastlane: bool,
-    surface: *mut IDXGISurface,
+    /// Set for capturers from a `CaptureSession`.
+    shared_cursor: Option<SharedCursor>,
+    /// Drawn instead of the pointer shape, from `set_custom_cursor`.
+    custom_cursor: Option<CursorImage>,
+    click_highlight: Option<ClickHighlight>,
+    /// The last press, from `notify_click` or `poll_buttons`.
+    last_click: Option<(MouseButton, Instant)>,
+    /// Which of left, right and middle were down at the last poll.
+    buttons_down: [bool; 3],
+    mapped: bool,
+    staging: *mut ID3D11Texture2D,
+    staging_desc: Option<D3D11_TEXTURE2D_DESC>,
     data: *mut u8,
     len: usize,
+    /// The row stride of `data`, in bytes.
+    pitch: usize,
     height: usize,
     width: usize,
     output_number: u32,
-    offset_x: i32,
-    offset_y: i32,
+    output: *mut IDXGIOutput1,
+    /// Refreshed whenever the duplication is created.
     desc: DXGI_OUTPUT_DESC,
+    /// Where move and dirty rects are fetched to, as 32-bit words so it's
+    /// aligned for both.
+    metadata: Vec<u32>,
+    dirty_rects: Vec<Rect>,
+    move_rects: Vec<MoveRect>,
+    /// Frames that don't change this are released without being copied.
+    watch_region: Option<Rect>,
+    /// Set while `frame_nv12` loads a frame.
+    want_nv12: bool,
+    /// Set while `frame_texture` loads a frame, which leaves the texture in
+    /// `texture` instead of copying it.
+    want_texture: bool,
+    texture: *mut ID3D11Texture2D,
+    /// Set while `frame_shared_handle` loads a frame.
+    want_shared: bool,
+    shared_sync: SharedSync,
+    shared: Option<SharedTexture>,
+    /// How many shared textures were made, to number them.
+    shared_textures: u64,
+    /// Whether the frame was loaded into a texture rather than `data`.
+    gpu_loaded: bool,
+    /// Set while `frame_into` loads a frame, to draw the cursor on the copy.
+    defer_cursor: bool,
+    /// The copy `frame_packed` returns.
+    packed: Vec<u8>,
+    /// Buffers for `frame_owned`.
+    frame_pool: FramePool,
+    /// Set by `set_correct_rotation`.
+    correct_rotation: bool,
+    /// The loaded frame turned the way the display shows it, when
+    /// `showing_rotated` is set.
+    rotated: Vec<u8>,
+    showing_rotated: bool,
+    /// A top-down copy of a desktop surface mapped bottom up, which `data`
+    /// points into.
+    top_down: Vec<u8>,
+    nv12: Option<VideoConverter>,
+    /// Scales frames on the GPU when `output_size` is set.
+    scaler: Option<VideoConverter>,
+    output_size: Option<(u32, u32)>,
+    /// The size of the loaded frame, after any scaling.
+    frame_size: (usize, usize),
+    activity: Option<ActivityTracker>,
+    accumulator: Option<Accumulator>,
+    diff: Option<FrameDiff>,
+    tiles: Option<ChangeTracker>,
+    /// Whether `tiles` saw the last acquired frame, so the dirty rects since
+    /// cover everything that changed.
+    tiles_synced: bool,
+    /// The cursor drawn on the last frame `tiles` saw.
+    tiles_cursor: Option<Rect>,
+    tile_hints: Vec<Rect>,
+    frame_info: Option<FrameInfo>,
+    cancel: CancellationToken,
+    last_present_time: i64,
+    repeats: u32,
+    unknown_shape: UnknownShapePolicy,
+    cursor_blend: CursorBlend,
+    cursor_fallback: bool,
+    /// From `Config::hdr`, for duplicating again.
+    hdr: bool,
+    opaque_alpha: bool,
+    flip_vertical: bool,
+    flip_horizontal: bool,
+    /// The output's, refreshed when duplicating again.
+    color_space: ColorSpace,
+    to_srgb: bool,
+    /// The loaded frame converted to sRGB, which `data` points into.
+    srgb: Vec<u8>,
+    /// From `set_adjustment`.
+    lut: Option<Lut>,
+    /// `frame_nv12` converts on the CPU through these while `lut` is set.
+    adjusted_yuv: YuvBuffer,
+    adjusted_nv12: Vec<u8>,
+    watchdog: Option<Watchdog>,
+    stats: Counters,
+    /// Kept apart from `stats`, so `reset_stats` leaves it alone.
+    frame_number: u64,
+    dupl_desc: DuplicationDesc,
+    max_buffer_memory: Option<usize>,
+    /// Set by `recreate`, until the next frame's info takes it.
+    mode_change: Option<ModeChange>,
+    /// Whether the size changed since the last frame.
+    resized: bool,
 }
 
 impl Capturer {
     pub fn new(display: &Display, capture_mouse: bool) -> io::Result<Capturer> {
-        let mut device = ptr::null_mut();
-        let mut context = ptr::null_mut();
-        let mut duplication = ptr::null_mut();
-        let mut desc = mem::MaybeUninit::uninit();
+        Capturer::with_config(display, capture_mouse, Config::default())
+    }
+
+    pub fn with_config(
+        display: &Display,
+        capture_mouse: bool,
+        config: Config,
+    ) -> io::Result<Capturer> {
+        debug!(
+            "creating a device for {} with flags {:#x}",
+            String::from_utf16_lossy(display.name()),
+            config.device_flags.bits()
+        );
+
+        unsafe {
+            let (device, context, device_flags) =
+                create_device(display.adapter, config.device_flags)?;
 
-        if unsafe {
-            D3D11CreateDevice(
+            let res = Capturer::from_device(
+                display,
                 display.adapter,
-                D3D_DRIVER_TYPE_UNKNOWN,
-                ptr::null_mut(),
-                0,
-                ptr::null_mut(),
-                0,
-                D3D11_SDK_VERSION,
-                &mut device,
-                #[allow(const_item_mutation)]
-                &mut D3D_FEATURE_LEVEL_9_1,
-                &mut context,
-            )
-        } != S_OK
-        {
-            return Err(io::ErrorKind::Other.into());
+                capture_mouse,
+                device,
+                context,
+                device_flags,
+                &config,
+            );
+
+            match res {
+                Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    Capturer::on_other_adapter(display, capture_mouse, &config)
+                }
+                res => res,
+            }
         }
+    }
 
-        let res = wrap_hresult(unsafe {
-            (*display.inner).DuplicateOutput(device as *mut IUnknown, &mut duplication)
-        });
+    /// Like `new`, but starts from the pointer of an earlier capturer of the
+    /// same display, so the cursor is drawn before its shape next changes.
+    /// See `restore_cursor`.
+    pub fn new_with_cursor_state(
+        display: &Display,
+        capture_mouse: bool,
+        cursor: SavedCursor,
+    ) -> io::Result<Capturer> {
+        let mut capturer = Capturer::new(display, capture_mouse)?;
+        capturer.restore_cursor(cursor);
+        Ok(capturer)
+    }
 
-        if let Err(err) = res {
-            unsafe {
+    /// Captures the display with the given `adapter_index` and `output_index`.
+    pub fn from_indices(adapter: UINT, output: UINT, capture_mouse: bool) -> io::Result<Capturer> {
+        let display = Displays::new()?
+            .find(|d| d.adapter_index == adapter && d.output_index == output)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        Capturer::new(&display, capture_mouse)
+    }
+
+    /// Hybrid graphics laptops may only let another adapter duplicate an output.
+    unsafe fn on_other_adapter(
+        display: &Display,
+        capture_mouse: bool,
+        config: &Config,
+    ) -> io::Result<Capturer> {
+        info!("duplication is unsupported on the output's adapter, trying the others");
+
+        let mut factory: *mut IDXGIFactory1 = ptr::null_mut();
+        check(
+            "CreateDXGIFactory1",
+            CreateDXGIFactory1(&IID_IDXGIFACTORY1, &mut factory),
+        )?;
+
+        let mut result = Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "hybrid graphics configuration not supported for this output",
+        ));
+
+        let mut i = 0;
+        while result.is_err() {
+            let mut adapter = ptr::null_mut();
+            if (*factory).EnumAdapters1(i, &mut adapter) != S_OK {
+                break;
+            }
+            i += 1;
+
+            if !same_adapter(adapter, display.adapter) {
+                if let Ok((device, context, device_flags)) =
+                    create_device(adapter, config.device_flags)
+                {
+                    let res = Capturer::from_device(
+                        display,
+                        adapter,
+                        capture_mouse,
+                        device,
+                        context,
+                        device_flags,
+                        config,
+                    );
+                    if res.is_ok() {
+                        info!("duplicating on adapter {}", i - 1);
+                        result = res;
+                    }
+                }
+            }
+
+            (*adapter).Release();
+        }
+
+        (*factory).Release();
+        result
+    }
+
+    /// Captures using an existing device and its immediate context.
+    ///
+    /// The device must belong to the display's adapter, or this fails with
+    /// `InvalidInput`. `config.device_flags` is ignored.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a valid `ID3D11Device`. The capturer takes its own
+    /// references to the device and context and releases only those on drop.
+    pub unsafe fn with_device(
+        display: &Display,
+        capture_mouse: bool,
+        device: *mut ID3D11Device,
+        config: Config,
+    ) -> io::Result<Capturer> {
+        if !is_on_adapter(device, display.adapter) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the device belongs to a different adapter than the display",
+            ));
+        }
+
+        let mut context = ptr::null_mut();
+        (*device).AddRef();
+        (*device).GetImmediateContext(&mut context);
+
+        let device_flags = DeviceFlags::from_bits((*device).GetCreationFlags());
+        Capturer::from_device(
+            display,
+            display.adapter,
+            capture_mouse,
+            device,
+            context,
+            device_flags,
+            &config,
+        )
+    }
+
+    /// Takes over one reference each to `device` and `context`,
+    /// which are released if this fails. `adapter` is the device's adapter.
+    unsafe fn from_device(
+        display: &Display,
+        adapter: *mut IDXGIAdapter1,
+        capture_mouse: bool,
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+        device_flags: DeviceFlags,
+        config: &Config,
+    ) -> io::Result<Capturer> {
+        if config.multithread_protected && !protect(context) {
+            (*device).Release();
+            (*context).Release();
+            return Err(io::Error::other("multithread protection is unavailable"));
+        }
+
+        let duplication = match duplicate(display.inner, device, config.hdr) {
+            Ok(duplication) => duplication,
+            Err(err) => {
                 (*device).Release();
                 (*context).Release();
+                return Err(err);
             }
-            return Err(err);
-        }
+        };
 
-        unsafe {
-            (*duplication).GetDesc(desc.assume_init_mut());
+        (*adapter).AddRef();
+        (*display.inner).AddRef();
+
+        let mut output_desc = mem::MaybeUninit::uninit();
+        (*display.inner).GetDesc(output_desc.as_mut_ptr());
+
+        Capturer::from_parts(
+            adapter,
+            display.inner,
+            device,
+            context,
+            duplication,
+            output_desc.assume_init(),
+            device_flags,
+            capture_mouse,
+            config,
+        )
+    }
+
+    /// Captures with a duplication someone else made, e.g. with
+    /// `DuplicateOutput1` for other formats. `desc` describes its output.
+    ///
+    /// Lost duplications can't be made again, since scrap doesn't know how
+    /// this one was made, so `frame` fails with `ConnectionReset` for good
+    /// once it's lost. `config.device_flags` is ignored.
+    ///
+    /// # Safety
+    ///
+    /// The interfaces must be valid, and the duplication must have been made
+    /// for `device`. The capturer takes its own reference to each and
+    /// releases only those on drop. Nothing else may acquire frames from the
+    /// duplication while the capturer exists.
+    pub unsafe fn from_raw(
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+        duplication: *mut IDXGIOutputDuplication,
+        desc: DXGI_OUTPUT_DESC,
+        capture_mouse: bool,
+        config: Config,
+    ) -> io::Result<Capturer> {
+        if config.multithread_protected && !protect(context) {
+            return Err(io::Error::other("multithread protection is unavailable"));
         }
 
-        Ok(unsafe {
-            let mut capturer = Capturer {
-                device,
-                context,
-                duplication,
-                fastlane: desc.assume_init_mut().DesktopImageInSystemMemory == TRUE,
-                surface: ptr::null_mut(),
-                height: display.height() as usize,
-                width: display.width() as usize,
-                data: ptr::null_mut(),
-                len: 0,
-                capture_mouse: capture_mouse,
-                cursor_info: CursorInfo {
-                    position: (0, 0),
-                    shape: Vec::new(),
-                    shape_info: mem::uninitialized(),
-                    visible: false,
-                    who_updated_position_last: 0,
-                    last_time_stamp: 0,
+        let adapter = match device_adapter(device) {
+            Some(adapter) => adapter,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the device has no DXGI adapter",
+                ))
+            }
+        };
+
+        (*device).AddRef();
+        (*context).AddRef();
+        (*duplication).AddRef();
+
+        let device_flags = DeviceFlags::from_bits((*device).GetCreationFlags());
+        Capturer::from_parts(
+            adapter,
+            ptr::null_mut(),
+            device,
+            context,
+            duplication,
+            desc,
+            device_flags,
+            capture_mouse,
+            &config,
+        )
+    }
+
+    /// Takes over one reference to each interface. `output` may be null
+    /// if the duplication can't be made again.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn from_parts(
+        adapter: *mut IDXGIAdapter1,
+        output: *mut IDXGIOutput1,
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+        duplication: *mut IDXGIOutputDuplication,
+        output_desc: DXGI_OUTPUT_DESC,
+        device_flags: DeviceFlags,
+        capture_mouse: bool,
+        config: &Config,
+    ) -> io::Result<Capturer> {
+        let mut desc = mem::MaybeUninit::uninit();
+        (*duplication).GetDesc(desc.as_mut_ptr());
+
+        let coords = output_desc.DesktopCoordinates;
+        let (width, height) = (
+            (coords.right - coords.left) as usize,
+            (coords.bottom - coords.top) as usize,
+        );
+
+        debug!(
+            "duplicating {}x{}: feature level {:#x}, flags {:#x}, system memory {}",
+            width,
+            height,
+            (*device).GetFeatureLevel(),
+            device_flags.bits(),
+            desc.assume_init_ref().DesktopImageInSystemMemory == TRUE
+        );
+
+        let mut capturer = Capturer {
+            adapter,
+            device,
+            device_flags,
+            feature_level: (*device).GetFeatureLevel(),
+            context,
+            duplication,
+            mapped: false,
+            staging: ptr::null_mut(),
+            staging_desc: None,
+            height,
+            width,
+            data: ptr::null_mut(),
+            len: 0,
+            pitch: 0,
+            cursor_mode: if capture_mouse {
+                CursorMode::Composite
+            } else {
+                CursorMode::None
+            },
+            cursor_info: CursorInfo::new(),
+            shared_cursor: None,
+            custom_cursor: None,
+            click_highlight: config.click_highlight,
+            last_click: None,
+            buttons_down: [false; 3],
+            // Only outputs sharing a cursor need telling apart.
+            output_number: 0,
+            output,
+            desc: output_desc,
+            metadata: Vec::new(),
+            dirty_rects: Vec::new(),
+            move_rects: Vec::new(),
+            watch_region: None,
+            want_nv12: false,
+            want_texture: false,
+            texture: ptr::null_mut(),
+            want_shared: false,
+            shared_sync: config.shared_sync,
+            shared: None,
+            shared_textures: 0,
+            gpu_loaded: false,
+            defer_cursor: false,
+            packed: Vec::new(),
+            frame_pool: FramePool::default(),
+            correct_rotation: false,
+            rotated: Vec::new(),
+            showing_rotated: false,
+            top_down: Vec::new(),
+            nv12: None,
+            scaler: None,
+            output_size: None,
+            frame_size: (0, 0),
+            activity: None,
+            accumulator: None,
+            diff: None,
+            tiles: None,
+            tiles_synced: false,
+            tiles_cursor: None,
+            tile_hints: Vec::new(),
+            frame_info: None,
+            cancel: config.cancel.clone(),
+            last_present_time: 0,
+            repeats: 0,
+            unknown_shape: config.unknown_shape,
+            cursor_blend: config.cursor_blend,
+            cursor_fallback: config.cursor_fallback,
+            hdr: config.hdr,
+            opaque_alpha: config.opaque_alpha,
+            flip_vertical: config.flip_vertical,
+            flip_horizontal: config.flip_horizontal,
+            color_space: output_color_space(output),
+            to_srgb: config.to_srgb,
+            srgb: Vec::new(),
+            lut: None,
+            adjusted_yuv: YuvBuffer::new(),
+            adjusted_nv12: Vec::new(),
+            watchdog: None,
+            stats: Counters::default(),
+            frame_number: 0,
+            dupl_desc: DuplicationDesc::from_raw(desc.assume_init_ref()),
+            mode_change: None,
+            resized: false,
+            max_buffer_memory: config.max_buffer_memory,
+        };
+
+        if !capturer.dupl_desc.in_system_memory {
+            // Fail now rather than on the first frame if we can't read frames back.
+            let mode = desc.assume_init_ref().ModeDesc;
+            capturer.create_staging(D3D11_TEXTURE2D_DESC {
+                Width: mode.Width,
+                Height: mode.Height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: mode.Format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
                 },
-                output_number: 0, // Initialize this properly
-                offset_x: 0,      // Initialize this properly
-                offset_y: 0,      // Initialize this properly
-                desc: display.desc.clone(),
-            };
-            let _ = capturer.load_frame(0);
-            capturer
-        })
+                Usage: D3D11_USAGE_STAGING,
+                BindFlags: 0,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+                MiscFlags: 0,
+            })?;
+        }
+
+        Ok(capturer)
     }
 
     unsafe fn load_frame(&mut self, timeout: UINT) -> io::Result<()> {
-        let mut frame = ptr::null_mut();
-        let mut info = mem::MaybeUninit::uninit();
+        let deadline = deadline_after(timeout);
+        let frame = self.acquire_frame(timeout)?;
+        self.map_frame(frame, deadline)
+    }
+
+    /// Acquires the next frame and reads everything about it but the image:
+    /// its info, the pointer and the dirty and move rects. The returned
+    /// resource is the caller's to release.
+    unsafe fn acquire_frame(&mut self, timeout: UINT) -> io::Result<*mut IDXGIResource> {
+        let mut frame: *mut IDXGIResource = ptr::null_mut();
+        let mut info: DXGI_OUTDUPL_FRAME_INFO = mem::zeroed();
         self.data = ptr::null_mut();
+        self.gpu_loaded = false;
 
-        wrap_hresult((*self.duplication).AcquireNextFrame(
-            timeout,
-            info.assume_init_mut(),
-            &mut frame,
-        ))?;
-
-        if self.capture_mouse {
-            let mouse_update_time = info
-                .assume_init_ref()
-                .LastMouseUpdateTime
-                .QuadPart()
-                .to_owned();
-            if mouse_update_time != 0 {
-                let update_position = if info.assume_init_mut().PointerPosition.Visible == 0
-                    && self.cursor_info.who_updated_position_last != self.output_number
-                {
-                    false
-                } else if info.assume_init_mut().PointerPosition.Visible != 0
-                    && self.cursor_info.visible
-                    && self.cursor_info.who_updated_position_last != self.output_number
-                    && self.cursor_info.last_time_stamp > mouse_update_time
-                {
-                    false
-                } else {
-                    true
-                };
+        let start = Instant::now();
+        let res = self.acquire(timeout, &mut info, &mut frame);
+        self.stats.acquire.add(start.elapsed());
+        res?;
 
-                // update cursor position
-                if update_position {
-                    self.cursor_info.position = (
-                        info.assume_init_mut().PointerPosition.Position.x
-                            + self.desc.DesktopCoordinates.left
-                            - self.offset_x,
-                        info.assume_init_mut().PointerPosition.Position.y
-                            + self.desc.DesktopCoordinates.top
-                            - self.offset_y,
-                    );
-                    self.cursor_info.who_updated_position_last = self.output_number;
-                    self.cursor_info.last_time_stamp = mouse_update_time;
-                    self.cursor_info.visible = info.assume_init_mut().PointerPosition.Visible != 0;
-                }
+        let frame_info = FrameInfo {
+            desktop: self.desktop_rect(),
+            rotation: self.desc.Rotation,
+            resized: mem::replace(&mut self.resized, false),
+            mode_change: self.mode_change.take(),
+            ..FrameInfo::from_raw(&info)
+        };
+        self.frame_info = Some(frame_info);
+        self.stats.count_accumulated(frame_info.accumulated_frames);
 
-                if info.assume_init_mut().PointerShapeBufferSize != 0 {
-                    if info.assume_init_mut().PointerShapeBufferSize
-                        > self.cursor_info.shape.len() as u32
-                    {
-                        self.cursor_info
-                            .shape
-                            .resize(info.assume_init_mut().PointerShapeBufferSize as usize, 0);
-                    }
-                    let mut shape_size = 0;
-                    wrap_hresult((*self.duplication).GetFramePointerShape(
-                        info.assume_init_mut().PointerShapeBufferSize,
-                        self.cursor_info.shape.as_mut_ptr() as *mut _,
-                        &mut shape_size,
-                        &mut self.cursor_info.shape_info,
-                    ))?;
-                }
+        // The image only changed if it was presented again since the last frame.
+        let present_time = frame_info.last_present_time;
+        if present_time == 0 || present_time == self.last_present_time {
+            self.repeats += 1;
+        } else {
+            self.last_present_time = present_time;
+            self.repeats = 0;
+        }
+        trace!("acquired a frame: {:?}", frame_info);
+
+        let res = self.update_pointer(&info).and_then(|()| {
+            self.poll_buttons();
+            self.load_metadata(info.TotalMetadataBufferSize)
+        });
+        if let Err(err) = res {
+            // The frame would otherwise stay acquired until the next call.
+            (*frame).Release();
+            (*self.duplication).ReleaseFrame();
+            return Err(err);
+        }
+        if let Some(ref mut activity) = self.activity {
+            activity.record(&self.dirty_rects);
+        }
+        Ok(frame)
+    }
+
+    /// Gets the acquired frame's image the way the capturer was asked for,
+    /// releasing `frame`.
+    unsafe fn map_frame(
+        &mut self,
+        frame: *mut IDXGIResource,
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        let present_time = self.frame_info.map_or(0, |info| info.last_present_time);
+        if let Some(region) = self.watch_region {
+            let touched = self
+                .dirty_rects
+                .iter()
+                .chain(self.move_rects.iter().map(|m| &m.destination))
+                .any(|r| r.intersection(&region).is_some());
+            if present_time == 0 || !touched {
+                // Leaving `data` null tells `frame_if_region_changed` to go again.
+                (*frame).Release();
+                return Ok(());
+            }
+        }
+
+        if self.want_texture {
+            if self.dupl_desc.in_system_memory {
+                (*frame).Release();
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the desktop image is in system memory, not a texture",
+                ));
             }
+            let res = (*frame).QueryInterface(
+                &IID_ID3D11TEXTURE2D,
+                &mut self.texture as *mut *mut _ as *mut *mut _,
+            );
+            (*frame).Release();
+            self.frame_size = self.framebuffer_size();
+            self.gpu_loaded = true;
+            return check("QueryInterface", res);
+        }
+
+        if self.want_shared {
+            let res = self.load_shared(frame, deadline);
+            (*frame).Release();
+            return res;
+        }
+
+        if self.want_nv12 || self.output_size.is_some() {
+            return self.load_converted(frame, deadline);
         }
+        self.frame_size = self.framebuffer_size();
 
-        if self.fastlane {
+        if self.dupl_desc.in_system_memory {
             let mut rect = mem::MaybeUninit::uninit();
-            let res = wrap_hresult((*self.duplication).MapDesktopSurface(rect.assume_init_mut()));
+            let start = Instant::now();
+            let res = check(
+                "MapDesktopSurface",
+                (*self.duplication).MapDesktopSurface(rect.as_mut_ptr()),
+            );
+            self.stats.map.add(start.elapsed());
 
             (*frame).Release();
 
             if let Err(err) = res {
                 Err(err)
             } else {
-                self.data = rect.assume_init_ref().pBits;
-                self.len = self.height * rect.assume_init_ref().Pitch as usize;
+                let rect = rect.assume_init_ref();
+                if rect.Pitch < 0 {
+                    return self.load_bottom_up(rect.pBits, rect.Pitch as isize);
+                }
+                self.data = rect.pBits;
+                self.pitch = rect.Pitch as usize;
+                self.len = self.frame_size.1 * self.pitch;
                 Ok(())
             }
         } else {
-            self.surface = ptr::null_mut();
-            self.surface = self.ohgodwhat(frame)?;
-
-            let mut rect = mem::MaybeUninit::uninit();
-            wrap_hresult((*self.surface).Map(rect.assume_init_mut(), DXGI_MAP_READ))?;
+            let start = Instant::now();
+            self.ohgodwhat(frame)?;
+            self.stats.copy.add(start.elapsed());
 
-            self.data = rect.assume_init_ref().pBits;
-            self.len = self.height * rect.assume_init_ref().Pitch as usize;
+            let start = Instant::now();
+            let mapped = self.map_staging(deadline);
+            self.stats.map.add(start.elapsed());
+            let mapped = mapped?;
+            self.data = mapped.pData as *mut u8;
+            self.pitch = mapped.RowPitch as usize;
+            self.len = self.frame_size.1 * self.pitch;
             Ok(())
         }
     }
 
-    unsafe fn ohgodwhat(&mut self, frame: *mut IDXGIResource) -> io::Result<*mut IDXGISurface> {
+    /// Waits in slices so that cancellation is noticed while blocked.
+    /// Making the duplication again counts against `timeout` too.
+    unsafe fn acquire(
+        &mut self,
+        timeout: UINT,
+        info: &mut DXGI_OUTDUPL_FRAME_INFO,
+        frame: &mut *mut IDXGIResource,
+    ) -> io::Result<()> {
+        let cancel = self.cancel.clone();
+        wait_in_slices(timeout, &cancel, |wait| {
+            if self.duplication.is_null() {
+                self.recreate()?;
+            }
+
+            let res = (*self.duplication).AcquireNextFrame(wait, info, frame);
+            if res == DXGI_ERROR_ACCESS_LOST {
+                // Mode changes, full-screen apps and the secure desktop all
+                // invalidate the duplication, so make a new one and go again.
+                (*self.duplication).Release();
+                self.duplication = ptr::null_mut();
+                Ok(false)
+            } else if res == DXGI_ERROR_WAIT_TIMEOUT {
+                Ok(false)
+            } else {
+                check("AcquireNextFrame", res).map(|()| true)
+            }
+        })
+    }
+
+    /// Copies the acquired image into the shared texture, making it first
+    /// if it doesn't fit.
+    unsafe fn load_shared(
+        &mut self,
+        frame: *mut IDXGIResource,
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        if self.dupl_desc.in_system_memory {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the desktop image is in system memory, not a texture",
+            ));
+        }
+
+        let mut texture: *mut ID3D11Texture2D = ptr::null_mut();
+        check(
+            "QueryInterface",
+            (*frame).QueryInterface(
+                &IID_ID3D11TEXTURE2D,
+                &mut texture as *mut *mut _ as *mut *mut _,
+            ),
+        )?;
+        let mut desc = mem::zeroed();
+        (*texture).GetDesc(&mut desc);
+
+        if !self.shared.as_ref().is_some_and(|s| s.fits(&desc)) {
+            self.shared = None;
+            self.shared_textures += 1;
+            match SharedTexture::new(
+                self.device,
+                self.context,
+                &desc,
+                self.shared_textures,
+                self.shared_sync,
+            ) {
+                Ok(shared) => self.shared = Some(shared),
+                Err(err) => {
+                    (*texture).Release();
+                    return Err(err);
+                }
+            }
+        }
+
+        let timeout = millis_left(deadline);
+        let start = Instant::now();
+        let res = self
+            .shared
+            .as_mut()
+            .unwrap()
+            .copy(self.context, texture, timeout);
+        self.stats.copy.add(start.elapsed());
+        (*texture).Release();
+        res?;
+
+        self.frame_size = self.framebuffer_size();
+        self.gpu_loaded = true;
+        Ok(())
+    }
+
+    /// Converts the acquired image to NV12 or scales it, or both, and maps
+    /// that instead.
+    unsafe fn load_converted(
+        &mut self,
+        frame: *mut IDXGIResource,
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        if let Err(err) = VideoConverter::check_feature_level(self.feature_level) {
+            (*frame).Release();
+            return Err(err);
+        }
+        if self.dupl_desc.in_system_memory {
+            (*frame).Release();
+            return Err(VideoProcessorUnsupported("the image is in system memory").into());
+        }
+
+        let mut texture: *mut ID3D11Texture2D = ptr::null_mut();
+        let res = (*frame).QueryInterface(
+            &IID_ID3D11TEXTURE2D,
+            &mut texture as *mut *mut _ as *mut *mut _,
+        );
+        (*frame).Release();
+        check("QueryInterface", res)?;
+
+        let mut desc = mem::MaybeUninit::uninit();
+        (*texture).GetDesc(desc.as_mut_ptr());
+        let desc = desc.assume_init();
+
+        let size = self.output_size.unwrap_or((desc.Width, desc.Height));
+        let (slot, format) = if self.want_nv12 {
+            (&mut self.nv12, DXGI_FORMAT_NV12)
+        } else {
+            (&mut self.scaler, desc.Format)
+        };
+        if !slot.as_ref().is_some_and(|c| c.fits(&desc, format, size)) {
+            *slot = None;
+            match VideoConverter::new(self.device, self.context, &desc, format, size) {
+                Ok(converter) => *slot = Some(converter),
+                Err(err) => {
+                    (*texture).Release();
+                    return Err(err);
+                }
+            }
+        }
+        let converter = slot.as_mut().unwrap();
+
+        let start = Instant::now();
+        let res = converter.convert(self.context, texture);
+        (*texture).Release();
+        self.stats.copy.add(start.elapsed());
+        res?;
+
+        let start = Instant::now();
+        let mapped = converter.map(self.context, deadline);
+        self.stats.map.add(start.elapsed());
+        let mapped = match mapped {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                if is_deadline_exceeded(&err) {
+                    self.stats.deadlines_exceeded += 1;
+                }
+                return Err(err);
+            }
+        };
+        self.data = mapped.pData as *mut u8;
+        self.pitch = mapped.RowPitch as usize;
+        self.frame_size = (size.0 as usize, size.1 as usize);
+        let height = size.1 as usize;
+        self.len = if self.want_nv12 {
+            // The UV plane follows the Y plane, both with even heights.
+            self.pitch * (height + (height & 1)) * 3 / 2
+        } else {
+            self.pitch * height
+        };
+        Ok(())
+    }
+
+    /// Copies a desktop surface mapped with a negative pitch, whose rows go
+    /// up in memory from `top`, so frames are always top-down with a
+    /// positive stride. Some drivers map it this way.
+    unsafe fn load_bottom_up(&mut self, top: *const u8, pitch: isize) -> io::Result<()> {
+        let (width, height) = self.frame_size;
+        let row = width * bytes_per_pixel(self.dupl_desc.mode.format);
+        self.reserve("top-down frame", self.top_down.capacity(), row * height)?;
+        frame::copy_bottom_up(top, pitch, row, height, &mut self.top_down);
+        self.data = self.top_down.as_mut_ptr();
+        self.pitch = row;
+        self.len = row * height;
+        Ok(())
+    }
+
+    /// Loads the frame's move and dirty rects, reusing one buffer for both.
+    unsafe fn load_metadata(&mut self, metadata_size: UINT) -> io::Result<()> {
+        self.move_rects.clear();
+        self.dirty_rects.clear();
+        if metadata_size == 0 {
+            return Ok(());
+        }
+
+        let len = self.fetch_metadata(
+            metadata_size,
+            "GetFrameMoveRects",
+            |d, len, buf, required| (*d).GetFrameMoveRects(len, buf as *mut _, required),
+        )?;
+        let moves = slice::from_raw_parts(
+            self.metadata.as_ptr() as *const DXGI_OUTDUPL_MOVE_RECT,
+            len / mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>(),
+        );
+        self.move_rects.extend(moves.iter().map(|m| {
+            let r = m.DestinationRect;
+            MoveRect {
+                source: (m.SourcePoint.x, m.SourcePoint.y),
+                destination: Rect::from_edges(r.left, r.top, r.right, r.bottom),
+            }
+        }));
+
+        let len = self.fetch_metadata(
+            metadata_size,
+            "GetFrameDirtyRects",
+            |d, len, buf, required| (*d).GetFrameDirtyRects(len, buf as *mut _, required),
+        )?;
+        let dirty = slice::from_raw_parts(
+            self.metadata.as_ptr() as *const RECT,
+            len / mem::size_of::<RECT>(),
+        );
+        self.dirty_rects.extend(
+            dirty
+                .iter()
+                .map(|r| Rect::from_edges(r.left, r.top, r.right, r.bottom)),
+        );
+        Ok(())
+    }
+
+    /// Calls `get` until `metadata` is big enough, and returns how many
+    /// bytes it wrote.
+    unsafe fn fetch_metadata<F>(
+        &mut self,
+        metadata_size: UINT,
+        stage: &'static str,
+        get: F,
+    ) -> io::Result<usize>
+    where
+        F: Fn(*mut IDXGIOutputDuplication, UINT, *mut u32, &mut UINT) -> HRESULT,
+    {
+        // The metadata size covers both kinds, so it's only a first guess.
+        let mut required = metadata_size;
+        loop {
+            let words = (required as usize).div_ceil(4);
+            if self.metadata.len() < words {
+                self.metadata.resize(words, 0);
+            }
+
+            let len = (self.metadata.len() * 4) as UINT;
+            let res = get(
+                self.duplication,
+                len,
+                self.metadata.as_mut_ptr(),
+                &mut required,
+            );
+            if res != DXGI_ERROR_MORE_DATA {
+                check(stage, res)?;
+                return Ok(required as usize);
+            }
+        }
+    }
+
+    unsafe fn ohgodwhat(&mut self, frame: *mut IDXGIResource) -> io::Result<()> {
         let mut texture: *mut ID3D11Texture2D = ptr::null_mut();
         (*frame).QueryInterface(
             &IID_ID3D11TEXTURE2D,
@@ -230,211 +1098,1860 @@ impl Capturer {
         );
 
         let mut texture_desc = mem::MaybeUninit::uninit();
-        (*texture).GetDesc(texture_desc.assume_init_mut());
-
-        texture_desc.assume_init_mut().Usage = D3D11_USAGE_STAGING;
-        texture_desc.assume_init_mut().BindFlags = 0;
-        texture_desc.assume_init_mut().CPUAccessFlags = D3D11_CPU_ACCESS_READ;
-        texture_desc.assume_init_mut().MiscFlags = 0;
-
-        let mut readable = ptr::null_mut();
-        let res = wrap_hresult((*self.device).CreateTexture2D(
-            texture_desc.assume_init_mut(),
-            ptr::null(),
-            &mut readable,
-        ));
+        (*texture).GetDesc(texture_desc.as_mut_ptr());
+        let texture_desc = texture_desc.assume_init();
 
-        if let Err(err) = res {
-            (*frame).Release();
-            (*texture).Release();
-            (*readable).Release();
-            Err(err)
+        let fits = self.staging_desc.as_ref().is_some_and(|desc| {
+            desc.Width == texture_desc.Width
+                && desc.Height == texture_desc.Height
+                && desc.Format == texture_desc.Format
+        });
+
+        if !fits {
+            if let Err(err) = self.create_staging(texture_desc) {
+                (*frame).Release();
+                (*texture).Release();
+                return Err(err);
+            }
+        }
+
+        (*self.context).CopyResource(
+            self.staging as *mut ID3D11Resource,
+            texture as *mut ID3D11Resource,
+        );
+
+        (*frame).Release();
+        (*texture).Release();
+        Ok(())
+    }
+
+    /// Maps the staging texture, giving up on the copy at `deadline`.
+    unsafe fn map_staging(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> io::Result<D3D11_MAPPED_SUBRESOURCE> {
+        let flags = if deadline.is_some() {
+            D3D11_MAP_FLAG_DO_NOT_WAIT
         } else {
-            (*readable).SetEvictionPriority(DXGI_RESOURCE_PRIORITY_MAXIMUM);
+            0
+        };
 
-            let mut surface = ptr::null_mut();
-            (*readable).QueryInterface(
-                &IID_IDXGISURFACE,
-                &mut surface as *mut *mut _ as *mut *mut _,
+        let mut mapped = mem::zeroed();
+        loop {
+            let res = (*self.context).Map(
+                self.staging as *mut ID3D11Resource,
+                0,
+                D3D11_MAP_READ,
+                flags,
+                &mut mapped,
             );
 
-            (*self.context).CopyResource(
-                readable as *mut ID3D11Resource,
-                texture as *mut ID3D11Resource,
-            );
+            if res != DXGI_ERROR_WAS_STILL_DRAWING {
+                check("Map", res)?;
+                self.mapped = true;
+                return Ok(mapped);
+            }
 
-            (*frame).Release();
-            (*texture).Release();
-            (*readable).Release();
-            Ok(surface)
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.stats.deadlines_exceeded += 1;
+                return Err(DeadlineExceeded.into());
+            }
+            thread::yield_now();
         }
     }
 
-    pub fn frame<'a>(&'a mut self, timeout: UINT) -> io::Result<&'a [u8]> {
-        unsafe {
-            if self.fastlane {
-                (*self.duplication).UnMapDesktopSurface();
-            } else {
-                if !self.surface.is_null() {
-                    (*self.surface).Unmap();
-                    (*self.surface).Release();
-                    self.surface = ptr::null_mut();
-                }
-            }
+    /// How many `frame` calls failed with `DeadlineExceeded`.
+    pub fn deadlines_exceeded(&self) -> u64 {
+        self.stats.deadlines_exceeded
+    }
 
-            (*self.duplication).ReleaseFrame();
+    /// Replaces the staging texture with one matching a desktop image.
+    unsafe fn create_staging(&mut self, mut desc: D3D11_TEXTURE2D_DESC) -> io::Result<()> {
+        desc.Usage = D3D11_USAGE_STAGING;
+        desc.BindFlags = 0;
+        desc.CPUAccessFlags = D3D11_CPU_ACCESS_READ;
+        desc.MiscFlags = 0;
 
-            self.load_frame(timeout)?;
-            let frame = slice::from_raw_parts_mut(self.data, self.len);
+        if !self.staging.is_null() {
+            (*self.staging).Release();
+            self.staging = ptr::null_mut();
+            self.staging_desc = None;
+        }
 
-            if self.capture_mouse && self.cursor_info.visible {
-                self.draw_cursor(frame);
-            }
-            Ok(slice::from_raw_parts(self.data, self.len))
+        let mut staging = ptr::null_mut();
+        let res = (*self.device).CreateTexture2D(&desc, ptr::null(), &mut staging);
+        if res == E_OUTOFMEMORY {
+            warn!("CreateTexture2D failed: {:#010x}", res);
+            return Err(self.out_of_memory());
         }
+        check("CreateTexture2D", res)?;
+
+        (*staging).SetEvictionPriority(DXGI_RESOURCE_PRIORITY_MAXIMUM);
+        self.staging = staging;
+        self.staging_desc = Some(desc);
+        Ok(())
     }
 
-    fn draw_cursor(&self, frame: &mut [u8]) {
-        let (cursor_x, cursor_y) = self.cursor_info.position;
-        let bytes_per_pixel = 4; // Assuming BGRA format
-        let cursor_width = self.cursor_info.shape_info.Width as i32;
-        let cursor_height = self.cursor_info.shape_info.Height as i32;
-        let cursor_pitch = self.cursor_info.shape_info.Pitch as usize;
-        let cursor_type = self.cursor_info.shape_info.Type;
-        let frame_width = self.width as i32;
-        let frame_height = self.height as i32;
-        let shape_len = self.cursor_info.shape.len();
+    /// Duplicates the output again, picking up its new mode.
+    unsafe fn recreate(&mut self) -> io::Result<()> {
+        if self.output.is_null() {
+            // From `from_raw`, so it's gone for good.
+            return wrap_hresult(DXGI_ERROR_ACCESS_LOST);
+        }
+
+        let duplication = duplicate(self.output, self.device, self.hdr)?;
+        self.duplication = duplication;
+        self.stats.count_recreation();
+
+        let mut desc = mem::MaybeUninit::uninit();
+        (*duplication).GetDesc(desc.as_mut_ptr());
+        (*self.output).GetDesc(&mut self.desc);
+        self.color_space = output_color_space(self.output);
+
+        let dupl_desc = DuplicationDesc::from_raw(desc.assume_init_ref());
+        let desktop = display_config::desktop_mode(&self.desc.DeviceName);
+        if let Some(change) = ModeChange::new(self.dupl_desc.mode, dupl_desc.mode, desktop) {
+            info!("mode changed: {:?}", change);
+            self.mode_change = Some(change);
+        }
+        self.dupl_desc = dupl_desc;
 
-        let (hot_x, hot_y) = (
-            self.cursor_info.shape_info.HotSpot.x as i32,
-            self.cursor_info.shape_info.HotSpot.y as i32,
+        let resize = take_size(
+            self.desc.DesktopCoordinates,
+            (&mut self.width, &mut self.height),
+            &mut self.resized,
+            &mut self.activity,
         );
+        if let Some((width, height)) = resize {
+            info!(
+                "output resized from {}x{} to {}x{}",
+                width, height, self.width, self.height
+            );
+        } else {
+            debug!("duplicating the output again");
+        }
+        Ok(())
+    }
 
-        for y in 0..cursor_height {
-            for x in 0..cursor_width {
-                let frame_x = cursor_x + x - hot_x;
-                let frame_y = cursor_y + y - hot_y;
-
-                if frame_x >= 0 && frame_y >= 0 && frame_x < frame_width && frame_y < frame_height {
-                    let frame_index =
-                        (frame_y as usize * self.width + frame_x as usize) * bytes_per_pixel;
-                    if frame_index + 3 < frame.len() {
-                        let cursor_index = y as usize * cursor_pitch + x as usize * 4; // 4 bytes per pixel for color cursors
-
-                        if cursor_index + 3 < shape_len {
-                            match cursor_type {
-                                DXGI_OUTDUPL_POINTER_SHAPE_TYPE_COLOR => {
-                                    self.draw_color_cursor(frame, frame_index, cursor_index);
-                                }
-                                DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MONOCHROME => {
-                                    self.draw_monochrome_cursor(
-                                        frame,
-                                        frame_index,
-                                        cursor_index,
-                                        x,
-                                    );
-                                }
-                                DXGI_OUTDUPL_POINTER_SHAPE_TYPE_MASKED_COLOR => {
-                                    self.draw_masked_color_cursor(frame, frame_index, cursor_index);
-                                }
-                                _ => {} // Unknown cursor type
-                            }
-                        }
-                    }
-                }
+    /// The mode frames are currently delivered in.
+    pub fn mode(&self) -> Mode {
+        self.dupl_desc.mode
+    }
+
+    /// What the duplication delivers, which changes with the display mode.
+    /// Its size is the frames', which can differ from the display's.
+    pub fn dupl_desc(&self) -> DuplicationDesc {
+        self.dupl_desc
+    }
+
+    /// How many times the duplication was lost and made again.
+    pub fn recreations(&self) -> u64 {
+        self.stats.recreations
+    }
+
+    /// The width of the frames, which can change when the display mode does.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// How the frames' pixels are laid out. FP16 only with `Config::hdr`.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.frame_format().into()
+    }
+
+    /// The color space of the frames, as opposed to the display's. An HDR
+    /// display gives scRGB frames with `Config::hdr`, unless they're
+    /// converted by `Config::to_srgb`, and sRGB ones without. `Unknown`
+    /// where the display's can't be asked.
+    pub fn color_space(&self) -> ColorSpace {
+        match self.color_space {
+            ColorSpace::Unknown => ColorSpace::Unknown,
+            _ if self.dupl_desc.mode.format == DXGI_FORMAT_R16G16B16A16_FLOAT && !self.to_srgb => {
+                ColorSpace::ScRgb
             }
+            _ => ColorSpace::Srgb,
         }
     }
 
-    fn draw_color_cursor(&self, frame: &mut [u8], frame_index: usize, cursor_index: usize) {
-        if cursor_index + 3 < self.cursor_info.shape.len() {
-            let alpha = self.cursor_info.shape[cursor_index + 3] as u16;
-            if alpha > 0 {
-                for i in 0..3 {
-                    if frame_index + i < frame.len()
-                        && cursor_index + i < self.cursor_info.shape.len()
-                    {
-                        let cursor_color = self.cursor_info.shape[cursor_index + i] as u16;
-                        let frame_color = frame[frame_index + i] as u16;
-                        frame[frame_index + i] =
-                            ((alpha * cursor_color + (255 - alpha) * frame_color) / 255) as u8;
-                    }
+    /// Whether frames are converted by `Config::to_srgb`.
+    fn converting_to_srgb(&self) -> bool {
+        self.to_srgb
+            && self.color_space != ColorSpace::Unknown
+            && self.dupl_desc.mode.format == DXGI_FORMAT_R16G16B16A16_FLOAT
+    }
+
+    /// The format of the frames, after any conversion.
+    fn frame_format(&self) -> DXGI_FORMAT {
+        if self.converting_to_srgb() {
+            DXGI_FORMAT_B8G8R8A8_UNORM
+        } else {
+            self.dupl_desc.mode.format
+        }
+    }
+
+    /// How many bytes each pixel of the frames takes, from `pixel_format`.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.pixel_format().bytes_per_pixel()
+    }
+
+    /// Scales frames to `width` by `height` on the GPU before they're read
+    /// back, or stops with `None`. `width()` and `height()` still report the
+    /// display's size; the frames report their own. On a sideways display
+    /// the size is of the frames DXGI gives, before any rotation correction.
+    ///
+    /// Scaling uses the D3D11 video processor, which filters at least
+    /// bilinearly. Without one, or below feature level 10_0, frames fail
+    /// with `VideoProcessorUnsupported`.
+    pub fn set_output_size(&mut self, size: Option<(u32, u32)>) {
+        if let Some((width, height)) = size {
+            assert!(width > 0 && height > 0, "output size must be positive");
+        }
+        self.output_size = size;
+    }
+
+    /// Applies `adjustment` whenever frames are converted, by `frame_rgba`,
+    /// `frame_yuv`, `frame_sdr`, `frame_nv12` and the like. Frames from
+    /// `frame`, `frame_into`, `frame_packed` and `frame_owned` are left
+    /// alone, except that with `Config::to_srgb` HDR frames are adjusted as
+    /// they're converted to sRGB.
+    pub fn set_adjustment(&mut self, adjustment: Adjustment) {
+        self.lut = adjustment.lut();
+    }
+
+    /// The table the conversions should apply to the loaded frame, unless
+    /// `convert_to_srgb` already did.
+    fn frame_lut(&self) -> Option<&Lut> {
+        if self.converting_to_srgb() {
+            None
+        } else {
+            self.lut.as_ref()
+        }
+    }
+
+    /// How far the display is turned from the frames DXGI gives.
+    pub fn rotation(&self) -> Rotation {
+        self.dupl_desc.rotation
+    }
+
+    /// Turns frames on the CPU so they're upright as the display shows
+    /// them, matching `width()` and `height()`, or leaves them as DXGI gives
+    /// them. On a display turned by 90 or 270 degrees, those are sideways.
+    ///
+    /// Doesn't apply to `frame_nv12`.
+    pub fn set_correct_rotation(&mut self, correct: bool) {
+        self.correct_rotation = correct;
+    }
+
+    /// The distance between rows of the last frame, in bytes, which may be
+    /// more than `width() * 4`. A frame is `pitch() * height()` bytes long.
+    /// Meaningless after a `frame` call fails.
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    /// Explains a failed staging allocation in terms of the adapter's budget.
+    fn out_of_memory(&self) -> io::Error {
+        let message = match unsafe { query_memory_info(self.adapter) } {
+            Some(info) => format!(
+                "out of video memory for the staging texture \
+                 ({} MiB of the {} MiB local budget in use)",
+                info.local.current_usage >> 20,
+                info.local.budget >> 20,
+            ),
+            None => "out of video memory for the staging texture".to_owned(),
+        };
+        io::Error::other(message)
+    }
+
+    /// The flags the device was actually created with.
+    pub fn device_flags(&self) -> DeviceFlags {
+        self.device_flags
+    }
+
+    /// The feature level the device supports. Below 10_0, `frame_nv12`
+    /// and `set_output_size` aren't available.
+    pub fn feature_level(&self) -> D3D_FEATURE_LEVEL {
+        self.feature_level
+    }
+
+    /// The adapter hosting the capture. On hybrid graphics systems, this
+    /// may not be the one the display is connected to.
+    pub fn adapter(&self) -> Adapter {
+        unsafe {
+            (*self.adapter).AddRef();
+        }
+        Adapter(self.adapter)
+    }
+
+    /// The video memory budget of the adapter hosting the capture.
+    pub fn memory_info(&self) -> Option<MemoryInfo> {
+        unsafe { query_memory_info(self.adapter) }
+    }
+
+    /// The token that interrupts this capturer's waits, from `Config::cancel`.
+    pub fn cancel_handle(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Waits up to `timeout` milliseconds, or forever if it's `INFINITE`,
+    /// for the screen to change.
+    ///
+    /// The timeout covers the whole call. If it runs out after a frame was
+    /// acquired, e.g. while the GPU is still copying, this fails with
+    /// `DeadlineExceeded`.
+    ///
+    /// Cancellation is noticed within `WAIT_SLICE`, plus the time to copy
+    /// out a frame that was already acquired.
+    pub fn frame<'a>(&'a mut self, timeout: UINT) -> io::Result<Frame<'a>> {
+        self.observed_capture(timeout)?;
+        Ok(self.current_frame())
+    }
+
+    /// Like `frame`, but writes the frame to `out` as tightly packed RGBA,
+    /// reusing its capacity. Alpha is always opaque. The cursor is included
+    /// if it's being captured.
+    pub fn frame_rgba(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        self.reserve("RGBA conversion", out.capacity(), width * height * 4)?;
+        convert::bgra_to_rgba_opaque(&frame, stride, width, height, lut, out);
+        Ok(())
+    }
+
+    /// Like `frame`, but writes the frame to `out` as tightly packed 24-bit
+    /// RGB, reusing its capacity. The cursor is included if it's being
+    /// captured.
+    pub fn frame_rgb24(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        self.reserve("RGB conversion", out.capacity(), width * height * 3)?;
+        convert::bgra_to_rgb24(&frame, stride, width, height, lut, out);
+        Ok(())
+    }
+
+    /// Like `frame_rgb24`, but with blue first.
+    pub fn frame_bgr24(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        self.reserve("BGR conversion", out.capacity(), width * height * 3)?;
+        convert::bgra_to_bgr24(&frame, stride, width, height, lut, out);
+        Ok(())
+    }
+
+    /// Like `frame`, but writes the frame to `out` as tightly packed
+    /// little-endian RGB565, reusing its capacity. The cursor is included if
+    /// it's being captured.
+    pub fn frame_rgb565(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        self.reserve("RGB565 conversion", out.capacity(), width * height * 2)?;
+        convert::bgra_to_rgb565(&frame, stride, width, height, lut, out);
+        Ok(())
+    }
+
+    /// Like `frame`, but writes the frame to `out` as tightly packed 8-bit
+    /// BGRA, tone mapping HDR frames for the display's SDR white level.
+    /// With `Config::hdr`, the cursor isn't drawn on HDR frames.
+    pub fn frame_sdr(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        let params = self.tonemap_params();
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        self.reserve("SDR conversion", out.capacity(), width * height * 4)?;
+        match frame.format() {
+            DXGI_FORMAT_R16G16B16A16_FLOAT => {
+                convert::to_sdr(&frame, stride, width, height, params, lut, out)
+            }
+            _ => {
+                check_bgra(&frame)?;
+                convert::decimate(&frame, stride, width, height, 1, lut, out);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tone mapping that keeps the display's SDR white at full brightness.
+    pub fn tonemap_params(&self) -> TonemapParams {
+        tonemap_params(&self.desc.DeviceName)
+    }
+
+    /// Like `frame`, but copies the frame into `dst` without row padding,
+    /// e.g. into a buffer shared with an encoder. The cursor is drawn on the
+    /// copy, leaving the mapped frame alone.
+    ///
+    /// Fails with `InvalidInput` if `dst` is too small, after the frame was
+    /// taken.
+    pub fn frame_into(&mut self, timeout: UINT, dst: &mut [u8]) -> io::Result<FrameLayout> {
+        self.capture_for_copy(timeout)?;
+        self.copy_packed(dst)
+    }
+
+    /// Like `frame`, but the frame is copied into a buffer the capturer
+    /// keeps, so its rows are never padded: the stride is always the width
+    /// times the bytes per pixel. The cursor is drawn on the copy.
+    pub fn frame_packed(&mut self, timeout: UINT) -> io::Result<Frame<'_>> {
+        self.capture_for_copy(timeout)?;
+        self.reserve("packed frame", self.packed.capacity(), self.packed_len())?;
+
+        let mut packed = mem::take(&mut self.packed);
+        packed.resize(self.packed_len(), 0);
+        let layout = self.copy_packed(&mut packed);
+        self.packed = packed;
+
+        let layout = layout?;
+        Ok(Frame::new(
+            &self.packed,
+            layout.width,
+            layout.height,
+            layout.stride,
+            layout.format,
+        ))
+    }
+
+    /// Like `frame_packed`, but the copy is owned, so it can outlive the
+    /// next call or go to another thread. Buffers of dropped frames are
+    /// reused.
+    pub fn frame_owned(&mut self, timeout: UINT) -> io::Result<OwnedFrame> {
+        self.capture_for_copy(timeout)?;
+        // A spare buffer may be reused, but counts until it is.
+        let len = self.packed_len();
+        self.reserve("owned frame", 0, len)?;
+
+        let mut data = self.frame_pool.take(len);
+        let layout = self.copy_packed(&mut data)?;
+        Ok(OwnedFrame::new(
+            data,
+            layout,
+            self.frame_number,
+            self.frame_pool.clone(),
+        ))
+    }
+
+    /// Frames from `frame_owned` as an iterator, each waiting up to `timeout`
+    /// milliseconds. See `Frames` for what it does with errors and how to
+    /// bound it.
+    pub fn frames(&mut self, timeout: UINT) -> Frames<'_> {
+        Frames::new(self, timeout)
+    }
+
+    /// Loads a frame to be copied, leaving the cursor for the copy.
+    fn capture_for_copy(&mut self, timeout: UINT) -> io::Result<()> {
+        self.defer_cursor = true;
+        let result = self.observed_capture(timeout);
+        self.defer_cursor = false;
+        result
+    }
+
+    /// The size of the loaded frame without padding.
+    fn packed_len(&self) -> usize {
+        let (width, height) = self.frame_size;
+        width * height * self.bytes_per_pixel()
+    }
+
+    /// Copies the loaded frame into `dst` without padding, and draws the
+    /// cursor on it.
+    fn copy_packed(&self, dst: &mut [u8]) -> io::Result<FrameLayout> {
+        let frame = self.current_frame();
+        let row = frame.width() * bytes_per_pixel(frame.format());
+        let len = row * frame.height();
+        if dst.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the frame needs {} bytes, not {}", len, dst.len()),
+            ));
+        }
+        let opaque = if self.opaque_alpha {
+            Some(self.pixel_format())
+        } else {
+            None
+        };
+        frame::copy_rows(
+            &frame,
+            dst,
+            (self.flip_horizontal, self.flip_vertical),
+            opaque,
+        );
+        let layout = FrameLayout {
+            width: frame.width(),
+            height: frame.height(),
+            stride: row,
+            format: frame.format(),
+        };
+
+        if self.should_draw_cursor() {
+            let flip = (self.flip_horizontal, self.flip_vertical);
+            self.draw_cursor_scaled(&mut dst[..len], row, self.frame_rotation(), flip);
+        }
+        Ok(layout)
+    }
+
+    /// Captures a frame like `frame` and saves it to `path`. Errors writing
+    /// the file are `snapshot::SaveError`s; anything else came from capture.
+    pub fn save_frame<P: AsRef<Path>>(
+        &mut self,
+        timeout: UINT,
+        path: P,
+        format: ImageFormat,
+    ) -> io::Result<()> {
+        let frame = self.frame(timeout)?;
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        snapshot::save(path.as_ref(), format, &frame, stride, width, height)
+    }
+
+    /// Captures a frame like `frame` and puts it on the clipboard as a
+    /// 24-bit DIB, for pasting into other programs. Fails with a
+    /// `ClipboardBusy` error if another program has the clipboard open.
+    pub fn copy_frame_to_clipboard(&mut self, timeout: UINT) -> io::Result<()> {
+        let frame = self.frame(timeout)?;
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        clipboard::copy_dib(&frame, stride, width, height)
+    }
+
+    /// Captures a frame like `frame` and encodes it as a JPEG, with
+    /// `quality` clamped to 1 to 100.
+    #[cfg(feature = "jpeg")]
+    pub fn frame_jpeg(&mut self, timeout: UINT, quality: u8) -> io::Result<Vec<u8>> {
+        let frame = self.frame(timeout)?;
+        check_bgra(&frame)?;
+        let (stride, width, height) = (frame.stride(), frame.width(), frame.height());
+        snapshot::encode_jpeg(&frame, stride, width, height, quality)
+    }
+
+    /// Like `frame`, but writes just the frame's BT.601 luma to `out`, one
+    /// byte per pixel without padding, reusing its capacity.
+    pub fn frame_luma(&mut self, timeout: UINT, out: &mut Vec<u8>) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let len = frame.width() * frame.height();
+        self.reserve("luma conversion", out.capacity(), len)?;
+        convert::decimate_luma(
+            &frame,
+            frame.stride(),
+            frame.width(),
+            frame.height(),
+            1,
+            lut,
+            out,
+        );
+        Ok(())
+    }
+
+    /// Like `frame`, but converts the frame to I420 in `dst`, for video
+    /// encoders. The cursor is included if it's being captured.
+    pub fn frame_yuv(
+        &mut self,
+        timeout: UINT,
+        matrix: YuvMatrix,
+        dst: &mut YuvBuffer,
+    ) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let len = i420_len(frame.width(), frame.height());
+        self.reserve("I420 conversion", dst.memory_usage(), len)?;
+        convert::bgra_to_i420(
+            &frame,
+            frame.stride(),
+            frame.width(),
+            frame.height(),
+            matrix,
+            lut,
+            dst,
+        );
+        Ok(())
+    }
+
+    /// Like `frame`, but converts the frame to YUV 4:4:4 in `dst`, which
+    /// keeps sharp colored edges like text intact. The cursor is included if
+    /// it's being captured.
+    pub fn frame_yuv444(
+        &mut self,
+        timeout: UINT,
+        matrix: YuvMatrix,
+        dst: &mut Yuv444Buffer,
+    ) -> io::Result<()> {
+        self.observed_capture(timeout)?;
+        let (frame, lut) = (self.current_frame(), self.frame_lut());
+        check_bgra(&frame)?;
+        let len = frame.width() * frame.height() * 3;
+        self.reserve("YUV 4:4:4 conversion", dst.memory_usage(), len)?;
+        convert::bgra_to_yuv444(
+            &frame,
+            frame.stride(),
+            frame.width(),
+            frame.height(),
+            matrix,
+            lut,
+            dst,
+        );
+        Ok(())
+    }
+
+    /// Like `frame`, but converts the frame to NV12 on the GPU and maps that
+    /// instead, which is much cheaper than converting on the CPU.
+    ///
+    /// Follows `set_output_size`. The cursor isn't drawn, and frames aren't
+    /// accumulated. Fails with
+    /// `VideoProcessorUnsupported` if the device can't do the conversion,
+    /// which is likelier without `DeviceFlags::VIDEO_SUPPORT`, or is below
+    /// feature level 10_0.
+    ///
+    /// With an adjustment from `set_adjustment`, the GPU can't apply it, so
+    /// this converts on the CPU like `frame_yuv` with BT.601, and the cursor
+    /// is drawn if it's being captured.
+    pub fn frame_nv12(&mut self, timeout: UINT) -> io::Result<Nv12Frame<'_>> {
+        if self.lut.is_some() {
+            return self.frame_nv12_adjusted(timeout);
+        }
+        self.want_nv12 = true;
+        let result = self.observed_capture(timeout);
+        self.want_nv12 = false;
+        result?;
+
+        let data = unsafe { slice::from_raw_parts(self.data, self.len) };
+        let (width, height) = self.frame_size;
+        Ok(Nv12Frame::new(data, width, height, self.pitch))
+    }
+
+    /// `frame_nv12` on the CPU, so the adjustment applies.
+    fn frame_nv12_adjusted(&mut self, timeout: UINT) -> io::Result<Nv12Frame<'_>> {
+        self.observed_capture(timeout)?;
+        let (width, height) = {
+            let frame = self.current_frame();
+            check_bgra(&frame)?;
+            (frame.width(), frame.height())
+        };
+        let held = self.adjusted_yuv.memory_usage() + self.adjusted_nv12.capacity();
+        let needed = i420_len(width, height) + nv12_len(width, height);
+        self.reserve("NV12 conversion", held, needed)?;
+
+        let mut yuv = mem::take(&mut self.adjusted_yuv);
+        let frame = self.current_frame();
+        convert::bgra_to_i420(
+            &frame,
+            frame.stride(),
+            width,
+            height,
+            YuvMatrix::Bt601,
+            self.frame_lut(),
+            &mut yuv,
+        );
+        let pitch = i420_to_nv12(&yuv, &mut self.adjusted_nv12);
+        self.adjusted_yuv = yuv;
+        Ok(Nv12Frame::new(&self.adjusted_nv12, width, height, pitch))
+    }
+
+    /// Like `frame`, but hands over the acquired texture without copying it,
+    /// e.g. for a hardware encoder on `d3d11_device`. Ignores
+    /// `set_output_size` and rotation correction, and the cursor isn't
+    /// drawn.
+    ///
+    /// Fails with `Unsupported` if DXGI keeps the desktop image in system
+    /// memory, where there's no texture to give.
+    pub fn frame_texture(&mut self, timeout: UINT) -> io::Result<FrameTexture<'_>> {
+        self.want_texture = true;
+        let result = self.observed_capture(timeout);
+        self.want_texture = false;
+        result?;
+
+        let texture = mem::replace(&mut self.texture, ptr::null_mut());
+        Ok(unsafe { FrameTexture::new(texture, self.duplication) })
+    }
+
+    /// Like `frame`, but copies the frame into a texture that other
+    /// processes can open, and returns a new handle to it. See
+    /// `SharedFrameHandle` for how to read it. The copy stays on the GPU,
+    /// and the cursor isn't drawn.
+    ///
+    /// The copy waits, within `timeout`, for the consumer to release the
+    /// last frame, and fails with `TimedOut` if it doesn't.
+    pub fn frame_shared_handle(&mut self, timeout: UINT) -> io::Result<SharedFrameHandle> {
+        self.want_shared = true;
+        let result = self.observed_capture(timeout);
+        self.want_shared = false;
+        result?;
+
+        unsafe { SharedFrameHandle::new(self.shared.as_ref().unwrap()) }
+    }
+
+    /// The device frames are captured on. Borrowed, so `AddRef` it to keep it
+    /// past the capturer.
+    pub fn d3d11_device(&self) -> *mut ID3D11Device {
+        self.device
+    }
+
+    /// The frame that was just loaded.
+    fn current_frame(&self) -> Frame<'_> {
+        let (width, height) = self.frame_size;
+        if self.showing_rotated {
+            let (width, height) = if self.rotation().is_sideways() {
+                (height, width)
+            } else {
+                (width, height)
+            };
+            let stride = width * self.bytes_per_pixel();
+            return Frame::new(&self.rotated, width, height, stride, self.frame_format());
+        }
+        let data = unsafe { slice::from_raw_parts(self.data, self.len) };
+        Frame::new(data, width, height, self.pitch, self.frame_format())
+    }
+
+    /// How `current_frame` is turned from the way the display shows it.
+    fn frame_rotation(&self) -> Rotation {
+        if self.showing_rotated {
+            Rotation::Identity
+        } else {
+            self.rotation()
+        }
+    }
+
+    /// The size of the frames DXGI gives, before scaling, which is
+    /// sideways if the display is.
+    fn framebuffer_size(&self) -> (usize, usize) {
+        let mode = self.dupl_desc.mode;
+        (mode.width as usize, mode.height as usize)
+    }
+
+    /// Waits up to `timeout` milliseconds in total for a frame that changes
+    /// `region`, releasing the others without copying them.
+    ///
+    /// `region` is in frame coordinates, before any `set_output_size`
+    /// scaling, and is clamped to the frame. Only changes to the desktop
+    /// image count, not the cursor moving.
+    pub fn frame_if_region_changed(
+        &mut self,
+        region: Rect,
+        timeout: UINT,
+    ) -> io::Result<Frame<'_>> {
+        let bounds = Rect::new(0, 0, self.width as u32, self.height as u32);
+        let region = region.intersection(&bounds).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the region is outside the frame",
+            )
+        })?;
+
+        let deadline = deadline_after(timeout);
+
+        self.watch_region = Some(region);
+        let result = loop {
+            let remaining = millis_left(deadline);
+            if remaining == 0 {
+                break wrap_hresult(DXGI_ERROR_WAIT_TIMEOUT);
+            }
+            match self.observed_capture(remaining) {
+                Ok(()) if self.data.is_null() => continue,
+                other => break other,
+            }
+        };
+        self.watch_region = None;
+
+        result?;
+        Ok(self.current_frame())
+    }
+
+    /// Captures, keeping the watchdog and the stats up to date.
+    fn observed_capture(&mut self, timeout: UINT) -> io::Result<()> {
+        let watchdog = self.watchdog.clone();
+        if let Some(ref watchdog) = watchdog {
+            watchdog.begin();
+        }
+        let result = self.capture(timeout);
+        if let Some(ref watchdog) = watchdog {
+            watchdog.record(&result);
+        }
+        self.count(&result);
+        result
+    }
+
+    /// Unmaps the last frame and gives it back to DXGI.
+    unsafe fn release_frame(&mut self) {
+        if self.mapped {
+            (*self.context).Unmap(self.staging as *mut ID3D11Resource, 0);
+            self.mapped = false;
+        }
+        for converter in self.nv12.iter_mut().chain(self.scaler.iter_mut()) {
+            converter.unmap(self.context);
+        }
+
+        // It's null if it was lost and duplicating again failed.
+        if !self.duplication.is_null() {
+            if self.dupl_desc.in_system_memory {
+                (*self.duplication).UnMapDesktopSurface();
+            }
+            (*self.duplication).ReleaseFrame();
+        }
+        self.showing_rotated = false;
+    }
+
+    fn capture(&mut self, timeout: UINT) -> io::Result<()> {
+        unsafe {
+            self.release_frame();
+            let loaded = self.load_frame(timeout);
+            let acquired = match loaded {
+                Ok(()) => !self.data.is_null() && !self.want_nv12,
+                Err(ref err) => err.kind() == io::ErrorKind::TimedOut,
+            };
+            if !acquired {
+                // Whatever changed in this frame won't be in the next one's
+                // dirty rects.
+                self.tiles_synced = false;
+            }
+            if loaded.is_err() || !acquired {
+                return loaded;
+            }
+            if self.converting_to_srgb() {
+                self.convert_to_srgb()?;
+            }
+            self.reserve_for_frame()?;
+            let frame = slice::from_raw_parts_mut(self.data, self.len);
+
+            let rotation = self.rotation();
+            if self.should_draw_cursor() && !self.defer_cursor {
+                self.draw_cursor_scaled(frame, self.pitch, rotation, (false, false));
+            }
+            if let Some(ref mut accumulator) = self.accumulator {
+                accumulator.push(frame);
+            }
+
+            if self.correct_rotation && rotation != Rotation::Identity {
+                let (width, height) = self.frame_size;
+                let bpp = self.bytes_per_pixel();
+                convert::rotate(
+                    frame,
+                    self.pitch,
+                    width,
+                    height,
+                    bpp,
+                    rotation,
+                    &mut self.rotated,
+                );
+                self.showing_rotated = true;
+            }
+
+            if let Some(mut diff) = self.diff.take() {
+                let frame = self.current_frame();
+                let bpp = self.bytes_per_pixel();
+                diff.update(&frame, frame.stride(), frame.width(), frame.height(), bpp);
+                self.diff = Some(diff);
+            }
+            if self.tiles.is_some() {
+                self.update_tiles();
+            }
+            Ok(())
+        }
+    }
+
+    /// Fails before anything is copied if the buffers kept for each loaded
+    /// frame would go over the limit.
+    fn reserve_for_frame(&self) -> io::Result<()> {
+        let (width, height) = self.frame_size;
+        let len = width * height * self.bytes_per_pixel();
+        if let Some(ref accumulator) = self.accumulator {
+            let needed = (accumulator.capacity() + 3) * self.len;
+            self.reserve("accumulated frames", accumulator.memory_usage(), needed)?;
+        }
+        if self.correct_rotation && self.rotation() != Rotation::Identity {
+            self.reserve("rotated frame", self.rotated.capacity(), len)?;
+        }
+        if let Some(ref diff) = self.diff {
+            self.reserve("diff frame", diff.memory_usage(), diff.memory_needed(len))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the loaded scRGB frame with an sRGB copy, adjusted as
+    /// `set_adjustment` asked.
+    unsafe fn convert_to_srgb(&mut self) -> io::Result<()> {
+        let (width, height) = self.frame_size;
+        self.reserve("sRGB frame", self.srgb.capacity(), width * height * 4)?;
+        let params = self.tonemap_params();
+        let src = slice::from_raw_parts(self.data, self.len);
+        let lut = self.lut.as_ref();
+        convert::to_sdr(src, self.pitch, width, height, params, lut, &mut self.srgb);
+        self.data = self.srgb.as_mut_ptr();
+        self.pitch = width * 4;
+        self.len = self.srgb.len();
+        Ok(())
+    }
+
+    /// Updates `tiles` with the loaded frame, only hashing what changed
+    /// when the frame lines up with its dirty rects.
+    fn update_tiles(&mut self) {
+        let mut tiles = match self.tiles.take() {
+            Some(tiles) => tiles,
+            None => return,
+        };
+        // The cursor drawn on the frame isn't in the dirty rects.
+        let cursor = if self.should_draw_cursor() && !self.defer_cursor {
+            self.drawn_cursor_rect()
+        } else {
+            None
+        };
+        let aligned = self.tiles_synced
+            && self.output_size.is_none()
+            && self.rotation() == Rotation::Identity;
+
+        let mut hints = mem::take(&mut self.tile_hints);
+        hints.clear();
+        hints.extend(
+            self.dirty_rects
+                .iter()
+                .chain(self.move_rects.iter().map(|m| &m.destination))
+                .chain(cursor.iter())
+                .chain(self.tiles_cursor.iter()),
+        );
+        let frame = self.current_frame();
+        tiles.update(
+            &frame,
+            frame.stride(),
+            frame.width(),
+            frame.height(),
+            self.bytes_per_pixel(),
+            if aligned { Some(&hints) } else { None },
+        );
+
+        self.tile_hints = hints;
+        self.tiles_cursor = cursor;
+        self.tiles_synced = true;
+        self.tiles = Some(tiles);
+    }
+
+    fn count(&mut self, result: &io::Result<()>) {
+        // Released by `frame_if_region_changed` without a look.
+        if result.is_ok() && self.data.is_null() && !self.gpu_loaded {
+            return;
+        }
+        if self.stats.count(result, self.repeats == 0) {
+            self.frame_number += 1;
+        }
+    }
+
+    /// Running totals since creation or the last `reset_stats`.
+    pub fn stats(&self) -> CaptureStats {
+        let stats = &self.stats;
+        CaptureStats {
+            frames: stats.frames,
+            changed_frames: stats.changed_frames,
+            missed_frames: stats.missed_frames,
+            timeouts: stats.timeouts,
+            errors: stats.errors,
+            last_error: stats.last_error.clone(),
+            recreations: stats.recreations,
+            deadlines_exceeded: stats.deadlines_exceeded,
+            cursor_shape_updates: stats.cursor_shape_updates,
+            unknown_shapes: stats.unknown_shapes,
+            invalid_shapes: stats.invalid_shapes,
+            width: self.width,
+            height: self.height,
+            format: self.dupl_desc.mode.format,
+            average_acquire: stats.acquire.average(),
+            average_copy: stats.copy.average(),
+            average_map: stats.map.average(),
+        }
+    }
+
+    /// The number of the last frame, counting from 1, or 0 before the first.
+    /// Timeouts and errors don't count, nor do frames released unread by
+    /// `frame_if_region_changed`. Duplicating again doesn't reset it.
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    /// Starts `frame_number` over, so the next frame is 1.
+    pub fn reset_frame_counter(&mut self) {
+        self.frame_number = 0;
+    }
+
+    /// Zeroes every counter, including the ones with their own accessors,
+    /// but not `frame_number`.
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Reports every `frame` call to a watchdog, or stops with `None`.
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    /// Waits up to `timeout` milliseconds for a frame and reads what DXGI
+    /// says about it, then gives it straight back without copying or
+    /// mapping the image. Much cheaper than `frame` for telling whether
+    /// anything is changing.
+    ///
+    /// The pointer, `dirty_rects` and `move_rects` are updated as for any
+    /// frame. Frames polled this way don't count in `frame_number` or the
+    /// stats, and the image they changed won't be in the next frame's
+    /// dirty rects.
+    pub fn poll_metadata(&mut self, timeout: UINT) -> io::Result<FrameMetadata> {
+        unsafe {
+            self.release_frame();
+            let frame = self.acquire_frame(timeout)?;
+            (*frame).Release();
+            (*self.duplication).ReleaseFrame();
+        }
+        self.tiles_synced = false;
+
+        Ok(FrameMetadata {
+            info: self.frame_info.unwrap_or_default(),
+            dirty_rects: self.dirty_rects.len(),
+            move_rects: self.move_rects.len(),
+        })
+    }
+
+    /// Metadata for the last frame acquired by `frame`.
+    pub fn last_frame_info(&self) -> Option<FrameInfo> {
+        self.frame_info
+    }
+
+    /// What changed in the last acquired frame, in frame coordinates before
+    /// any `set_output_size` scaling. Empty if only the pointer changed.
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty_rects
+    }
+
+    /// Content the last acquired frame moved, e.g. by scrolling, in the same
+    /// coordinates as `dirty_rects`. Copying these before applying the
+    /// dirty rects rebuilds the frame from the one before.
+    pub fn move_rects(&self) -> &[MoveRect] {
+        &self.move_rects
+    }
+
+    /// How many desktop updates the last acquired frame folded together,
+    /// or 0 if only the pointer changed or no frame was acquired. More
+    /// than 1 means updates were missed.
+    pub fn accumulated_frames(&self) -> u32 {
+        self.frame_info.map_or(0, |info| info.accumulated_frames)
+    }
+
+    /// Desktop updates missed over the capturer's life, from
+    /// `accumulated_frames`. Carries on when the duplication is made again,
+    /// but updates while it was lost aren't counted. Zeroed by
+    /// `reset_stats`.
+    pub fn total_missed_frames(&self) -> u64 {
+        self.stats.missed_frames
+    }
+
+    /// When the last acquired frame's desktop image was presented, in
+    /// performance counter ticks. `None` if only the pointer changed, or no
+    /// frame was acquired. See `qpc_to_duration`.
+    pub fn last_present_time(&self) -> Option<i64> {
+        self.frame_info
+            .map(|info| info.last_present_time)
+            .filter(|&time| time != 0)
+    }
+
+    /// Where the cursor's hot spot is in the returned pixels, if it's visible.
+    pub fn cursor_position_frame(&self) -> Option<(i32, i32)> {
+        // Frames currently always cover the whole output.
+        self.cursor_position_local()
+    }
+
+    /// Where the cursor's hot spot is relative to the captured output's
+    /// top-left corner, as DXGI reports it, if it's visible. This is what
+    /// the cursor is drawn from.
+    pub fn cursor_position_local(&self) -> Option<(i32, i32)> {
+        if !self.cursor_info.visible {
+            return None;
+        }
+        Some(self.cursor_info.position)
+    }
+
+    /// Where the cursor's hot spot is on the virtual desktop, if it's
+    /// visible: the local position plus the output's origin there. For a
+    /// display left of the primary one at (-1920, 0), local (100, 50) is
+    /// (-1820, 50).
+    pub fn cursor_position_virtual(&self) -> Option<(i32, i32)> {
+        let local = self.cursor_position_local()?;
+        Some(cursor::virtual_position(local, self.desktop_rect()))
+    }
+
+    /// Same as `cursor_position_virtual`.
+    pub fn cursor_position_desktop(&self) -> Option<(i32, i32)> {
+        self.cursor_position_virtual()
+    }
+
+    /// Whether the cursor is drawn on frames.
+    pub fn capture_mouse(&self) -> bool {
+        self.cursor_mode == CursorMode::Composite
+    }
+
+    /// Starts or stops drawing the cursor on frames from the next one on,
+    /// without duplicating again. The pointer is tracked either way, so a
+    /// cursor turned on draws straight away if its shape was ever received.
+    pub fn set_capture_mouse(&mut self, capture_mouse: bool) {
+        self.cursor_mode = if capture_mouse {
+            CursorMode::Composite
+        } else {
+            CursorMode::None
+        };
+    }
+
+    /// `Composite` if the capturer was made capturing the mouse, else `None`.
+    pub fn cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    /// Changes what's done with the cursor from the next frame on, like
+    /// `set_capture_mouse`.
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.cursor_mode = mode;
+    }
+
+    /// A copy of the pointer's position, visibility and shape, for
+    /// `restore_cursor` on a capturer made later. Duplicating again after
+    /// access is lost already keeps them.
+    pub fn save_cursor(&self) -> SavedCursor {
+        SavedCursor(self.cursor_info.clone())
+    }
+
+    /// Takes the pointer from `save_cursor`. DXGI only sends the shape when
+    /// it changes, so a new capturer otherwise has none to draw until then.
+    /// The shape generation carries on from the saved one.
+    pub fn restore_cursor(&mut self, cursor: SavedCursor) {
+        self.cursor_info.restore(cursor);
+    }
+
+    /// Draws `image` as the cursor instead of the pointer's own shape, still
+    /// following its position and visibility, or goes back to the pointer's
+    /// shape with `None`. The image's alpha is blended like a color
+    /// pointer's; its `invert` flags are ignored. Images with less data
+    /// than their size needs aren't drawn.
+    pub fn set_custom_cursor(&mut self, image: Option<CursorImage>) {
+        self.custom_cursor = image;
+    }
+
+    /// In `CursorMode::Overlay`, the visible cursor as an image, with where
+    /// its top-left corner goes on the frame. That's relative to the output
+    /// as the display shows it, so it doesn't follow `output_size` scaling
+    /// or frames left sideways.
+    pub fn cursor_overlay(&self) -> Option<(CursorImage, (i32, i32))> {
+        if self.cursor_mode != CursorMode::Overlay || !self.cursor_info.visible {
+            return None;
+        }
+        let shape = self.drawn_shape()?;
+        let image = shape.to_bgra()?;
+        let rect = self.cursor_rect(&shape, self.desktop_rect());
+        Some((image, (rect.x, rect.y)))
+    }
+
+    /// The pointer as last reported, whether or not it's drawn on frames.
+    pub fn cursor(&self) -> CursorState<'_> {
+        self.cursor_info.state()
+    }
+
+    /// Where the hot spot is in the pointer shape, if one was received.
+    pub fn cursor_hotspot(&self) -> Option<(i32, i32)> {
+        self.cursor().shape.map(|shape| shape.hot_spot)
+    }
+
+    /// The pointer shape decoded to BGRA, if one of a known type was
+    /// received. See `CursorShape::to_bgra`.
+    pub fn cursor_bgra(&self) -> Option<CursorImage> {
+        self.cursor().shape.and_then(|shape| shape.to_bgra())
+    }
+
+    /// The visible size of the pointer shape, if one was received. For
+    /// monochrome shapes that's half the height DXGI reports, which counts
+    /// both masks; see `CursorShapeKind::Monochrome`.
+    pub fn cursor_size(&self) -> Option<(u32, u32)> {
+        self.cursor().shape.map(|shape| shape.visible_size())
+    }
+
+    /// The distance between rows of the pointer shape's data, in bytes, if
+    /// one was received.
+    pub fn cursor_pitch(&self) -> Option<u32> {
+        self.cursor().shape.map(|shape| shape.pitch)
+    }
+
+    /// Whether the last frame moved, showed, hid or reshaped the pointer.
+    pub fn cursor_updated(&self) -> bool {
+        self.cursor_info.updated
+    }
+
+    /// Goes up by one each time a pointer shape is received, even if it's
+    /// the same as before, by any capturer of the same `CaptureSession`.
+    /// Duplicating again after access is lost doesn't reset it, though the
+    /// next frame usually resends the shape.
+    pub fn cursor_shape_generation(&self) -> u64 {
+        self.cursor_info.shape_generation
+    }
+
+    /// Whether a pointer shape was received since `cursor_shape_generation`
+    /// returned `generation`.
+    pub fn cursor_shape_changed_since(&self, generation: u64) -> bool {
+        self.cursor_info.shape_generation != generation
+    }
+
+    /// The type of the last pointer shape, if one was received.
+    pub fn cursor_kind(&self) -> Option<CursorShapeKind> {
+        self.cursor_info.kind
+    }
+
+    /// How many pointer shapes of an unknown type were received.
+    pub fn unknown_shape_count(&self) -> u64 {
+        self.stats.unknown_shapes
+    }
+
+    /// Takes in the pointer's position and shape from a frame's info, even
+    /// when the cursor isn't drawn.
+    unsafe fn update_pointer(&mut self, info: &DXGI_OUTDUPL_FRAME_INFO) -> io::Result<()> {
+        self.cursor_info.updated = false;
+        if let Some(shared) = self.shared_cursor.clone() {
+            let rect = self.desktop_rect();
+            follow_cursor(
+                &mut self.cursor_info,
+                &lock(&shared),
+                self.output_number,
+                rect,
+            );
+        }
+
+        let mouse_update_time = *info.LastMouseUpdateTime.QuadPart();
+        if mouse_update_time == 0 {
+            // DXGI's position, once it gave one, beats asking.
+            if self.cursor_fallback && self.cursor_info.last_time_stamp == 0 {
+                self.poll_system_cursor();
+            }
+            return Ok(());
+        }
+
+        let pointer = info.PointerPosition;
+        let visible = pointer.Visible != 0;
+        let update_position = match self.shared_cursor {
+            Some(ref shared) => takes_pointer(
+                &lock(shared),
+                self.output_number,
+                visible,
+                mouse_update_time,
+            ),
+            None => takes_pointer(
+                &self.cursor_info,
+                self.output_number,
+                visible,
+                mouse_update_time,
+            ),
+        };
+
+        if update_position {
+            let position = (pointer.Position.x, pointer.Position.y);
+            self.cursor_info.updated |=
+                position != self.cursor_info.position || visible != self.cursor_info.visible;
+            self.cursor_info.position = position;
+            self.cursor_info.who_updated_position_last = self.output_number;
+            self.cursor_info.last_time_stamp = mouse_update_time;
+            self.cursor_info.visible = visible;
+        }
+
+        let got_shape = info.PointerShapeBufferSize != 0;
+        if got_shape {
+            self.stats.cursor_shape_updates += 1;
+            self.cursor_info
+                .shape
+                .resize(info.PointerShapeBufferSize as usize, 0);
+            let mut shape_size = 0;
+            check(
+                "GetFramePointerShape",
+                (*self.duplication).GetFramePointerShape(
+                    info.PointerShapeBufferSize,
+                    self.cursor_info.shape.as_mut_ptr() as *mut _,
+                    &mut shape_size,
+                    &mut self.cursor_info.shape_info,
+                ),
+            )?;
+            self.cursor_info.shape.truncate(shape_size as usize);
+            self.cursor_info.updated = true;
+            self.cursor_info.shape_generation += 1;
+            let kind = CursorShapeKind::from(self.cursor_info.shape_info.Type);
+            self.cursor_info.kind = Some(kind);
+            self.check_shape_size(kind);
+        }
+
+        // Shared before an unknown shape can fail the frame.
+        if let Some(shared) = self.shared_cursor.clone() {
+            let (rect, took) = (self.desktop_rect(), (update_position, got_shape));
+            share_cursor(&mut lock(&shared), &mut self.cursor_info, took, rect);
+        }
+        match self.cursor_info.kind {
+            Some(kind) if got_shape => self.check_shape_kind(kind),
+            _ => Ok(()),
+        }
+    }
+
+    /// Notes a press of `button` for `Config::click_highlight`, which then
+    /// draws around the cursor for its `duration`. Releases are ignored.
+    /// For clicks `ClickHighlight::poll_buttons` can't see, like ones sent
+    /// to a remote session.
+    pub fn notify_click(&mut self, button: MouseButton, pressed: bool) {
+        if pressed {
+            self.last_click = Some((button, Instant::now()));
+        }
+    }
+
+    /// Notes buttons pressed since the last frame, for
+    /// `ClickHighlight::poll_buttons`.
+    fn poll_buttons(&mut self) {
+        if !self.click_highlight.is_some_and(|h| h.poll_buttons) {
+            return;
+        }
+        let buttons = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+        for (i, &button) in buttons.iter().enumerate() {
+            let down = cursor::button_down(button);
+            if down && !self.buttons_down[i] {
+                self.notify_click(button, true);
+            }
+            self.buttons_down[i] = down;
+        }
+    }
+
+    /// Takes the pointer's position and visibility from `GetCursorInfo`,
+    /// for `Config::cursor_fallback`.
+    fn poll_system_cursor(&mut self) {
+        let ((x, y), showing) = match cursor::system_cursor() {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        let output = self.desktop_rect();
+        let on_output = output.contains(&Rect::new(x, y, 1, 1));
+        let position = (x - output.x, y - output.y);
+        let visible = showing && on_output;
+        self.cursor_info.updated |=
+            position != self.cursor_info.position || visible != self.cursor_info.visible;
+        self.cursor_info.position = position;
+        self.cursor_info.visible = visible;
+    }
+
+    fn check_shape_kind(&mut self, kind: CursorShapeKind) -> io::Result<()> {
+        let raw = match kind {
+            CursorShapeKind::Unknown(raw) => raw,
+            _ => return Ok(()),
+        };
+
+        self.stats.unknown_shapes += 1;
+        match self.unknown_shape {
+            UnknownShapePolicy::Ignore => Ok(()),
+            UnknownShapePolicy::Warn => {
+                warn!("unknown pointer shape type {}", raw);
+                Ok(())
+            }
+            UnknownShapePolicy::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown pointer shape type {}", raw),
+            )),
+        }
+    }
+
+    /// Whether the last frame's desktop image is the same as the one before,
+    /// e.g. because only the pointer moved. A drawn cursor may still differ.
+    pub fn is_repeat(&self) -> bool {
+        self.repeats > 0
+    }
+
+    /// Whether the last acquired frame presented a new desktop image, as
+    /// opposed to just a pointer update.
+    pub fn frame_was_updated(&self) -> bool {
+        self.frame_info.is_some_and(|info| info.desktop_updated())
+    }
+
+    /// Whether the last acquired frame only moved or reshaped the pointer,
+    /// so sending the cursor position is enough. Always false in
+    /// `CursorMode::None`, as nothing drawn or overlaid can differ.
+    pub fn cursor_only_update(&self) -> bool {
+        self.cursor_mode != CursorMode::None
+            && self.frame_info.is_some_and(|info| info.pointer_only())
+    }
+
+    /// How many frames in a row have been repeats.
+    pub fn consecutive_repeats(&self) -> u32 {
+        self.repeats
+    }
+
+    /// How many pointer shapes were dropped for not fitting their buffer.
+    pub fn invalid_shape_count(&self) -> u64 {
+        self.stats.invalid_shapes
+    }
+
+    fn check_shape_size(&mut self, kind: CursorShapeKind) {
+        let info = &self.cursor_info.shape_info;
+        let valid = cursor::shape_fits(
+            kind,
+            info.Width,
+            info.Height,
+            info.Pitch,
+            self.cursor_info.shape.len(),
+        );
+
+        if !valid {
+            warn!(
+                "dropping a {}x{} pointer shape with pitch {} in {} bytes",
+                info.Width,
+                info.Height,
+                info.Pitch,
+                self.cursor_info.shape.len()
+            );
+            self.stats.invalid_shapes += 1;
+        }
+        self.cursor_info.shape_valid = valid;
+    }
+
+    /// Waits up to `max_wait` in total for the screen to change, retrying
+    /// quietly until then. Real errors are returned straight away.
+    pub fn next_frame(&mut self, max_wait: Duration) -> io::Result<Frame<'_>> {
+        // Round up, so a tiny wait doesn't become a non-blocking poll.
+        let millis = max_wait.as_nanos().div_ceil(1_000_000);
+        self.frame(cmp::min(millis, INFINITE as u128 - 1) as UINT)
+    }
+
+    /// Starts tracking where the screen is changing, or stops with `None`.
+    pub fn track_activity(&mut self, config: Option<Activity>) {
+        self.activity = config.map(ActivityTracker::new);
+    }
+
+    /// The union of the areas that changed within `window`, if tracking.
+    pub fn activity_bounds(&self, window: Duration) -> Option<Rect> {
+        self.activity.as_ref().and_then(|a| a.bounds(window))
+    }
+
+    /// A stable crop around the recently active area, if tracking.
+    /// This is the region the caller should cut out of the frame, in frame coordinates.
+    pub fn activity_crop(&mut self) -> Option<Rect> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        self.activity.as_mut().map(|a| a.crop(width, height))
+    }
+
+    /// Starts comparing each frame with the one before in `block_size` by
+    /// `block_size` blocks, for `frame_changed_regions`, or stops with
+    /// `None`. This keeps a copy of the last frame.
+    pub fn track_changes(&mut self, block_size: Option<u32>) {
+        self.diff = block_size.map(FrameDiff::new);
+    }
+
+    /// The blocks of the last frame whose pixels differ from the frame
+    /// before, in frame coordinates. Unlike `dirty_rects`, these are never
+    /// reported for content that was redrawn the same. Empty unless
+    /// `track_changes` was called. Copies like `frame_into` are compared
+    /// before the cursor is drawn on them.
+    pub fn frame_changed_regions(&self) -> &[Rect] {
+        self.diff.as_ref().map_or(&[], |d| d.changed())
+    }
+
+    /// Starts hashing each frame in `tile_size` by `tile_size` tiles, for
+    /// `changed_tiles`, or stops with `None`. Only a hash per tile is kept.
+    ///
+    /// When a frame's dirty rects line up with its pixels, i.e. without
+    /// `set_output_size` or a turned display, only tiles touching them or
+    /// the drawn cursor are hashed. Otherwise every tile is.
+    pub fn track_tiles(&mut self, tile_size: Option<u32>) {
+        self.tiles = tile_size.map(ChangeTracker::new);
+        self.tiles_synced = false;
+        self.tiles_cursor = None;
+    }
+
+    /// The tiles of the last frame whose hashes differ from the frame
+    /// before, in frame coordinates. Empty unless `track_tiles` was called.
+    /// Every tile counts as changed on the first frame and after a resize.
+    pub fn changed_tiles(&self) -> impl Iterator<Item = TileRect> + '_ {
+        self.tiles.iter().flat_map(|t| t.changed_tiles())
+    }
+
+    /// A hash of the last frame's pixels, or of `region` of it, for skipping
+    /// frames identical to one already seen. See `convert::hash_pixels`.
+    ///
+    /// `region` is in frame coordinates and clamped to the frame. Row
+    /// padding isn't hashed, but the cursor is if it was drawn on the
+    /// frame. `None` if no frame was read back to the CPU.
+    pub fn frame_hash(&self, region: Option<Rect>) -> Option<u64> {
+        if self.data.is_null() {
+            return None;
+        }
+        let frame = self.current_frame();
+        let bpp = self.bytes_per_pixel();
+        let bounds = Rect::new(0, 0, frame.width() as u32, frame.height() as u32);
+        let region = match region {
+            Some(region) => region.intersection(&bounds).unwrap_or_default(),
+            None => bounds,
+        };
+        let start = region.y as usize * frame.stride() + region.x as usize * bpp;
+        Some(convert::hash_pixels(
+            &frame[start..],
+            frame.stride(),
+            region.w as usize,
+            region.h as usize,
+            bpp,
+        ))
+    }
+
+    /// Keeps the last `frames` frames for `averaged_frame`, or stops with `None`.
+    /// This costs roughly `frames + 3` times the frame size in memory, and
+    /// fails with a `BufferLimitExceeded` error if that's over
+    /// `Config::max_buffer_memory`. Frames are checked again as they're
+    /// kept, in case they grow.
+    ///
+    /// This used to return nothing; the `Result` is new with the limit.
+    pub fn accumulate(&mut self, frames: Option<usize>) -> io::Result<()> {
+        if let Some(n) = frames {
+            let frame = if self.len > 0 {
+                self.len
+            } else {
+                self.width * self.height * 4
+            };
+            let held = self.accumulator.as_ref().map_or(0, |a| a.memory_usage());
+            self.reserve("accumulated frames", held, (n + 3) * frame)?;
+        }
+
+        self.accumulator = frames.map(Accumulator::new);
+        Ok(())
+    }
+
+    /// Changes `Config::max_buffer_memory`. Buffers already over a new
+    /// limit are kept, but won't grow.
+    pub fn set_max_buffer_memory(&mut self, bytes: Option<usize>) {
+        self.max_buffer_memory = bytes;
+    }
+
+    pub fn max_buffer_memory(&self) -> Option<usize> {
+        self.max_buffer_memory
+    }
+
+    /// Fails with a `BufferLimitExceeded` error if growing the `buffer`
+    /// holding `held` bytes to `needed` would take the capturer's buffers
+    /// over `Config::max_buffer_memory`. Spare owned frame buffers are freed
+    /// to make room first. Buffers outside `buffer_memory_usage`, like ones
+    /// the caller passed in, hold nothing as far as this is concerned, so
+    /// they're counted as they grow.
+    pub(crate) fn reserve(
+        &self,
+        buffer: &'static str,
+        held: usize,
+        needed: usize,
+    ) -> io::Result<()> {
+        let growth = needed.saturating_sub(held);
+        let check = || {
+            let used = self.buffer_memory_usage().cpu();
+            memory::check_limit(buffer, self.max_buffer_memory, used, growth)
+        };
+        check().or_else(|_| {
+            self.frame_pool.clear();
+            check()
+        })
+    }
+
+    /// How much memory the capturer's buffers hold right now.
+    pub fn buffer_memory_usage(&self) -> BufferMemory {
+        BufferMemory {
+            cursor_shape: self.cursor_info.shape.capacity(),
+            metadata: self.metadata.capacity() * 4
+                + self.dirty_rects.capacity() * mem::size_of::<Rect>()
+                + self.move_rects.capacity() * mem::size_of::<MoveRect>(),
+            accumulator: self.accumulator.as_ref().map_or(0, |a| a.memory_usage()),
+            packed: self.packed.capacity(),
+            rotated: self.rotated.capacity(),
+            top_down: self.top_down.capacity(),
+            srgb: self.srgb.capacity(),
+            owned_frames: self.frame_pool.memory_usage(),
+            diff: self.diff.as_ref().map_or(0, |d| d.memory_usage()),
+            tiles: self.tiles.as_ref().map_or(0, |t| t.memory_usage())
+                + self.tile_hints.capacity() * mem::size_of::<Rect>(),
+            conversions: self.adjusted_yuv.memory_usage() + self.adjusted_nv12.capacity(),
+            staging: self.staging_desc.as_ref().map_or(0, |desc| {
+                desc.Width as usize * desc.Height as usize * bytes_per_pixel(desc.Format)
+            }),
+        }
+    }
+
+    /// The mean of the last `n` frames, laid out like a frame.
+    /// Returns `None` unless accumulating and at least one frame was captured.
+    pub fn averaged_frame(&mut self, n: usize) -> Option<&[u8]> {
+        self.accumulator.as_mut().and_then(|a| a.average(n))
+    }
+
+    /// Where the output sits on the virtual desktop.
+    fn desktop_rect(&self) -> Rect {
+        let coords = self.desc.DesktopCoordinates;
+        Rect::from_edges(coords.left, coords.top, coords.right, coords.bottom)
+    }
+
+    fn should_draw_cursor(&self) -> bool {
+        // The cursor is drawn as 8-bit BGRA.
+        let bgra = self.bytes_per_pixel() == 4;
+        self.cursor_mode == CursorMode::Composite && self.cursor_info.visible && bgra
+    }
+
+    /// Draws the cursor, and any click highlight under it, onto the loaded
+    /// frame or a copy of it, which is turned by `rotation` from the way the
+    /// display shows it, then mirrored horizontally and vertically by
+    /// `flip`. Rows are `pitch` bytes apart, which for the mapped surface
+    /// can be more than the width.
+    fn draw_cursor_scaled(
+        &self,
+        frame: &mut [u8],
+        pitch: usize,
+        rotation: Rotation,
+        flip: (bool, bool),
+    ) {
+        let (width, height) = self.frame_size;
+        let size = if self.rotation().is_sideways() {
+            (height as u32, width as u32)
+        } else {
+            (width as u32, height as u32)
+        };
+        let canvas = Canvas {
+            region: self.desktop_rect(),
+            size,
+            rotation,
+            flip,
+            pitch,
+        };
+        self.draw_click_highlight(frame, &canvas);
+        self.draw_cursor(frame, &canvas);
+    }
+
+    /// The shape the cursor is drawn with: the custom one if set, else the
+    /// last one DXGI sent that fits its buffer.
+    fn drawn_shape(&self) -> Option<CursorShape<'_>> {
+        match self.custom_cursor {
+            Some(ref image) => image.as_shape(),
+            None => self.cursor().shape,
+        }
+    }
+
+    /// The extent of `shape` at the cursor, in `region` coordinates.
+    fn cursor_rect(&self, shape: &CursorShape, region: Rect) -> Rect {
+        let (x, y) = cursor::cursor_origin(
+            self.cursor_info.position,
+            shape.hot_spot,
+            self.desktop_rect(),
+            region,
+        );
+        let (width, height) = shape.visible_size();
+        Rect::new(x, y, width, height)
+    }
+
+    /// The circle of a click highlight that's still showing, as its bounds
+    /// in `region` coordinates and its color.
+    fn click_circle(&self, region: Rect) -> Option<(Rect, [u8; 4])> {
+        let highlight = self.click_highlight?;
+        let (button, pressed) = self.last_click?;
+        if pressed.elapsed() >= highlight.duration {
+            return None;
+        }
+        let color = if button == MouseButton::Right {
+            highlight.right_color
+        } else {
+            highlight.color
+        };
+        let (x, y) = cursor::cursor_origin(
+            self.cursor_info.position,
+            (0, 0),
+            self.desktop_rect(),
+            region,
+        );
+        let radius = highlight.radius as i32;
+        let diameter = highlight.radius * 2;
+        Some((Rect::new(x - radius, y - radius, diameter, diameter), color))
+    }
+
+    /// Everything `draw_cursor_scaled` would draw, in desktop coordinates.
+    fn drawn_cursor_rect(&self) -> Option<Rect> {
+        let region = self.desktop_rect();
+        let shape = self
+            .drawn_shape()
+            .map(|shape| self.cursor_rect(&shape, region));
+        let circle = self.click_circle(region).map(|(rect, _)| rect);
+        match (shape, circle) {
+            (Some(shape), Some(circle)) => Some(shape.union(&circle)),
+            (shape, circle) => shape.or(circle),
+        }
+    }
+
+    fn draw_click_highlight(&self, frame: &mut [u8], canvas: &Canvas) {
+        let (circle, color) = match self.click_circle(canvas.region) {
+            Some(circle) => circle,
+            None => return,
+        };
+        let diameter = circle.w as i64;
+        let bpp = self.bytes_per_pixel();
+        canvas.paint(frame, bpp, circle, |frame, frame_index, x, y| {
+            // Twice the distance from the center to the pixel's center.
+            let (dx, dy) = (2 * x as i64 + 1 - diameter, 2 * y as i64 + 1 - diameter);
+            if dx * dx + dy * dy <= diameter * diameter {
+                for i in 0..3 {
+                    frame[frame_index + i] =
+                        cursor::blend_straight(color[i], color[3], frame[frame_index + i]);
+                }
+                frame[frame_index + 3] = 255; // Full opacity
+            }
+        });
+    }
+
+    fn draw_cursor(&self, frame: &mut [u8], canvas: &Canvas) {
+        let shape = match self.drawn_shape() {
+            Some(shape) => shape,
+            None => return,
+        };
+        // Custom images are straight alpha.
+        let premultiplied = self.custom_cursor.is_none()
+            && match self.cursor_blend {
+                CursorBlend::Straight => false,
+                CursorBlend::Premultiplied => true,
+                CursorBlend::Auto => {
+                    shape.kind == CursorShapeKind::Color && cursor::looks_premultiplied(&shape)
+                }
+            };
+        let blend = if premultiplied {
+            cursor::blend_premultiplied
+        } else {
+            cursor::blend_straight
+        };
+
+        let rect = self.cursor_rect(&shape, canvas.region);
+        let bpp = self.bytes_per_pixel();
+        canvas.paint(frame, bpp, rect, |frame, frame_index, x, y| {
+            // 4 bytes per pixel for color cursors
+            let cursor_index = y * shape.pitch as usize + x * 4;
+            match shape.kind {
+                CursorShapeKind::Color => {
+                    cursor::draw_color_cursor(frame, frame_index, shape.data, cursor_index, blend);
+                }
+                CursorShapeKind::Monochrome => {
+                    cursor::draw_monochrome_cursor(frame, frame_index, &shape, x, y);
                 }
-                if frame_index + 3 < frame.len() {
-                    frame[frame_index + 3] = 255; // Full opacity
+                CursorShapeKind::MaskedColor => {
+                    cursor::draw_masked_color_cursor(frame, frame_index, shape.data, cursor_index);
                 }
+                CursorShapeKind::Unknown(_) => {}
             }
-        }
+        });
     }
+}
 
-    fn draw_monochrome_cursor(
-        &self,
-        frame: &mut [u8],
-        frame_index: usize,
-        cursor_index: usize,
-        x: i32,
-    ) {
-        let byte_index = cursor_index / 8;
-        let bit_index = 7 - (x % 8) as usize;
-        if byte_index < self.cursor_info.shape.len()
-            && byte_index + (self.cursor_info.shape_info.Height as usize / 2)
-                < self.cursor_info.shape.len()
-        {
-            let and_mask = (self.cursor_info.shape[byte_index] >> bit_index) & 1;
-            let xor_mask = (self.cursor_info.shape
-                [byte_index + (self.cursor_info.shape_info.Height as usize / 2)]
-                >> bit_index)
-                & 1;
-
-            if and_mask == 0 && xor_mask == 1 {
-                // Invert the pixel
-                for i in 0..3 {
-                    if frame_index + i < frame.len() {
-                        frame[frame_index + i] = 255 - frame[frame_index + i];
-                    }
-                }
-            } else if and_mask == 0 && xor_mask == 0 {
-                // Make the pixel black
-                for i in 0..3 {
-                    if frame_index + i < frame.len() {
-                        frame[frame_index + i] = 0;
-                    }
-                }
-            }
+/// A frame being drawn on: `region` of the virtual desktop scaled to `size`
+/// pixels, then turned by `rotation` and mirrored horizontally and
+/// vertically by `flip`, with rows `pitch` bytes apart.
+#[derive(Copy, Clone)]
+struct Canvas {
+    region: Rect,
+    size: (u32, u32),
+    rotation: Rotation,
+    flip: (bool, bool),
+    pitch: usize,
+}
+
+impl Canvas {
+    /// Calls `draw` with the index in `frame` of each pixel covering `rect`,
+    /// which is in `region` coordinates, and the pixel's position in
+    /// `rect`.
+    fn paint<F>(&self, frame: &mut [u8], bytes_per_pixel: usize, rect: Rect, mut draw: F)
+    where
+        F: FnMut(&mut [u8], usize, usize, usize),
+    {
+        let Canvas {
+            region,
+            size,
+            rotation,
+            flip,
+            pitch,
+        } = *self;
+        if region.is_empty() {
+            return;
         }
-    }
 
-    fn draw_masked_color_cursor(&self, frame: &mut [u8], frame_index: usize, cursor_index: usize) {
-        if cursor_index + 3 < self.cursor_info.shape.len() {
-            let alpha = self.cursor_info.shape[cursor_index + 3] as u16;
-            if alpha > 0 {
-                for i in 0..3 {
-                    if frame_index + i < frame.len()
-                        && cursor_index + i < self.cursor_info.shape.len()
-                    {
-                        if self.cursor_info.shape[cursor_index + i] > 0 {
-                            frame[frame_index + i] = self.cursor_info.shape[cursor_index + i];
-                        }
-                    }
+        // Between region and frame pixels, rounding outwards.
+        let (w, h) = (size.0 as i64, size.1 as i64);
+        let (rw, rh) = (region.w as i64, region.h as i64);
+        let scaled = Rect::from_edges(
+            (rect.x as i64 * w).div_euclid(rw) as i32,
+            (rect.y as i64 * h).div_euclid(rh) as i32,
+            -(-rect.right() as i64 * w).div_euclid(rw) as i32,
+            -(-rect.bottom() as i64 * h).div_euclid(rh) as i32,
+        );
+        let visible = match scaled.intersection(&Rect::new(0, 0, size.0, size.1)) {
+            Some(visible) => visible,
+            None => return,
+        };
+        let (buffer_width, buffer_height) = if rotation.is_sideways() {
+            (size.1 as usize, size.0 as usize)
+        } else {
+            (size.0 as usize, size.1 as usize)
+        };
+
+        for frame_y in visible.y..visible.bottom() {
+            let y = (frame_y as i64 * rh / h) as i32 - rect.y;
+            if y < 0 || y >= rect.h as i32 {
+                continue;
+            }
+            for frame_x in visible.x..visible.right() {
+                let x = (frame_x as i64 * rw / w) as i32 - rect.x;
+                if x < 0 || x >= rect.w as i32 {
+                    continue;
                 }
-                if frame_index + 3 < frame.len() {
-                    frame[frame_index + 3] = 255; // Full opacity
+
+                // The rect is placed and clipped the way the display shows
+                // it; each of its pixels is then found in the framebuffer,
+                // which for a turned display is in the panel's orientation.
+                let (x_in, y_in) = rotation.source(
+                    frame_x as usize,
+                    frame_y as usize,
+                    buffer_width,
+                    buffer_height,
+                );
+                let x_in = if flip.0 {
+                    buffer_width - 1 - x_in
+                } else {
+                    x_in
+                };
+                let y_in = if flip.1 {
+                    buffer_height - 1 - y_in
+                } else {
+                    y_in
+                };
+                let frame_index = y_in * pitch + x_in * bytes_per_pixel;
+                if frame_index + bytes_per_pixel <= frame.len() {
+                    draw(frame, frame_index, x as usize, y as usize);
                 }
             }
         }
     }
 }
 
+// D3D11 devices are free-threaded, and the immediate context and duplication
+// may be used from any thread as long as it's one at a time, which `&mut`
+// already ensures.
+unsafe impl Send for Capturer {}
+
 impl Drop for Capturer {
     fn drop(&mut self) {
         unsafe {
-            if !self.surface.is_null() {
-                (*self.surface).Unmap();
-                (*self.surface).Release();
+            if self.mapped {
+                (*self.context).Unmap(self.staging as *mut ID3D11Resource, 0);
+            }
+            if !self.staging.is_null() {
+                (*self.staging).Release();
+            }
+            if !self.duplication.is_null() {
+                (*self.duplication).Release();
             }
-            (*self.duplication).Release();
             (*self.device).Release();
             (*self.context).Release();
+            (*self.adapter).Release();
+            if !self.output.is_null() {
+                (*self.output).Release();
+            }
         }
     }
 }
@@ -451,7 +2968,9 @@ pub struct Displays {
 impl Displays {
     pub fn new() -> io::Result<Displays> {
         let mut factory = ptr::null_mut();
-        wrap_hresult(unsafe { CreateDXGIFactory1(&IID_IDXGIFACTORY1, &mut factory) })?;
+        check("CreateDXGIFactory1", unsafe {
+            CreateDXGIFactory1(&IID_IDXGIFACTORY1, &mut factory)
+        })?;
 
         let mut adapter = ptr::null_mut();
         unsafe {
@@ -498,13 +3017,14 @@ impl Displays {
 
         // Advance to the next display.
 
+        let output_index = self.ndisplay;
         self.ndisplay += 1;
 
         // We get the display's details.
 
         let desc = unsafe {
             let mut desc = mem::MaybeUninit::uninit();
-            (*output).GetDesc(desc.assume_init_mut());
+            (*output).GetDesc(desc.as_mut_ptr());
             desc
         };
 
@@ -535,10 +3055,31 @@ impl Displays {
             inner: inner as *mut IDXGIOutput1,
             adapter: self.adapter,
             desc: unsafe { desc.assume_init() },
+            adapter_index: self.nadapter,
+            output_index,
         }))
     }
 }
 
+impl Displays {
+    /// Every display, minus those mirroring one that came before.
+    /// See `Display::is_clone_of`.
+    pub fn deduplicated(self) -> Vec<Display> {
+        let mut displays: Vec<Display> = Vec::new();
+        for display in self {
+            if !displays.iter().any(|d| display.is_clone_of(d)) {
+                displays.push(display);
+            }
+        }
+        displays
+    }
+}
+
+// DXGI factories, adapters and outputs are free-threaded, so they may be
+// used and released on another thread. They're not `Sync`, since nothing here
+// guards against using one from two threads at once.
+unsafe impl Send for Displays {}
+
 impl Iterator for Displays {
     type Item = Display;
     fn next(&mut self) -> Option<Display> {
@@ -577,12 +3118,175 @@ impl Drop for Displays {
     }
 }
 
+/// Video memory usage of one segment group, in bytes.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemorySegment {
+    pub budget: u64,
+    pub current_usage: u64,
+    pub available_for_reservation: u64,
+    pub current_reservation: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MemoryInfo {
+    /// Memory local to the GPU, i.e. dedicated video memory.
+    pub local: MemorySegment,
+    /// System memory the GPU can use.
+    pub non_local: MemorySegment,
+}
+
+/// What `Capturer::poll_metadata` found out about a frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameMetadata {
+    pub info: FrameInfo,
+    /// How many rects changed, which `Capturer::dirty_rects` lists.
+    pub dirty_rects: usize,
+    /// How many rects moved, which `Capturer::move_rects` lists.
+    pub move_rects: usize,
+}
+
+/// What DXGI reported about the last acquired frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameInfo {
+    /// When the desktop image was last presented, in performance counter
+    /// ticks, or 0 if only the pointer changed.
+    pub last_present_time: i64,
+    /// When the pointer was last updated, or 0 if it wasn't.
+    pub last_mouse_update_time: i64,
+    /// How many desktop updates were folded into this frame.
+    pub accumulated_frames: u32,
+    pub rects_coalesced: bool,
+    pub protected_content_masked_out: bool,
+    pub pointer_position: (i32, i32),
+    pub pointer_visible: bool,
+    /// Non-zero when the pointer shape changed.
+    pub pointer_shape_buffer_size: u32,
+    pub total_metadata_buffer_size: u32,
+    /// Where the output sat on the virtual desktop.
+    pub desktop: Rect,
+    pub rotation: DXGI_MODE_ROTATION,
+    /// Whether the frame is a different size than the one before, after
+    /// the display mode changed. The new size is in `desktop`.
+    pub resized: bool,
+    /// Set on the first frame after the display mode changed.
+    pub mode_change: Option<ModeChange>,
+}
+
+impl FrameInfo {
+    /// Whether the desktop image was presented again, so may have new
+    /// pixels.
+    pub fn desktop_updated(&self) -> bool {
+        self.last_present_time != 0
+    }
+
+    /// Whether only the pointer moved or changed shape.
+    pub fn pointer_only(&self) -> bool {
+        !self.desktop_updated() && self.last_mouse_update_time != 0
+    }
+
+    unsafe fn from_raw(info: &DXGI_OUTDUPL_FRAME_INFO) -> FrameInfo {
+        FrameInfo {
+            last_present_time: *info.LastPresentTime.QuadPart(),
+            last_mouse_update_time: *info.LastMouseUpdateTime.QuadPart(),
+            accumulated_frames: info.AccumulatedFrames,
+            rects_coalesced: info.RectsCoalesced != 0,
+            protected_content_masked_out: info.ProtectedContentMaskedOut != 0,
+            pointer_position: (
+                info.PointerPosition.Position.x,
+                info.PointerPosition.Position.y,
+            ),
+            pointer_visible: info.PointerPosition.Visible != 0,
+            pointer_shape_buffer_size: info.PointerShapeBufferSize,
+            total_metadata_buffer_size: info.TotalMetadataBufferSize,
+            desktop: Rect::default(),
+            rotation: 0,
+            resized: false,
+            mode_change: None,
+        }
+    }
+}
+
+/// Converts performance counter ticks, like `FrameInfo::last_present_time`,
+/// to the time since the counter started, which is about when Windows did.
+pub fn qpc_to_duration(ticks: i64) -> Duration {
+    let frequency = unsafe {
+        let mut frequency: LARGE_INTEGER = mem::zeroed();
+        // Can't fail since Windows XP.
+        QueryPerformanceFrequency(&mut frequency);
+        *frequency.QuadPart() as u64
+    };
+    let ticks = ticks.max(0) as u64;
+    let nanos = ticks as u128 * 1_000_000_000 / frequency as u128;
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+pub struct Adapter(*mut IDXGIAdapter1);
+
+impl Adapter {
+    /// The adapter's video memory budget and usage.
+    /// Returns `None` before Windows 10, which lacks `IDXGIAdapter3`.
+    pub fn memory_info(&self) -> Option<MemoryInfo> {
+        unsafe { query_memory_info(self.0) }
+    }
+}
+
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.0).Release();
+        }
+    }
+}
+
+unsafe fn query_memory_info(adapter: *mut IDXGIAdapter1) -> Option<MemoryInfo> {
+    let mut adapter3: *mut IDXGIAdapter3 = ptr::null_mut();
+    (*adapter).QueryInterface(
+        &IID_IDXGIADAPTER3,
+        &mut adapter3 as *mut *mut _ as *mut *mut _,
+    );
+
+    if adapter3.is_null() {
+        return None;
+    }
+
+    let segment = |group: DXGI_MEMORY_SEGMENT_GROUP| {
+        let mut info: DXGI_QUERY_VIDEO_MEMORY_INFO = mem::zeroed();
+        if (*adapter3).QueryVideoMemoryInfo(0, group, &mut info) == S_OK {
+            Some(MemorySegment {
+                budget: info.Budget,
+                current_usage: info.CurrentUsage,
+                available_for_reservation: info.AvailableForReservation,
+                current_reservation: info.CurrentReservation,
+            })
+        } else {
+            None
+        }
+    };
+
+    let local = segment(DXGI_MEMORY_SEGMENT_GROUP_LOCAL);
+    let non_local = segment(DXGI_MEMORY_SEGMENT_GROUP_NON_LOCAL);
+    (*adapter3).Release();
+
+    Some(MemoryInfo {
+        local: local?,
+        non_local: non_local?,
+    })
+}
+
 pub struct Display {
     inner: *mut IDXGIOutput1,
     adapter: *mut IDXGIAdapter1,
     desc: DXGI_OUTPUT_DESC,
+    adapter_index: UINT,
+    output_index: UINT,
 }
 
+// See `Displays`. A display can be found on one thread and captured on another.
+unsafe impl Send for Display {}
+
 impl Display {
     pub fn width(&self) -> LONG {
         self.desc.DesktopCoordinates.right - self.desc.DesktopCoordinates.left
@@ -592,14 +3296,61 @@ impl Display {
         self.desc.DesktopCoordinates.bottom - self.desc.DesktopCoordinates.top
     }
 
+    /// Where the display sits on the virtual desktop.
+    pub fn desktop(&self) -> Rect {
+        let coords = self.desc.DesktopCoordinates;
+        Rect::from_edges(coords.left, coords.top, coords.right, coords.bottom)
+    }
+
     pub fn rotation(&self) -> DXGI_MODE_ROTATION {
         self.desc.Rotation
     }
 
+    /// The brightness of SDR content on this display, in nits, or `None`
+    /// unless HDR is on. Users can change it any time, so it isn't cached.
+    pub fn sdr_white_level_nits(&self) -> Option<f32> {
+        sdr_white_level_nits(&self.desc.DeviceName)
+    }
+
+    /// The display's color space, which changes when HDR is turned on or
+    /// off. `Unknown` before Windows 10 1803.
+    pub fn color_space(&self) -> ColorSpace {
+        unsafe { output_color_space(self.inner) }
+    }
+
+    /// Tone mapping that keeps this display's SDR white at full brightness.
+    pub fn tonemap_params(&self) -> TonemapParams {
+        tonemap_params(&self.desc.DeviceName)
+    }
+
+    /// The adapter this display is connected to.
+    pub fn adapter(&self) -> Adapter {
+        unsafe {
+            (*self.adapter).AddRef();
+        }
+        Adapter(self.adapter)
+    }
+
+    /// The index of the display's adapter, as passed to `EnumAdapters1`.
+    pub fn adapter_index(&self) -> UINT {
+        self.adapter_index
+    }
+
+    /// The index of the display on its adapter, as passed to `EnumOutputs`.
+    /// Like the adapter index, it counts displays that were skipped.
+    pub fn output_index(&self) -> UINT {
+        self.output_index
+    }
+
+    /// Whether both show the same content, i.e. they're the same GDI
+    /// source or cover the same part of the desktop, like a mirrored
+    /// laptop screen and projector.
+    pub fn is_clone_of(&self, other: &Display) -> bool {
+        is_clone(&self.desc, &other.desc)
+    }
+
     pub fn name(&self) -> &[u16] {
-        let s = &self.desc.DeviceName;
-        let i = s.iter().position(|&x| x == 0).unwrap_or(s.len());
-        &s[..i]
+        device_name(&self.desc)
     }
 }
 
@@ -612,6 +3363,384 @@ impl Drop for Display {
     }
 }
 
+/// Every level we can capture with, best first.
+const FEATURE_LEVELS: [D3D_FEATURE_LEVEL; 7] = [
+    D3D_FEATURE_LEVEL_11_1,
+    D3D_FEATURE_LEVEL_11_0,
+    D3D_FEATURE_LEVEL_10_1,
+    D3D_FEATURE_LEVEL_10_0,
+    D3D_FEATURE_LEVEL_9_3,
+    D3D_FEATURE_LEVEL_9_2,
+    D3D_FEATURE_LEVEL_9_1,
+];
+
+/// Creates a device and its immediate context, returning the flags it really got.
+unsafe fn create_device(
+    adapter: *mut IDXGIAdapter1,
+    flags: DeviceFlags,
+) -> io::Result<(*mut ID3D11Device, *mut ID3D11DeviceContext, DeviceFlags)> {
+    let mut device = ptr::null_mut();
+    let mut context = ptr::null_mut();
+    let mut flags = flags;
+    let mut levels = &FEATURE_LEVELS[..];
+
+    loop {
+        let res = D3D11CreateDevice(
+            adapter,
+            D3D_DRIVER_TYPE_UNKNOWN,
+            ptr::null_mut(),
+            flags.bits(),
+            levels.as_ptr(),
+            levels.len() as UINT,
+            D3D11_SDK_VERSION,
+            &mut device,
+            ptr::null_mut(),
+            &mut context,
+        );
+
+        match res {
+            S_OK => return Ok((device, context, flags)),
+            // Runtimes that predate 11.1 reject the whole array.
+            E_INVALIDARG if levels.len() == FEATURE_LEVELS.len() => {
+                info!("feature level 11_1 was rejected, retrying without it");
+                levels = &FEATURE_LEVELS[1..];
+            }
+            // The debug layer is an optional Windows feature, so it's often missing.
+            DXGI_ERROR_SDK_COMPONENT_MISSING if flags.contains(DeviceFlags::DEBUG) => {
+                info!("the D3D11 debug layer is missing, retrying without it");
+                flags = flags.without(DeviceFlags::DEBUG);
+            }
+            _ => {
+                warn!("D3D11CreateDevice failed: {:#010x}", res);
+                return Err(io::ErrorKind::Other.into());
+            }
+        }
+    }
+}
+
+unsafe fn duplicate(
+    output: *mut IDXGIOutput1,
+    device: *mut ID3D11Device,
+    hdr: bool,
+) -> io::Result<*mut IDXGIOutputDuplication> {
+    let mut duplication = ptr::null_mut();
+    let mut res = DXGI_ERROR_UNSUPPORTED;
+
+    // Only `DuplicateOutput1` can hand out FP16, and it's missing before
+    // Windows 10 1703 and refused to processes that aren't DPI aware.
+    let mut output5: *mut IDXGIOutput5 = ptr::null_mut();
+    if hdr
+        && (*output).QueryInterface(
+            &IID_IDXGIOUTPUT5,
+            &mut output5 as *mut *mut _ as *mut *mut _,
+        ) == S_OK
+    {
+        let formats = [DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_B8G8R8A8_UNORM];
+        res = (*output5).DuplicateOutput1(
+            device as *mut IUnknown,
+            0,
+            formats.len() as UINT,
+            formats.as_ptr(),
+            &mut duplication,
+        );
+        (*output5).Release();
+        if res != S_OK && res != DXGI_ERROR_NOT_CURRENTLY_AVAILABLE {
+            debug!("DuplicateOutput1 failed: {:#010x}, so no HDR", res);
+        }
+    }
+    if res != S_OK && res != DXGI_ERROR_NOT_CURRENTLY_AVAILABLE {
+        res = (*output).DuplicateOutput(device as *mut IUnknown, &mut duplication);
+    }
+    if res == DXGI_ERROR_NOT_CURRENTLY_AVAILABLE {
+        warn!("DuplicateOutput failed: too many duplications");
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "too many applications are duplicating this output; \
+             within one process, share a capturer with SharedCapturer",
+        ));
+    }
+    check("DuplicateOutput", res)?;
+    Ok(duplication)
+}
+
+/// Whether a device was created on the given adapter.
+unsafe fn is_on_adapter(device: *mut ID3D11Device, adapter: *mut IDXGIAdapter1) -> bool {
+    match device_adapter(device) {
+        Some(theirs) => {
+            let same = same_adapter(theirs, adapter);
+            (*theirs).Release();
+            same
+        }
+        None => false,
+    }
+}
+
+/// The adapter a device was created on, with a new reference.
+unsafe fn device_adapter(device: *mut ID3D11Device) -> Option<*mut IDXGIAdapter1> {
+    let mut dxgi_device: *mut IDXGIDevice = ptr::null_mut();
+    (*device).QueryInterface(
+        &IID_IDXGIDEVICE,
+        &mut dxgi_device as *mut *mut _ as *mut *mut _,
+    );
+
+    if dxgi_device.is_null() {
+        return None;
+    }
+
+    let mut adapter = ptr::null_mut();
+    (*dxgi_device).GetAdapter(&mut adapter);
+    (*dxgi_device).Release();
+
+    if adapter.is_null() {
+        return None;
+    }
+
+    let mut adapter1: *mut IDXGIAdapter1 = ptr::null_mut();
+    (*adapter).QueryInterface(
+        &IID_IDXGIADAPTER1,
+        &mut adapter1 as *mut *mut _ as *mut *mut _,
+    );
+    (*adapter).Release();
+
+    if adapter1.is_null() {
+        None
+    } else {
+        Some(adapter1)
+    }
+}
+
+fn sdr_white_level_nits(device_name: &[u16]) -> Option<f32> {
+    unsafe { display_config::find_path(device_name) }
+        .and_then(|path| unsafe { display_config::sdr_white_level(&path) })
+}
+
+fn tonemap_params(device_name: &[u16]) -> TonemapParams {
+    let mut params = TonemapParams::default();
+    if let Some(nits) = sdr_white_level_nits(device_name) {
+        params.sdr_white_nits = nits;
+    }
+    params
+}
+
+/// Interleaves `src` into NV12 laid out like the GPU's, padding odd sizes
+/// by repeating the last row and column. Returns the pitch.
+fn i420_to_nv12(src: &YuvBuffer, dst: &mut Vec<u8>) -> usize {
+    let (width, height) = (src.width(), src.height());
+    let pitch = width.div_ceil(2) * 2;
+    let (cw, ch) = (src.uv_stride(), height.div_ceil(2));
+    dst.clear();
+    dst.reserve(pitch * (ch * 2 + ch));
+    for y in 0..ch * 2 {
+        let row = &src.y[cmp::min(y, height - 1) * width..][..width];
+        dst.extend_from_slice(row);
+        if pitch > width {
+            dst.push(row[width - 1]);
+        }
+    }
+    for cy in 0..ch {
+        for cx in 0..cw {
+            dst.push(src.u[cy * cw + cx]);
+            dst.push(src.v[cy * cw + cx]);
+        }
+    }
+    pitch
+}
+
+fn lock(shared: &SharedCursor) -> MutexGuard<'_, CursorInfo> {
+    // A panic can't leave it half-changed in a way that matters.
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether `output`'s pointer update at `time_stamp` moves the pointer,
+/// given the last one that did, as in DXGI's desktop duplication sample:
+/// an output that doesn't have the pointer can't hide it, or take it from
+/// one showing it with a newer update.
+fn takes_pointer(last: &CursorInfo, output: u32, visible: bool, time_stamp: i64) -> bool {
+    if last.who_updated_position_last == output {
+        return true;
+    }
+    visible && !(last.visible && last.last_time_stamp > time_stamp)
+}
+
+/// Takes a session's pointer into `local`, for `output` at `rect` on the
+/// virtual desktop. It's only visible on the output that last moved it.
+fn follow_cursor(local: &mut CursorInfo, shared: &CursorInfo, output: u32, rect: Rect) {
+    if shared.last_time_stamp != 0 {
+        let position = (shared.position.0 - rect.x, shared.position.1 - rect.y);
+        let visible = shared.visible && shared.who_updated_position_last == output;
+        local.updated |= position != local.position || visible != local.visible;
+        local.position = position;
+        local.visible = visible;
+        local.who_updated_position_last = shared.who_updated_position_last;
+        local.last_time_stamp = shared.last_time_stamp;
+    }
+    if shared.kind.is_some() && shared.shape_generation != local.shape_generation {
+        local.shape.clone_from(&shared.shape);
+        local.shape_info = shared.shape_info;
+        local.kind = shared.kind;
+        local.shape_valid = shared.shape_valid;
+        local.shape_generation = shared.shape_generation;
+        local.updated = true;
+    }
+}
+
+/// Gives a session what `local` just took from a frame of the output at
+/// `rect`: the position if it `took` the pointer, and the shape if it `got`
+/// one, which gets the session's next generation.
+fn share_cursor(
+    shared: &mut CursorInfo,
+    local: &mut CursorInfo,
+    (took, got): (bool, bool),
+    rect: Rect,
+) {
+    if took {
+        shared.position = cursor::virtual_position(local.position, rect);
+        shared.visible = local.visible;
+        shared.who_updated_position_last = local.who_updated_position_last;
+        shared.last_time_stamp = local.last_time_stamp;
+    }
+    if got {
+        local.shape_generation = local.shape_generation.max(shared.shape_generation + 1);
+        shared.shape.clone_from(&local.shape);
+        shared.shape_info = local.shape_info;
+        shared.kind = local.kind;
+        shared.shape_valid = local.shape_valid;
+        shared.shape_generation = local.shape_generation;
+    }
+}
+
+/// The bytes `convert::bgra_to_i420` writes for a `width` by `height` frame.
+fn i420_len(width: usize, height: usize) -> usize {
+    width * height + 2 * width.div_ceil(2) * height.div_ceil(2)
+}
+
+/// The bytes `i420_to_nv12` writes for a `width` by `height` frame.
+fn nv12_len(width: usize, height: usize) -> usize {
+    width.div_ceil(2) * 2 * height.div_ceil(2) * 3
+}
+
+/// The conversions only understand 8-bit BGRA.
+fn check_bgra(frame: &Frame) -> io::Result<()> {
+    if bytes_per_pixel(frame.format()) == 4 {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the frame isn't 8-bit BGRA",
+        ))
+    }
+}
+
+/// Asks `IDXGIOutput6` for the output's color space. `output` may be null.
+unsafe fn output_color_space(output: *mut IDXGIOutput1) -> ColorSpace {
+    let mut output6: *mut IDXGIOutput6 = ptr::null_mut();
+    if output.is_null()
+        || (*output).QueryInterface(
+            &IID_IDXGIOUTPUT6,
+            &mut output6 as *mut *mut _ as *mut *mut _,
+        ) != S_OK
+    {
+        return ColorSpace::Unknown;
+    }
+
+    let mut desc: DXGI_OUTPUT_DESC1 = mem::zeroed();
+    let res = (*output6).GetDesc1(&mut desc);
+    (*output6).Release();
+    if res == S_OK {
+        desc.ColorSpace.into()
+    } else {
+        ColorSpace::Unknown
+    }
+}
+
+fn bytes_per_pixel(format: DXGI_FORMAT) -> usize {
+    PixelFormat::from(format).bytes_per_pixel()
+}
+
+/// Takes the size of an output duplicated again from its `coords`. If it
+/// changed, sets `resized` and forgets the `activity`, which was in the old
+/// size's coordinates, and returns the old size.
+fn take_size(
+    coords: RECT,
+    size: (&mut usize, &mut usize),
+    resized: &mut bool,
+    activity: &mut Option<ActivityTracker>,
+) -> Option<(usize, usize)> {
+    let (width, height) = (
+        (coords.right - coords.left) as usize,
+        (coords.bottom - coords.top) as usize,
+    );
+    let old = (*size.0, *size.1);
+    if (width, height) == old {
+        return None;
+    }
+    *size.0 = width;
+    *size.1 = height;
+    *resized = true;
+    if let Some(ref mut activity) = *activity {
+        activity.reset();
+    }
+    Some(old)
+}
+
+/// The output's GDI device name, without the terminating nul.
+fn device_name(desc: &DXGI_OUTPUT_DESC) -> &[u16] {
+    let s = &desc.DeviceName;
+    let i = s.iter().position(|&x| x == 0).unwrap_or(s.len());
+    &s[..i]
+}
+
+/// Whether two outputs show the same content. See `Display::is_clone_of`.
+fn is_clone(a: &DXGI_OUTPUT_DESC, b: &DXGI_OUTPUT_DESC) -> bool {
+    same_placement(a, b) || device_name(a) == device_name(b)
+}
+
+/// Whether two outputs cover the same, non-empty part of the desktop.
+fn same_placement(a: &DXGI_OUTPUT_DESC, b: &DXGI_OUTPUT_DESC) -> bool {
+    let (a, b) = (a.DesktopCoordinates, b.DesktopCoordinates);
+    a.right > a.left
+        && a.bottom > a.top
+        && (a.left, a.top, a.right, a.bottom) == (b.left, b.top, b.right, b.bottom)
+}
+
+unsafe fn same_adapter(a: *mut IDXGIAdapter1, b: *mut IDXGIAdapter1) -> bool {
+    let mut a_desc: DXGI_ADAPTER_DESC1 = mem::zeroed();
+    let mut b_desc: DXGI_ADAPTER_DESC1 = mem::zeroed();
+    (*a).GetDesc1(&mut a_desc);
+    (*b).GetDesc1(&mut b_desc);
+
+    a_desc.AdapterLuid.LowPart == b_desc.AdapterLuid.LowPart
+        && a_desc.AdapterLuid.HighPart == b_desc.AdapterLuid.HighPart
+}
+
+/// Turns on multithread protection for a device context.
+unsafe fn protect(context: *mut ID3D11DeviceContext) -> bool {
+    let mut multithread: *mut ID3D11Multithread = ptr::null_mut();
+    (*context).QueryInterface(
+        &IID_ID3D11MULTITHREAD,
+        &mut multithread as *mut *mut _ as *mut *mut _,
+    );
+
+    if multithread.is_null() {
+        return false;
+    }
+
+    (*multithread).SetMultithreadProtected(TRUE);
+    (*multithread).Release();
+    true
+}
+
+/// Like `wrap_hresult`, but logs failures other than timeouts.
+fn check(stage: &'static str, x: HRESULT) -> io::Result<()> {
+    if x == S_OK || x == DXGI_ERROR_WAIT_TIMEOUT {
+        return wrap_hresult(x);
+    }
+
+    let error = HresultError { stage, code: x };
+    warn!("{}", error);
+    wrap_hresult(x).map_err(|err| io::Error::new(err.kind(), error))
+}
+
 fn wrap_hresult(x: HRESULT) -> io::Result<()> {
     use std::io::ErrorKind::*;
     Err((match x {
@@ -627,3 +3756,353 @@ fn wrap_hresult(x: HRESULT) -> io::Result<()> {
     })
     .into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_info(present: i64, mouse: i64, accumulated: u32) -> FrameInfo {
+        unsafe {
+            let mut info: DXGI_OUTDUPL_FRAME_INFO = mem::zeroed();
+            *info.LastPresentTime.QuadPart_mut() = present;
+            *info.LastMouseUpdateTime.QuadPart_mut() = mouse;
+            info.AccumulatedFrames = accumulated;
+            FrameInfo::from_raw(&info)
+        }
+    }
+
+    #[test]
+    fn new_pixels_or_just_the_pointer() {
+        // Presented, presented with the pointer moving, pointer only, and
+        // neither, e.g. a frame with just metadata.
+        let cases = [
+            (frame_info(10, 0, 1), true, false),
+            (frame_info(10, 12, 2), true, false),
+            (frame_info(0, 12, 0), false, true),
+            (frame_info(0, 0, 0), false, false),
+        ];
+        for &(info, updated, pointer_only) in &cases {
+            assert_eq!(info.desktop_updated(), updated, "{:?}", info);
+            assert_eq!(info.pointer_only(), pointer_only, "{:?}", info);
+        }
+    }
+
+    /// The framebuffer pixels painted for a 2 by 2 pointer with its hot spot
+    /// at `position`, on a display shown 3 by 2 at (1920, -500) and turned
+    /// by `rotation`.
+    fn painted(rotation: Rotation, position: (i32, i32)) -> Vec<(usize, usize)> {
+        let output = Rect::new(1920, -500, 3, 2);
+        let (x, y) = cursor::cursor_origin(position, (0, 0), output, output);
+        let buffer_width = if rotation.is_sideways() { 2 } else { 3 };
+        let canvas = Canvas {
+            region: output,
+            size: (3, 2),
+            rotation,
+            flip: (false, false),
+            pitch: buffer_width * 4,
+        };
+        let mut frame = vec![0; 6 * 4];
+        let mut painted = Vec::new();
+        canvas.paint(&mut frame, 4, Rect::new(x, y, 2, 2), |_, index, _, _| {
+            painted.push((index % canvas.pitch / 4, index / canvas.pitch));
+        });
+        painted
+    }
+
+    #[test]
+    fn cursor_on_rotated_displays() {
+        // The rotation, the pointer's position and the pixels painted.
+        type Case = (Rotation, (i32, i32), &'static [(usize, usize)]);
+        let cases: &[Case] = &[
+            // Fully inside.
+            (
+                Rotation::Identity,
+                (0, 0),
+                &[(0, 0), (1, 0), (0, 1), (1, 1)],
+            ),
+            (
+                Rotation::Rotate90,
+                (0, 0),
+                &[(1, 0), (1, 1), (0, 0), (0, 1)],
+            ),
+            (
+                Rotation::Rotate180,
+                (0, 0),
+                &[(2, 1), (1, 1), (2, 0), (1, 0)],
+            ),
+            (
+                Rotation::Rotate270,
+                (0, 0),
+                &[(0, 2), (0, 1), (1, 2), (1, 1)],
+            ),
+            // Clipped at the bottom right corner as shown.
+            (Rotation::Identity, (2, 1), &[(2, 1)]),
+            (Rotation::Rotate90, (2, 1), &[(0, 2)]),
+            (Rotation::Rotate180, (2, 1), &[(0, 0)]),
+            (Rotation::Rotate270, (2, 1), &[(1, 0)]),
+            // Clipped at the top left corner as shown.
+            (Rotation::Identity, (-1, -1), &[(0, 0)]),
+            (Rotation::Rotate90, (-1, -1), &[(1, 0)]),
+            (Rotation::Rotate180, (-1, -1), &[(2, 1)]),
+            (Rotation::Rotate270, (-1, -1), &[(0, 2)]),
+            // Entirely off the display.
+            (Rotation::Rotate90, (3, 0), &[]),
+            (Rotation::Rotate270, (0, -2), &[]),
+        ];
+        for &(rotation, position, expected) in cases {
+            assert_eq!(
+                painted(rotation, position),
+                expected,
+                "{:?} at {:?}",
+                rotation,
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn color_cursor_leaves_row_padding_alone() {
+        // A 3 by 2 frame with rows 16 bytes apart, so each row ends in 4
+        // bytes of padding, and a 2 by 2 color pointer at (1, 0): opaque,
+        // half covering, clear and opaque.
+        let canvas = Canvas {
+            region: Rect::new(0, 0, 3, 2),
+            size: (3, 2),
+            rotation: Rotation::Identity,
+            flip: (false, false),
+            pitch: 16,
+        };
+        #[rustfmt::skip]
+        let shape: [u8; 16] = [
+            10, 20, 30, 255,   200, 100, 0, 128,
+            90, 90, 90, 0,     1, 2, 3, 255,
+        ];
+        let mut frame = vec![64; 2 * 16];
+        canvas.paint(
+            &mut frame,
+            4,
+            Rect::new(1, 0, 2, 2),
+            |frame, index, x, y| {
+                cursor::draw_color_cursor(
+                    frame,
+                    index,
+                    &shape,
+                    y * 8 + x * 4,
+                    cursor::blend_straight,
+                );
+            },
+        );
+
+        let mut expected = vec![64; 2 * 16];
+        expected[4..8].copy_from_slice(&[10, 20, 30, 255]);
+        expected[8..12].copy_from_slice(&[132, 82, 31, 255]);
+        expected[24..28].copy_from_slice(&[1, 2, 3, 255]);
+        assert_eq!(frame, expected);
+        assert_eq!(&frame[12..16], &[64; 4]);
+        assert_eq!(&frame[28..32], &[64; 4]);
+    }
+
+    /// The pointer as output `number` showed it at `position`.
+    fn pointer(number: u32, position: (i32, i32), time_stamp: i64) -> CursorInfo {
+        let mut info = CursorInfo::new();
+        info.position = position;
+        info.visible = true;
+        info.who_updated_position_last = number;
+        info.last_time_stamp = time_stamp;
+        info
+    }
+
+    #[test]
+    fn who_takes_the_pointer() {
+        let last = pointer(1, (5, 5), 100);
+        // The output that has it can move or hide it.
+        assert!(takes_pointer(&last, 1, true, 50));
+        assert!(takes_pointer(&last, 1, false, 150));
+        // Others can't hide it, or take it with an older update.
+        assert!(!takes_pointer(&last, 2, false, 150));
+        assert!(!takes_pointer(&last, 2, true, 50));
+        assert!(takes_pointer(&last, 2, true, 150));
+
+        // Once it's hidden, any output showing it takes it.
+        let mut hidden = last.clone();
+        hidden.visible = false;
+        assert!(takes_pointer(&hidden, 2, true, 50));
+    }
+
+    #[test]
+    fn shared_pointer_moves_between_outputs() {
+        let (left, right) = (Rect::new(-1920, 0, 1920, 1080), Rect::new(0, 0, 1920, 1080));
+        let mut shared = CursorInfo::new();
+
+        // The left output sees the pointer and a shape.
+        let mut a = pointer(1, (100, 50), 10);
+        a.shape = vec![1, 2, 3, 4];
+        a.kind = Some(CursorShapeKind::Color);
+        a.shape_valid = true;
+        a.shape_generation = 1;
+        share_cursor(&mut shared, &mut a, (true, true), left);
+        assert_eq!(shared.position, (-1820, 50));
+        assert_eq!(shared.shape_generation, 1);
+
+        // The right output hasn't seen either.
+        let mut b = CursorInfo::new();
+        follow_cursor(&mut b, &shared, 2, right);
+        assert_eq!(b.position, (-1820, 50));
+        assert!(!b.visible);
+        assert_eq!(b.shape, [1, 2, 3, 4]);
+        assert_eq!(b.shape_generation, 1);
+        assert!(b.updated);
+
+        // Then the pointer moves over, with the same shape.
+        b.updated = false;
+        b.position = (30, 40);
+        b.visible = true;
+        b.who_updated_position_last = 2;
+        b.last_time_stamp = 20;
+        share_cursor(&mut shared, &mut b, (true, false), right);
+        assert_eq!(shared.position, (30, 40));
+
+        a.updated = false;
+        follow_cursor(&mut a, &shared, 1, left);
+        assert_eq!(a.position, (1950, 40));
+        assert!(!a.visible);
+        assert!(a.updated);
+
+        // A new shape from the right output goes to the next generation.
+        b.shape = vec![5, 6, 7, 8];
+        b.shape_generation += 1;
+        share_cursor(&mut shared, &mut b, (false, true), right);
+        assert_eq!(shared.shape_generation, 2);
+        follow_cursor(&mut a, &shared, 1, left);
+        assert_eq!(a.shape, [5, 6, 7, 8]);
+        assert_eq!(a.shape_generation, 2);
+    }
+
+    #[test]
+    fn resizing_after_recreation() {
+        let (mut width, mut height, mut resized) = (1920, 1080, false);
+        let mut activity = Some(ActivityTracker::new(Activity::default()));
+        let changed = Rect::new(10, 10, 100, 100);
+        activity.as_mut().unwrap().record(&[changed]);
+        let active = |activity: &Option<ActivityTracker>| {
+            activity.as_ref().unwrap().bounds(Duration::from_secs(60))
+        };
+        let rect = |right, bottom| RECT {
+            left: 1920,
+            top: 0,
+            right,
+            bottom,
+        };
+
+        // Duplicating again in the same mode changes nothing.
+        let same = take_size(
+            rect(3840, 1080),
+            (&mut width, &mut height),
+            &mut resized,
+            &mut activity,
+        );
+        assert_eq!(same, None);
+        assert_eq!((width, height, resized), (1920, 1080, false));
+        assert_eq!(active(&activity), Some(changed));
+
+        // A new mode takes the new size and starts the activity over.
+        let old = take_size(
+            rect(3200, 1024),
+            (&mut width, &mut height),
+            &mut resized,
+            &mut activity,
+        );
+        assert_eq!(old, Some((1920, 1080)));
+        assert_eq!((width, height, resized), (1280, 1024, true));
+        assert_eq!(active(&activity), None);
+
+        // Without activity tracking, only the size changes.
+        let mut none = None;
+        resized = false;
+        take_size(
+            rect(3840, 1080),
+            (&mut width, &mut height),
+            &mut resized,
+            &mut none,
+        );
+        assert_eq!((width, height, resized), (1920, 1080, true));
+    }
+
+    #[test]
+    fn displays_can_change_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Display>();
+        assert_send::<Displays>();
+        assert_send::<Capturer>();
+    }
+
+    /// An output named `\\.\DISPLAY<number>` covering `rect`.
+    fn output_desc(number: u8, rect: (i32, i32, i32, i32)) -> DXGI_OUTPUT_DESC {
+        let mut desc: DXGI_OUTPUT_DESC = unsafe { mem::zeroed() };
+        let name = format!("\\\\.\\DISPLAY{}", number);
+        for (dst, src) in desc.DeviceName.iter_mut().zip(name.encode_utf16()) {
+            *dst = src;
+        }
+        let (left, top, right, bottom) = rect;
+        desc.DesktopCoordinates = RECT {
+            left,
+            top,
+            right,
+            bottom,
+        };
+        desc
+    }
+
+    #[test]
+    fn clones_share_a_rect_or_a_name() {
+        let laptop = output_desc(1, (0, 0, 1920, 1080));
+        assert_eq!(
+            device_name(&laptop),
+            "\\\\.\\DISPLAY1".encode_utf16().collect::<Vec<_>>()
+        );
+        // A projector mirroring it, and one extending the desktop.
+        assert!(is_clone(&laptop, &output_desc(2, (0, 0, 1920, 1080))));
+        assert!(!is_clone(&laptop, &output_desc(2, (1920, 0, 3840, 1080))));
+        // Overlapping isn't enough.
+        assert!(!is_clone(&laptop, &output_desc(2, (960, 0, 2880, 1080))));
+        // The same GDI source, wherever DXGI puts it.
+        assert!(is_clone(&laptop, &output_desc(1, (1920, 0, 3840, 1080))));
+        // Outputs that aren't on the desktop are all at 0,0 and empty.
+        let (a, b) = (output_desc(3, (0, 0, 0, 0)), output_desc(4, (0, 0, 0, 0)));
+        assert!(!is_clone(&a, &b));
+        assert!(is_clone(&a, &a));
+    }
+
+    #[test]
+    fn saved_pointer_survives_a_new_capturer() {
+        // A 2 by 1 color shape, seen and drawn by the first capturer.
+        let mut old = pointer(1, (100, 50), 10);
+        old.shape = vec![1, 2, 3, 255, 4, 5, 6, 128];
+        old.shape_info.Width = 2;
+        old.shape_info.Height = 1;
+        old.shape_info.Pitch = 8;
+        old.shape_info.HotSpot.x = 1;
+        old.kind = Some(CursorShapeKind::Color);
+        old.shape_valid = true;
+        old.shape_generation = 3;
+        old.updated = true;
+        let before = old.state().shape.and_then(|shape| shape.to_bgra());
+        assert!(before.is_some());
+        let saved = SavedCursor(old.clone());
+
+        // The old one goes away and the new one has no shape until DXGI
+        // sends one, unless it's given the saved pointer.
+        drop(old);
+        let mut new = CursorInfo::new();
+        assert!(new.state().shape.is_none());
+        new.restore(saved);
+
+        let state = new.state();
+        assert_eq!(state.position, (100, 50));
+        assert!(state.visible);
+        assert_eq!(state.shape.map(|shape| shape.hot_spot), Some((1, 0)));
+        assert_eq!(state.shape.and_then(|shape| shape.to_bgra()), before);
+        assert_eq!(new.shape_generation, 3);
+        assert!(!new.updated);
+    }
+}
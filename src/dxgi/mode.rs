@@ -0,0 +1,195 @@
+use crate::convert::Rotation;
+use winapi::shared::dxgi1_2::DXGI_OUTDUPL_DESC;
+use winapi::shared::dxgiformat::{
+    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM,
+};
+use winapi::shared::dxgitype::{
+    DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709, DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020,
+    DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709, DXGI_COLOR_SPACE_TYPE, DXGI_MODE_DESC,
+    DXGI_MODE_ROTATION, DXGI_MODE_ROTATION_ROTATE180, DXGI_MODE_ROTATION_ROTATE270,
+    DXGI_MODE_ROTATION_ROTATE90, DXGI_MODE_SCANLINE_ORDER,
+};
+
+/// The display mode a duplication delivers frames in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Mode {
+    pub width: u32,
+    pub height: u32,
+    pub format: DXGI_FORMAT,
+    /// In hertz, as a fraction.
+    pub refresh_rate: (u32, u32),
+}
+
+impl Mode {
+    pub(crate) fn from_raw(desc: &DXGI_MODE_DESC) -> Mode {
+        Mode {
+            width: desc.Width,
+            height: desc.Height,
+            format: desc.Format,
+            refresh_rate: (desc.RefreshRate.Numerator, desc.RefreshRate.Denominator),
+        }
+    }
+}
+
+/// What a duplication delivers, from `DXGI_OUTDUPL_DESC`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DuplicationDesc {
+    /// The desktop image's size and format. On a display turned by 90 or
+    /// 270 degrees, the size is sideways.
+    pub mode: Mode,
+    pub scanline_ordering: DXGI_MODE_SCANLINE_ORDER,
+    /// How far the display is turned from the desktop image.
+    pub rotation: Rotation,
+    /// Whether the image is in system memory, so it's mapped directly
+    /// rather than copied through a staging texture.
+    pub in_system_memory: bool,
+}
+
+impl DuplicationDesc {
+    pub(crate) fn from_raw(desc: &DXGI_OUTDUPL_DESC) -> DuplicationDesc {
+        DuplicationDesc {
+            mode: Mode::from_raw(&desc.ModeDesc),
+            scanline_ordering: desc.ModeDesc.ScanlineOrdering,
+            rotation: desc.Rotation.into(),
+            in_system_memory: desc.DesktopImageInSystemMemory != 0,
+        }
+    }
+}
+
+/// How a frame's pixels are laid out.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum PixelFormat {
+    /// 8 bits per channel, blue first. What SDR displays give.
+    Bgra8,
+    /// 8 bits per channel, red first.
+    Rgba8,
+    /// 10 bits per color channel and 2 of alpha, red in the low bits.
+    Rgb10A2,
+    /// Linear scRGB as 16-bit floats, red first. What HDR displays give.
+    /// See `convert::to_sdr`.
+    Rgba16Float,
+    /// A format this version doesn't know about.
+    Unknown(u32),
+}
+
+impl PixelFormat {
+    /// How many bytes each pixel takes. Unknown formats are taken to be 32
+    /// bits, like all the others but FP16.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba16Float => 8,
+            _ => 4,
+        }
+    }
+
+    /// Sets the alpha of every pixel in `pixels` to opaque. Does nothing to
+    /// unknown formats.
+    pub(crate) fn make_opaque(self, pixels: &mut [u8]) {
+        match self {
+            PixelFormat::Bgra8 | PixelFormat::Rgba8 => {
+                for px in pixels.chunks_exact_mut(4) {
+                    px[3] = 0xff;
+                }
+            }
+            PixelFormat::Rgb10A2 => {
+                // Alpha is the top two bits.
+                for px in pixels.chunks_exact_mut(4) {
+                    px[3] |= 0xc0;
+                }
+            }
+            PixelFormat::Rgba16Float => {
+                // 1.0 as a half float.
+                for px in pixels.chunks_exact_mut(8) {
+                    px[6..].copy_from_slice(&0x3c00u16.to_le_bytes());
+                }
+            }
+            PixelFormat::Unknown(_) => {}
+        }
+    }
+}
+
+impl From<DXGI_FORMAT> for PixelFormat {
+    fn from(format: DXGI_FORMAT) -> PixelFormat {
+        match format {
+            DXGI_FORMAT_B8G8R8A8_UNORM => PixelFormat::Bgra8,
+            DXGI_FORMAT_R8G8B8A8_UNORM => PixelFormat::Rgba8,
+            DXGI_FORMAT_R10G10B10A2_UNORM => PixelFormat::Rgb10A2,
+            DXGI_FORMAT_R16G16B16A16_FLOAT => PixelFormat::Rgba16Float,
+            other => PixelFormat::Unknown(other),
+        }
+    }
+}
+
+/// The color space of a display or its frames.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub enum ColorSpace {
+    /// Couldn't be asked, before Windows 10 1803.
+    #[default]
+    Unknown,
+    /// Gamma 2.2 with BT.709 primaries. What SDR displays use.
+    Srgb,
+    /// Linear with BT.709 primaries, where 1.0 is 80 nits. What FP16 frames
+    /// hold.
+    ScRgb,
+    /// PQ with BT.2020 primaries. What HDR displays use.
+    Hdr10,
+    /// A color space this version doesn't know about.
+    Other(u32),
+}
+
+impl From<DXGI_COLOR_SPACE_TYPE> for ColorSpace {
+    fn from(space: DXGI_COLOR_SPACE_TYPE) -> ColorSpace {
+        match space {
+            DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709 => ColorSpace::Srgb,
+            DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709 => ColorSpace::ScRgb,
+            DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020 => ColorSpace::Hdr10,
+            other => ColorSpace::Other(other),
+        }
+    }
+}
+
+impl From<DXGI_MODE_ROTATION> for Rotation {
+    /// Unspecified counts as no rotation.
+    fn from(rotation: DXGI_MODE_ROTATION) -> Rotation {
+        match rotation {
+            DXGI_MODE_ROTATION_ROTATE90 => Rotation::Rotate90,
+            DXGI_MODE_ROTATION_ROTATE180 => Rotation::Rotate180,
+            DXGI_MODE_ROTATION_ROTATE270 => Rotation::Rotate270,
+            _ => Rotation::Identity,
+        }
+    }
+}
+
+/// The mode changed while the duplication was made again.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ModeChange {
+    pub old: Mode,
+    pub new: Mode,
+    /// A guess that an exclusive full-screen app was entered or left,
+    /// because one of the modes isn't the desktop's saved mode.
+    pub likely_fullscreen: bool,
+}
+
+impl ModeChange {
+    /// `None` if nothing changed. `desktop` is the size of the saved desktop
+    /// mode, if known.
+    pub(crate) fn new(old: Mode, new: Mode, desktop: Option<(u32, u32)>) -> Option<ModeChange> {
+        if old == new {
+            return None;
+        }
+
+        let likely_fullscreen = desktop.is_some_and(|desktop| {
+            (old.width, old.height) != (new.width, new.height)
+                && ((old.width, old.height) != desktop || (new.width, new.height) != desktop)
+        });
+
+        Some(ModeChange {
+            old,
+            new,
+            likely_fullscreen,
+        })
+    }
+}
@@ -0,0 +1,205 @@
+use super::{Frame, FrameLayout};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{mem, ops};
+use winapi::shared::dxgiformat::DXGI_FORMAT;
+
+/// Buffers are freed rather than kept once this many are waiting.
+const MAX_POOLED: usize = 4;
+
+/// Buffers given back by dropped `OwnedFrame`s, for the next ones.
+#[derive(Clone, Default)]
+pub(crate) struct FramePool(Arc<Mutex<Buffers>>);
+
+#[derive(Default)]
+struct Buffers {
+    spare: Vec<Vec<u8>>,
+    /// The bytes held by `OwnedFrame`s that are still around.
+    lent: usize,
+}
+
+impl FramePool {
+    /// A buffer of `len` bytes, reusing one that was given back if it can.
+    pub(crate) fn take(&self, len: usize) -> Vec<u8> {
+        let mut buffers = self.lock();
+        let mut buffer = buffers.spare.pop().unwrap_or_default();
+        buffer.resize(len, 0);
+        buffers.lent += buffer.capacity();
+        buffer
+    }
+
+    fn give_back(&self, buffer: Vec<u8>) {
+        let mut buffers = self.lock();
+        buffers.lent = buffers.lent.saturating_sub(buffer.capacity());
+        if buffers.spare.len() < MAX_POOLED {
+            buffers.spare.push(buffer);
+        }
+    }
+
+    /// Stops counting a buffer of `capacity` bytes that won't come back.
+    fn forget(&self, capacity: usize) {
+        let mut buffers = self.lock();
+        buffers.lent = buffers.lent.saturating_sub(capacity);
+    }
+
+    /// Frees the buffers waiting to be reused.
+    pub(crate) fn clear(&self) {
+        self.lock().spare = Vec::new();
+    }
+
+    /// The bytes held by frames that are still around and by buffers
+    /// waiting to be reused.
+    pub(crate) fn memory_usage(&self) -> usize {
+        let buffers = self.lock();
+        buffers.lent + buffers.spare.iter().map(|b| b.capacity()).sum::<usize>()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Buffers> {
+        // A panic can't leave the list half-changed.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A copy of a frame that outlives the capturer's next call, e.g. to send
+/// to another thread. Rows aren't padded.
+///
+/// Dropping it hands the buffer back to the capturer, so steady capture
+/// doesn't allocate.
+pub struct OwnedFrame {
+    data: Vec<u8>,
+    layout: FrameLayout,
+    number: u64,
+    pool: FramePool,
+}
+
+impl OwnedFrame {
+    pub(crate) fn new(
+        data: Vec<u8>,
+        layout: FrameLayout,
+        number: u64,
+        pool: FramePool,
+    ) -> OwnedFrame {
+        OwnedFrame {
+            data,
+            layout,
+            number,
+            pool,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.layout.height
+    }
+
+    /// Always the width times the bytes per pixel.
+    pub fn stride(&self) -> usize {
+        self.layout.stride
+    }
+
+    pub fn format(&self) -> DXGI_FORMAT {
+        self.layout.format
+    }
+
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    /// The capturer's `frame_number` for this frame, to put frames sent
+    /// different ways back in order.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// Borrows it as a `Frame`.
+    pub fn as_frame(&self) -> Frame<'_> {
+        let FrameLayout {
+            width,
+            height,
+            stride,
+            format,
+        } = self.layout;
+        Frame::new(&self.data, width, height, stride, format)
+    }
+
+    /// Each row's pixels.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.layout.stride)
+    }
+
+    /// Each row's pixels, for changing them in place.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        self.data.chunks_exact_mut(self.layout.stride)
+    }
+
+    /// Keeps the buffer instead of handing it back.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        let data = mem::take(&mut self.data);
+        self.pool.forget(data.capacity());
+        data
+    }
+}
+
+impl ops::Deref for OwnedFrame {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for OwnedFrame {
+    fn drop(&mut self) {
+        let data = mem::take(&mut self.data);
+        if data.capacity() > 0 {
+            self.pool.give_back(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winapi::shared::dxgiformat::DXGI_FORMAT_B8G8R8A8_UNORM;
+
+    fn frame(pool: &FramePool, len: usize) -> OwnedFrame {
+        let layout = FrameLayout {
+            width: len / 4,
+            height: 1,
+            stride: len,
+            format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        };
+        OwnedFrame::new(pool.take(len), layout, 0, pool.clone())
+    }
+
+    #[test]
+    fn lent_and_spare_buffers_count() {
+        let pool = FramePool::default();
+        let first = frame(&pool, 64);
+        let second = frame(&pool, 32);
+        let lent = first.data.capacity() + second.data.capacity();
+        assert_eq!(pool.memory_usage(), lent);
+
+        // Given back, but still held for the next frame.
+        drop(first);
+        assert_eq!(pool.memory_usage(), lent);
+
+        let third = frame(&pool, 16);
+        assert!(third.data.capacity() >= 64);
+        assert_eq!(pool.memory_usage(), lent);
+
+        drop(second);
+        assert_eq!(pool.memory_usage(), lent);
+        pool.clear();
+        assert_eq!(pool.memory_usage(), third.data.capacity());
+    }
+
+    #[test]
+    fn kept_buffers_stop_counting() {
+        let pool = FramePool::default();
+        let data = frame(&pool, 64).into_vec();
+        assert_eq!(data.len(), 64);
+        assert_eq!(pool.memory_usage(), 0);
+    }
+}
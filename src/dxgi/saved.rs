@@ -0,0 +1,239 @@
+use super::{display_config, Capturer, Config, DeviceFlags, Display, Displays};
+use std::error::Error;
+use std::{fmt, io};
+
+/// Identifies a display across launches.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayId {
+    /// The monitor's device interface path, which survives reboots and
+    /// reconnects. Empty if Windows wouldn't say.
+    pub monitor: String,
+    /// The GDI device name, like `\\.\DISPLAY1`. This can change whenever
+    /// monitors are plugged in or out.
+    pub name: String,
+}
+
+impl Display {
+    pub fn id(&self) -> DisplayId {
+        let monitor = unsafe { display_config::find_path(&self.desc.DeviceName) }
+            .and_then(|path| unsafe { display_config::monitor_path(&path) });
+        DisplayId {
+            monitor: monitor.unwrap_or_default(),
+            name: String::from_utf16_lossy(self.name()),
+        }
+    }
+}
+
+impl DisplayId {
+    fn matches(&self, other: &DisplayId) -> bool {
+        if self.monitor.is_empty() || other.monitor.is_empty() {
+            self.name == other.name
+        } else {
+            self.monitor == other.monitor
+        }
+    }
+}
+
+/// Everything needed to start the same capture again later.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CapturerOptions {
+    pub display: DisplayId,
+    pub capture_mouse: bool,
+    pub config: Config,
+}
+
+/// Why `Capturer::from_saved` failed.
+#[derive(Debug)]
+pub enum RestoreError {
+    /// The saved display isn't connected anymore. The displays that are,
+    /// most likely replacement first.
+    DisplayMissing { candidates: Vec<DisplayId> },
+    /// The options can't work on any display.
+    Invalid(&'static str),
+    /// The display is there, but capturing it failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RestoreError::DisplayMissing { .. } => f.write_str("the saved display is missing"),
+            RestoreError::Invalid(reason) => write!(f, "invalid capture options: {}", reason),
+            RestoreError::Io(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for RestoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RestoreError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RestoreError {
+    fn from(err: io::Error) -> RestoreError {
+        RestoreError::Io(err)
+    }
+}
+
+impl Capturer {
+    /// Starts a capture from saved options.
+    pub fn from_saved(options: &CapturerOptions) -> Result<Capturer, RestoreError> {
+        let flags = options.config.device_flags;
+        if !DeviceFlags::all().contains(flags) {
+            return Err(RestoreError::Invalid("unknown device flags"));
+        }
+        if flags.contains(DeviceFlags::SINGLETHREADED) && options.config.multithread_protected {
+            return Err(RestoreError::Invalid(
+                "a single-threaded device can't be multithread protected",
+            ));
+        }
+
+        let mut candidates = Vec::new();
+        for display in Displays::new()? {
+            let id = display.id();
+            if options.display.matches(&id) {
+                return Ok(Capturer::with_config(
+                    &display,
+                    options.capture_mouse,
+                    options.config.clone(),
+                )?);
+            }
+            candidates.push(id);
+        }
+
+        // Whatever took over the old GDI name is the best guess.
+        candidates.sort_by_key(|id| id.name != options.display.name);
+        Err(RestoreError::DisplayMissing { candidates })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(monitor: &str, name: &str) -> DisplayId {
+        DisplayId {
+            monitor: monitor.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn display_ids_match_by_monitor_then_name() {
+        let saved = id("\\\\?\\DISPLAY#DEL4321#1", "\\\\.\\DISPLAY1");
+        // The same monitor, renumbered after a reconnect.
+        assert!(saved.matches(&id(&saved.monitor, "\\\\.\\DISPLAY3")));
+        // Another monitor that took over the name.
+        assert!(!saved.matches(&id("\\\\?\\DISPLAY#SAM0001#2", &saved.name)));
+        // Without a monitor path on either side, only the name is left.
+        assert!(saved.matches(&id("", &saved.name)));
+        assert!(!saved.matches(&id("", "\\\\.\\DISPLAY2")));
+        assert!(!id("", &saved.name).matches(&id("", "\\\\.\\DISPLAY2")));
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::*;
+    use crate::dxgi::{ClickHighlight, CursorBlend, SharedSync, UnknownShapePolicy};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn options() -> CapturerOptions {
+        CapturerOptions {
+            display: DisplayId {
+                monitor: "monitor".to_owned(),
+                name: "\\\\.\\DISPLAY2".to_owned(),
+            },
+            capture_mouse: true,
+            config: Config {
+                device_flags: DeviceFlags::BGRA_SUPPORT | DeviceFlags::VIDEO_SUPPORT,
+                unknown_shape: UnknownShapePolicy::Warn,
+                cursor_blend: CursorBlend::Auto,
+                click_highlight: Some(ClickHighlight {
+                    duration: Duration::from_millis(250),
+                    ..ClickHighlight::default()
+                }),
+                to_srgb: true,
+                shared_sync: SharedSync::Fence,
+                max_buffer_memory: Some(1 << 20),
+                ..Config::default()
+            },
+        }
+    }
+
+    #[test]
+    fn options_keep_their_shape() {
+        // Saved options outlive the version that wrote them, so the field
+        // names and encodings can't change.
+        let value = serde_json::to_value(options()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "display": {
+                    "monitor": "monitor",
+                    "name": "\\\\.\\DISPLAY2",
+                },
+                "capture_mouse": true,
+                "config": {
+                    "device_flags": 0x820,
+                    "multithread_protected": false,
+                    "unknown_shape": "Warn",
+                    "cursor_blend": "Auto",
+                    "cursor_fallback": false,
+                    "click_highlight": {
+                        "radius": 24,
+                        "color": [0x00, 0xFF, 0xFF, 0x80],
+                        "right_color": [0x00, 0x00, 0xFF, 0x80],
+                        "duration": { "secs": 0, "nanos": 250_000_000 },
+                        "poll_buttons": true,
+                    },
+                    "hdr": false,
+                    "opaque_alpha": false,
+                    "flip_vertical": false,
+                    "flip_horizontal": false,
+                    "to_srgb": true,
+                    "shared_sync": "Fence",
+                    "max_buffer_memory": 1048576,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let json = serde_json::to_string(&options()).unwrap();
+        let restored: CapturerOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.display, options().display);
+        assert!(restored.capture_mouse);
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+        assert!(!restored.config.cancel.is_cancelled());
+    }
+
+    #[test]
+    fn missing_config_fields_are_defaults() {
+        let restored: CapturerOptions = serde_json::from_value(json!({
+            "display": { "monitor": "", "name": "\\\\.\\DISPLAY1" },
+            "capture_mouse": false,
+            "config": { "hdr": true, "click_highlight": { "radius": 10 } },
+        }))
+        .unwrap();
+        let config = restored.config;
+        assert!(config.hdr);
+        assert_eq!(config.device_flags, DeviceFlags::empty());
+        assert_eq!(config.shared_sync, SharedSync::KeyedMutex);
+        assert_eq!(
+            config.click_highlight,
+            Some(ClickHighlight {
+                radius: 10,
+                ..ClickHighlight::default()
+            })
+        );
+    }
+}
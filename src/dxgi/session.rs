@@ -0,0 +1,73 @@
+use super::{create_device, is_on_adapter, Capturer, Config, CursorInfo, Display, SharedCursor};
+use std::io;
+use std::sync::{Arc, Mutex};
+use winapi::um::d3d11::ID3D11Device;
+
+/// Captures several outputs with one device per adapter.
+///
+/// Capturers from the same session share their adapter's device and
+/// immediate context, which is multithread protected so they can live on
+/// different threads. Each capturer holds its own reference to the device,
+/// so the session may be dropped first.
+///
+/// They share the pointer too. DXGI reports the pointer to the output it's
+/// on, and only sends its shape when it changes, so on its own a capturer
+/// can be left with no shape, or an old one, when the pointer comes over
+/// from another output. In a session, each capturer's cursor is visible
+/// only while the output last to move the pointer is its own, and a shape
+/// received by any of them is drawn by all.
+pub struct CaptureSession {
+    config: Config,
+    devices: Vec<*mut ID3D11Device>,
+    cursor: SharedCursor,
+}
+
+impl CaptureSession {
+    /// `config.multithread_protected` is always turned on.
+    pub fn new(config: Config) -> CaptureSession {
+        CaptureSession {
+            config: Config {
+                multithread_protected: true,
+                ..config
+            },
+            devices: Vec::new(),
+            cursor: Arc::new(Mutex::new(CursorInfo::new())),
+        }
+    }
+
+    /// Starts capturing a display, reusing the device of its adapter if this
+    /// session already has one.
+    pub fn capturer(&mut self, display: &Display, capture_mouse: bool) -> io::Result<Capturer> {
+        let existing = self
+            .devices
+            .iter()
+            .find(|&&device| unsafe { is_on_adapter(device, display.adapter) });
+
+        let device = match existing {
+            Some(&device) => device,
+            None => unsafe {
+                let (device, context, _) =
+                    create_device(display.adapter, self.config.device_flags)?;
+                (*context).Release();
+                self.devices.push(device);
+                device
+            },
+        };
+
+        let mut capturer =
+            unsafe { Capturer::with_device(display, capture_mouse, device, self.config.clone())? };
+        capturer.output_number = (display.adapter_index << 16) | display.output_index;
+        capturer.shared_cursor = Some(self.cursor.clone());
+        Ok(capturer)
+    }
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        for &device in &self.devices {
+            unsafe {
+                (*device).Release();
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use super::{Capturer, Frame};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use winapi::shared::minwindef::UINT;
+
+/// One capturer used from several places, e.g. a preview and a recorder.
+///
+/// Outputs only allow a few duplications at a time, so within a process it's
+/// better to share one than to open another. Clones are handles to the same
+/// capturer, and calls from different handles take turns.
+///
+/// Each frame goes to the handle that asked for it. DXGI only reports
+/// changes, so when several handles poll, each sees a complete image but
+/// not necessarily every update.
+#[derive(Clone)]
+pub struct SharedCapturer(Arc<Mutex<Capturer>>);
+
+impl SharedCapturer {
+    pub fn new(capturer: Capturer) -> SharedCapturer {
+        SharedCapturer(Arc::new(Mutex::new(capturer)))
+    }
+
+    /// Waits for a frame like `Capturer::frame` and hands it to `f`.
+    /// Other handles wait until `f` returns.
+    pub fn with_frame<T, F>(&self, timeout: UINT, f: F) -> io::Result<T>
+    where
+        F: FnOnce(Frame<'_>) -> T,
+    {
+        let mut capturer = self.lock();
+        capturer.frame(timeout).map(f)
+    }
+
+    /// Gives `f` the capturer itself, e.g. for its metadata or settings.
+    pub fn with_capturer<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Capturer) -> T,
+    {
+        f(&mut self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Capturer> {
+        // A panic mid-frame leaves nothing half-updated that matters here.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
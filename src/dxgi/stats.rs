@@ -0,0 +1,197 @@
+use super::is_deadline_exceeded;
+use crate::is_cancelled;
+use std::io;
+use std::time::Duration;
+use winapi::shared::dxgiformat::DXGI_FORMAT;
+
+/// Running totals for a capturer, from `Capturer::stats`.
+///
+/// Counts are since the capturer was created or `reset_stats` was called.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaptureStats {
+    /// Frames returned by `frame`.
+    pub frames: u64,
+    /// Frames whose desktop image changed, i.e. that weren't repeats.
+    pub changed_frames: u64,
+    /// Desktop updates that were folded into a later frame rather than
+    /// delivered, i.e. `accumulated_frames - 1` summed over acquired frames.
+    /// Updates while the duplication was lost aren't known, so not counted.
+    pub missed_frames: u64,
+    /// Calls that timed out without a frame.
+    pub timeouts: u64,
+    /// Calls that failed with an error other than a timeout.
+    pub errors: u64,
+    /// The latest of those errors.
+    pub last_error: Option<String>,
+    /// How many times the duplication was lost and made again.
+    pub recreations: u64,
+    pub deadlines_exceeded: u64,
+    pub cursor_shape_updates: u64,
+    pub unknown_shapes: u64,
+    pub invalid_shapes: u64,
+    pub width: usize,
+    pub height: usize,
+    pub format: DXGI_FORMAT,
+    /// Mean time spent waiting for a frame, including timeouts.
+    pub average_acquire: Duration,
+    /// Mean time spent copying a frame to the staging texture.
+    pub average_copy: Duration,
+    /// Mean time spent mapping a frame for reading.
+    pub average_map: Duration,
+}
+
+/// What a capturer counts as it goes.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Counters {
+    pub frames: u64,
+    pub changed_frames: u64,
+    pub missed_frames: u64,
+    pub timeouts: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+    pub recreations: u64,
+    pub deadlines_exceeded: u64,
+    pub cursor_shape_updates: u64,
+    pub unknown_shapes: u64,
+    pub invalid_shapes: u64,
+    pub acquire: Timer,
+    pub copy: Timer,
+    pub map: Timer,
+}
+
+impl Counters {
+    /// Counts how a capture went, `changed` saying whether a frame's image
+    /// was new. Returns whether there was a frame, to be numbered. Deadlines
+    /// and cancellations were asked for, so aren't counted as anything.
+    pub fn count(&mut self, result: &io::Result<()>, changed: bool) -> bool {
+        match *result {
+            Ok(()) => {
+                self.frames += 1;
+                if changed {
+                    self.changed_frames += 1;
+                }
+                return true;
+            }
+            Err(ref err) if is_deadline_exceeded(err) || is_cancelled(err) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => self.timeouts += 1,
+            Err(ref err) => {
+                self.errors += 1;
+                self.last_error = Some(err.to_string());
+            }
+        }
+        false
+    }
+
+    /// Counts the updates folded into a frame of `accumulated` updates.
+    /// Pointer-only frames have none.
+    pub fn count_accumulated(&mut self, accumulated: u32) {
+        self.missed_frames += accumulated.saturating_sub(1) as u64;
+    }
+
+    /// Counts the duplication being made again after access was lost. The
+    /// other totals carry on across it.
+    pub fn count_recreation(&mut self) {
+        self.recreations += 1;
+    }
+
+    /// Zeroes everything, for `Capturer::reset_stats`.
+    pub fn reset(&mut self) {
+        *self = Counters::default();
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct Timer {
+    total: Duration,
+    count: u32,
+}
+
+impl Timer {
+    pub fn add(&mut self, time: Duration) {
+        self.total += time;
+        self.count = self.count.saturating_add(1);
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            Duration::default()
+        } else {
+            self.total / self.count
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dxgi::DeadlineExceeded;
+    use crate::Cancelled;
+
+    #[test]
+    fn only_frames_are_numbered() {
+        let mut counters = Counters::default();
+        let timeout = || Err(io::ErrorKind::TimedOut.into());
+        let results: Vec<io::Result<()>> = vec![
+            Ok(()),
+            timeout(),
+            Ok(()),
+            Err(DeadlineExceeded.into()),
+            Err(Cancelled.into()),
+            Err(io::Error::new(io::ErrorKind::ConnectionReset, "lost")),
+            timeout(),
+            Ok(()),
+        ];
+        let numbers: Vec<u64> = results
+            .iter()
+            .scan(0, |number, result| {
+                if counters.count(result, true) {
+                    *number += 1;
+                }
+                Some(*number)
+            })
+            .collect();
+        assert_eq!(numbers, [1, 1, 2, 2, 2, 2, 2, 3]);
+        assert_eq!(counters.frames, 3);
+        assert_eq!(counters.timeouts, 2);
+        assert_eq!(counters.errors, 1);
+        assert_eq!(counters.last_error.as_deref(), Some("lost"));
+    }
+
+    #[test]
+    fn missed_frames_are_the_folded_updates() {
+        let mut counters = Counters::default();
+        for &accumulated in &[1, 3, 0, 1, 5] {
+            counters.count_accumulated(accumulated);
+        }
+        assert_eq!(counters.missed_frames, 6);
+    }
+
+    #[test]
+    fn repeats_arent_changed_frames() {
+        let mut counters = Counters::default();
+        assert!(counters.count(&Ok(()), true));
+        assert!(counters.count(&Ok(()), false));
+        assert_eq!((counters.frames, counters.changed_frames), (2, 1));
+    }
+
+    #[test]
+    fn missed_frames_survive_recreation() {
+        let mut counters = Counters::default();
+        counters.count(&Ok(()), true);
+        counters.count_accumulated(4);
+        // Access is lost, the duplication made again and capture goes on.
+        counters.count(&Err(io::ErrorKind::ConnectionReset.into()), false);
+        counters.count_recreation();
+        counters.count(&Ok(()), true);
+        counters.count_accumulated(2);
+        assert_eq!(counters.missed_frames, 4);
+        assert_eq!(counters.recreations, 1);
+        assert_eq!(counters.frames, 2);
+
+        counters.reset();
+        assert_eq!(counters.missed_frames, 0);
+        assert_eq!(counters.recreations, 0);
+        assert_eq!(counters.frames, 0);
+    }
+}
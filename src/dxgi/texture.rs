@@ -0,0 +1,63 @@
+use std::marker::PhantomData;
+use winapi::shared::dxgi1_2::IDXGIOutputDuplication;
+use winapi::shared::dxgiformat::DXGI_FORMAT;
+use winapi::um::d3d11::ID3D11Texture2D;
+
+/// The acquired desktop image itself, from `Capturer::frame_texture`.
+///
+/// The texture belongs to the capturer's device, `Capturer::d3d11_device`,
+/// and is only valid until this is dropped, which gives the frame back to
+/// DXGI. Until then the desktop image can't update, so don't hold on to it.
+pub struct FrameTexture<'a> {
+    texture: *mut ID3D11Texture2D,
+    duplication: *mut IDXGIOutputDuplication,
+    width: usize,
+    height: usize,
+    format: DXGI_FORMAT,
+    _capturer: PhantomData<&'a mut ()>,
+}
+
+impl<'a> FrameTexture<'a> {
+    /// Takes over a reference to `texture` and the acquired frame.
+    pub(crate) unsafe fn new(
+        texture: *mut ID3D11Texture2D,
+        duplication: *mut IDXGIOutputDuplication,
+    ) -> FrameTexture<'a> {
+        let mut desc = std::mem::zeroed();
+        (*texture).GetDesc(&mut desc);
+        FrameTexture {
+            texture,
+            duplication,
+            width: desc.Width as usize,
+            height: desc.Height as usize,
+            format: desc.Format,
+            _capturer: PhantomData,
+        }
+    }
+
+    /// Borrowed; `AddRef` it to keep it past this frame's drop.
+    pub fn texture(&self) -> *mut ID3D11Texture2D {
+        self.texture
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> DXGI_FORMAT {
+        self.format
+    }
+}
+
+impl<'a> Drop for FrameTexture<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.texture).Release();
+            (*self.duplication).ReleaseFrame();
+        }
+    }
+}
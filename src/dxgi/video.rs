@@ -0,0 +1,457 @@
+use super::check;
+use super::DeadlineExceeded;
+use std::error::Error;
+use std::time::Instant;
+use std::{fmt, io, mem, ptr, thread};
+use winapi::shared::dxgiformat::{DXGI_FORMAT, DXGI_FORMAT_NV12};
+use winapi::shared::dxgitype::{DXGI_RATIONAL, DXGI_SAMPLE_DESC};
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::{FALSE, TRUE, UINT};
+use winapi::shared::windef::RECT;
+use winapi::shared::winerror::DXGI_ERROR_WAS_STILL_DRAWING;
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::{D3D_FEATURE_LEVEL, D3D_FEATURE_LEVEL_10_0};
+
+const IID_ID3D11VIDEODEVICE: GUID = GUID {
+    Data1: 0x10ec4d5b,
+    Data2: 0x975a,
+    Data3: 0x4689,
+    Data4: [0xb9, 0xe4, 0xd0, 0xaa, 0xc3, 0x0f, 0xe3, 0x33],
+};
+
+const IID_ID3D11VIDEOCONTEXT: GUID = GUID {
+    Data1: 0x61f21c45,
+    Data2: 0x3c0e,
+    Data3: 0x4a74,
+    Data4: [0x9c, 0xea, 0x67, 0x10, 0x0d, 0x9a, 0xd5, 0xe4],
+};
+
+/// The error payload of `Capturer::frame_nv12`, or of frames scaled with
+/// `Capturer::set_output_size`, when the device can't do the conversion,
+/// e.g. because its feature level is below 10_0.
+/// Check for it with `is_video_processor_unsupported`, and convert on the
+/// CPU instead, e.g. with `Capturer::frame_yuv`.
+#[derive(Copy, Clone, Debug)]
+pub struct VideoProcessorUnsupported(pub &'static str);
+
+impl fmt::Display for VideoProcessorUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the video processor can't do the conversion: {}", self.0)
+    }
+}
+
+impl Error for VideoProcessorUnsupported {}
+
+impl From<VideoProcessorUnsupported> for io::Error {
+    fn from(unsupported: VideoProcessorUnsupported) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, unsupported)
+    }
+}
+
+/// Whether an error came from a device that can't do a GPU conversion.
+pub fn is_video_processor_unsupported(err: &io::Error) -> bool {
+    err.get_ref()
+        .is_some_and(|e| e.is::<VideoProcessorUnsupported>())
+}
+
+/// A frame from `Capturer::frame_nv12`.
+///
+/// The planes are rounded up to an even size, as NV12 needs. The extra row
+/// or column is black.
+pub struct Nv12Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    pitch: usize,
+}
+
+impl<'a> Nv12Frame<'a> {
+    pub(crate) fn new(data: &'a [u8], width: usize, height: usize, pitch: usize) -> Nv12Frame<'a> {
+        Nv12Frame {
+            data,
+            width,
+            height,
+            pitch,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// One byte per pixel, `height` rounded up to even rows.
+    pub fn y(&self) -> &'a [u8] {
+        &self.data[..self.pitch * even(self.height)]
+    }
+
+    /// Interleaved U and V, at half the resolution in each direction.
+    pub fn uv(&self) -> &'a [u8] {
+        &self.data[self.pitch * even(self.height)..]
+    }
+
+    pub fn y_pitch(&self) -> usize {
+        self.pitch
+    }
+
+    pub fn uv_pitch(&self) -> usize {
+        self.pitch
+    }
+}
+
+/// Converts and scales desktop images with the D3D11 video processor.
+pub(crate) struct VideoConverter {
+    video_device: *mut ID3D11VideoDevice,
+    video_context: *mut ID3D11VideoContext,
+    enumerator: *mut ID3D11VideoProcessorEnumerator,
+    processor: *mut ID3D11VideoProcessor,
+    /// A copy of the desktop image the processor can read from.
+    input: *mut ID3D11Texture2D,
+    input_view: *mut ID3D11VideoProcessorInputView,
+    output: *mut ID3D11Texture2D,
+    output_view: *mut ID3D11VideoProcessorOutputView,
+    staging: *mut ID3D11Texture2D,
+    source: D3D11_TEXTURE2D_DESC,
+    format: DXGI_FORMAT,
+    size: (u32, u32),
+    mapped: bool,
+}
+
+impl VideoConverter {
+    /// Fails unless a device at `level` is one the conversions are used
+    /// on. The video processor is there on some 9_x devices, but drivers
+    /// that old are too unreliable to be worth trying.
+    pub fn check_feature_level(level: D3D_FEATURE_LEVEL) -> io::Result<()> {
+        if level < D3D_FEATURE_LEVEL_10_0 {
+            return Err(VideoProcessorUnsupported("the feature level is below 10_0").into());
+        }
+        Ok(())
+    }
+
+    /// Sets up converting images like `source` to `format`, scaled to `size`.
+    /// NV12 textures are padded to an even size.
+    pub unsafe fn new(
+        device: *mut ID3D11Device,
+        context: *mut ID3D11DeviceContext,
+        source: &D3D11_TEXTURE2D_DESC,
+        format: DXGI_FORMAT,
+        size: (u32, u32),
+    ) -> io::Result<VideoConverter> {
+        // Fields start out null, so dropping a half-built one is fine.
+        let mut converter = VideoConverter {
+            video_device: ptr::null_mut(),
+            video_context: ptr::null_mut(),
+            enumerator: ptr::null_mut(),
+            processor: ptr::null_mut(),
+            input: ptr::null_mut(),
+            input_view: ptr::null_mut(),
+            output: ptr::null_mut(),
+            output_view: ptr::null_mut(),
+            staging: ptr::null_mut(),
+            source: *source,
+            format,
+            size,
+            mapped: false,
+        };
+
+        let res = (*device).QueryInterface(
+            &IID_ID3D11VIDEODEVICE,
+            &mut converter.video_device as *mut *mut _ as *mut *mut _,
+        );
+        if res < 0 {
+            return Err(VideoProcessorUnsupported("no video device").into());
+        }
+        let res = (*context).QueryInterface(
+            &IID_ID3D11VIDEOCONTEXT,
+            &mut converter.video_context as *mut *mut _ as *mut *mut _,
+        );
+        if res < 0 {
+            return Err(VideoProcessorUnsupported("no video context").into());
+        }
+
+        let (width, height) = (source.Width, source.Height);
+        let (out_width, out_height) = if format == DXGI_FORMAT_NV12 {
+            (even(size.0 as usize) as UINT, even(size.1 as usize) as UINT)
+        } else {
+            size
+        };
+        let rate = DXGI_RATIONAL {
+            Numerator: 60,
+            Denominator: 1,
+        };
+        let content = D3D11_VIDEO_PROCESSOR_CONTENT_DESC {
+            InputFrameFormat: D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE,
+            InputFrameRate: rate,
+            InputWidth: width,
+            InputHeight: height,
+            OutputFrameRate: rate,
+            OutputWidth: out_width,
+            OutputHeight: out_height,
+            Usage: D3D11_VIDEO_USAGE_OPTIMAL_SPEED,
+        };
+        let res = (*converter.video_device)
+            .CreateVideoProcessorEnumerator(&content, &mut converter.enumerator);
+        if res < 0 {
+            return Err(VideoProcessorUnsupported("no video processor").into());
+        }
+
+        let mut input_support = 0;
+        let mut output_support = 0;
+        (*converter.enumerator).CheckVideoProcessorFormat(source.Format, &mut input_support);
+        (*converter.enumerator).CheckVideoProcessorFormat(format, &mut output_support);
+        if input_support & D3D11_VIDEO_PROCESSOR_FORMAT_SUPPORT_INPUT == 0 {
+            return Err(VideoProcessorUnsupported("the desktop format isn't an input").into());
+        }
+        if output_support & D3D11_VIDEO_PROCESSOR_FORMAT_SUPPORT_OUTPUT == 0 {
+            return Err(VideoProcessorUnsupported("the output format isn't supported").into());
+        }
+
+        check(
+            "CreateVideoProcessor",
+            (*converter.video_device).CreateVideoProcessor(
+                converter.enumerator,
+                0,
+                &mut converter.processor,
+            ),
+        )?;
+
+        let texture = |format, w, h, usage, bind, cpu, out: &mut *mut ID3D11Texture2D| {
+            let desc = D3D11_TEXTURE2D_DESC {
+                Width: w,
+                Height: h,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: usage,
+                BindFlags: bind,
+                CPUAccessFlags: cpu,
+                MiscFlags: 0,
+            };
+            check(
+                "CreateTexture2D",
+                (*device).CreateTexture2D(&desc, ptr::null(), out),
+            )
+        };
+        texture(
+            source.Format,
+            width,
+            height,
+            D3D11_USAGE_DEFAULT,
+            D3D11_BIND_RENDER_TARGET,
+            0,
+            &mut converter.input,
+        )?;
+        texture(
+            format,
+            out_width,
+            out_height,
+            D3D11_USAGE_DEFAULT,
+            D3D11_BIND_RENDER_TARGET,
+            0,
+            &mut converter.output,
+        )?;
+        texture(
+            format,
+            out_width,
+            out_height,
+            D3D11_USAGE_STAGING,
+            0,
+            D3D11_CPU_ACCESS_READ,
+            &mut converter.staging,
+        )?;
+
+        let input_desc = D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC {
+            FourCC: 0,
+            ViewDimension: D3D11_VPIV_DIMENSION_TEXTURE2D,
+            Texture2D: D3D11_TEX2D_VPIV {
+                MipSlice: 0,
+                ArraySlice: 0,
+            },
+        };
+        check(
+            "CreateVideoProcessorInputView",
+            (*converter.video_device).CreateVideoProcessorInputView(
+                converter.input as *mut ID3D11Resource,
+                converter.enumerator,
+                &input_desc,
+                &mut converter.input_view,
+            ),
+        )?;
+
+        let mut output_desc: D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC = mem::zeroed();
+        output_desc.ViewDimension = D3D11_VPOV_DIMENSION_TEXTURE2D;
+        check(
+            "CreateVideoProcessorOutputView",
+            (*converter.video_device).CreateVideoProcessorOutputView(
+                converter.output as *mut ID3D11Resource,
+                converter.enumerator,
+                &output_desc,
+                &mut converter.output_view,
+            ),
+        )?;
+
+        // Scale the whole image to `size`, leaving any padding as background.
+        let video = &*converter.video_context;
+        let source_rect = RECT {
+            left: 0,
+            top: 0,
+            right: width as i32,
+            bottom: height as i32,
+        };
+        let dest_rect = RECT {
+            left: 0,
+            top: 0,
+            right: size.0 as i32,
+            bottom: size.1 as i32,
+        };
+        video.VideoProcessorSetStreamFrameFormat(
+            converter.processor,
+            0,
+            D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE,
+        );
+        video.VideoProcessorSetStreamAutoProcessingMode(converter.processor, 0, FALSE);
+        video.VideoProcessorSetStreamSourceRect(converter.processor, 0, TRUE, &source_rect);
+        video.VideoProcessorSetStreamDestRect(converter.processor, 0, TRUE, &dest_rect);
+
+        Ok(converter)
+    }
+
+    /// Whether this converter was set up for this conversion.
+    pub fn fits(
+        &self,
+        source: &D3D11_TEXTURE2D_DESC,
+        format: DXGI_FORMAT,
+        size: (u32, u32),
+    ) -> bool {
+        self.source.Width == source.Width
+            && self.source.Height == source.Height
+            && self.source.Format == source.Format
+            && self.format == format
+            && self.size == size
+    }
+
+    /// Queues converting `source` into the staging texture.
+    pub unsafe fn convert(
+        &mut self,
+        context: *mut ID3D11DeviceContext,
+        source: *mut ID3D11Texture2D,
+    ) -> io::Result<()> {
+        (*context).CopyResource(
+            self.input as *mut ID3D11Resource,
+            source as *mut ID3D11Resource,
+        );
+
+        let stream = D3D11_VIDEO_PROCESSOR_STREAM {
+            Enable: TRUE,
+            OutputIndex: 0,
+            InputFrameOrField: 0,
+            PastFrames: 0,
+            FutureFrames: 0,
+            ppPastSurfaces: ptr::null_mut(),
+            pInputSurface: self.input_view,
+            ppFutureSurfaces: ptr::null_mut(),
+            ppPastSurfacesRight: ptr::null_mut(),
+            pInputSurfaceRight: ptr::null_mut(),
+            ppFutureSurfacesRight: ptr::null_mut(),
+        };
+        check(
+            "VideoProcessorBlt",
+            (*self.video_context).VideoProcessorBlt(
+                self.processor,
+                self.output_view,
+                0,
+                1,
+                &stream,
+            ),
+        )?;
+
+        (*context).CopyResource(
+            self.staging as *mut ID3D11Resource,
+            self.output as *mut ID3D11Resource,
+        );
+        Ok(())
+    }
+
+    /// Maps the converted image, giving up at `deadline`.
+    pub unsafe fn map(
+        &mut self,
+        context: *mut ID3D11DeviceContext,
+        deadline: Option<Instant>,
+    ) -> io::Result<D3D11_MAPPED_SUBRESOURCE> {
+        let flags = if deadline.is_some() {
+            D3D11_MAP_FLAG_DO_NOT_WAIT
+        } else {
+            0
+        };
+
+        let mut mapped = mem::zeroed();
+        loop {
+            let res = (*context).Map(
+                self.staging as *mut ID3D11Resource,
+                0,
+                D3D11_MAP_READ,
+                flags,
+                &mut mapped,
+            );
+
+            if res != DXGI_ERROR_WAS_STILL_DRAWING {
+                check("Map", res)?;
+                self.mapped = true;
+                return Ok(mapped);
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(DeadlineExceeded.into());
+            }
+            thread::yield_now();
+        }
+    }
+
+    pub unsafe fn unmap(&mut self, context: *mut ID3D11DeviceContext) {
+        if self.mapped {
+            (*context).Unmap(self.staging as *mut ID3D11Resource, 0);
+            self.mapped = false;
+        }
+    }
+}
+
+impl Drop for VideoConverter {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.output_view.is_null() {
+                (*self.output_view).Release();
+            }
+            if !self.input_view.is_null() {
+                (*self.input_view).Release();
+            }
+            for texture in [self.staging, self.output, self.input] {
+                if !texture.is_null() {
+                    (*texture).Release();
+                }
+            }
+            if !self.processor.is_null() {
+                (*self.processor).Release();
+            }
+            if !self.enumerator.is_null() {
+                (*self.enumerator).Release();
+            }
+            if !self.video_context.is_null() {
+                (*self.video_context).Release();
+            }
+            if !self.video_device.is_null() {
+                (*self.video_device).Release();
+            }
+        }
+    }
+}
+
+fn even(x: usize) -> usize {
+    x + (x & 1)
+}
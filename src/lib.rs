@@ -1,6 +1,21 @@
 #[macro_use]
 extern crate cfg_if;
+#[cfg(feature = "vpx")]
+extern crate env_libvpx_sys as vpx_sys;
+#[cfg(feature = "jpeg")]
+extern crate jpeg_encoder;
 extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "png")]
+extern crate repng;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[macro_use]
+mod macros;
 
 #[cfg(quartz)]
 extern crate block;
@@ -11,6 +26,7 @@ pub mod quartz;
 pub mod x11;
 
 #[cfg(dxgi)]
+#[macro_use]
 extern crate winapi;
 #[cfg(dxgi)]
 pub mod dxgi;
@@ -0,0 +1,44 @@
+//! Logging that compiles to nothing without the `log` feature.
+
+#![allow(unused_macros)]
+
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)+) => { ::log::trace!($($arg)+) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)+) => { ::log::debug!($($arg)+) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! info {
+    ($($arg:tt)+) => { ::log::info!($($arg)+) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! warn {
+    ($($arg:tt)+) => { ::log::warn!($($arg)+) };
+}
+
+// The arguments still type-check, so nothing goes unused, but are never evaluated.
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! info {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! warn {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } };
+}
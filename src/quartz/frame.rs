@@ -4,6 +4,7 @@ use std::{ops, ptr, slice};
 pub struct Frame {
     surface: IOSurfaceRef,
     inner: &'static [u8],
+    stride: usize,
 }
 
 impl Frame {
@@ -18,7 +19,18 @@ impl Frame {
             IOSurfaceGetAllocSize(surface),
         );
 
-        Frame { surface, inner }
+        let stride = IOSurfaceGetBytesPerRow(surface);
+
+        Frame {
+            surface,
+            inner,
+            stride,
+        }
+    }
+
+    /// The distance between rows, in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
     }
 }
 
@@ -0,0 +1,461 @@
+//! Checks the DXGI capture path end to end: shows known patterns in a window,
+//! captures them in each output format, and compares the pixels. Needs an
+//! interactive desktop, so these are ignored; run them by hand with
+//! `cargo test --test pattern_check -- --ignored --test-threads=1`.
+
+#![cfg(windows)]
+
+extern crate scrap;
+extern crate winapi;
+
+use scrap::dxgi::{Capturer, CursorImage, Displays};
+use scrap::Rect;
+use std::cell::RefCell;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{io, mem, ptr, thread};
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::{HWND, RECT};
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::wingdi::{SetDIBitsToDevice, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS};
+use winapi::um::winuser::*;
+
+const SIZE: usize = 256;
+const TOLERANCE: u8 = 2;
+const TIMEOUT: UINT = 100;
+
+/// The windows and the pointer are shared, so only one test runs at a time.
+static DESKTOP: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The pattern the window paints, as packed BGRA.
+    static PATTERN: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Pattern {
+    name: &'static str,
+    pixel: fn(usize, usize) -> [u8; 3],
+}
+
+const PATTERNS: &[Pattern] = &[
+    Pattern {
+        name: "solid red",
+        pixel: |_, _| [0, 0, 255],
+    },
+    Pattern {
+        name: "solid grey",
+        pixel: |_, _| [128, 128, 128],
+    },
+    Pattern {
+        name: "gradient",
+        pixel: |x, y| [x as u8, y as u8, (x + y) as u8 / 2],
+    },
+    Pattern {
+        name: "checkerboard",
+        pixel: |x, y| {
+            if (x / 16 + y / 16) % 2 == 0 {
+                [255, 255, 255]
+            } else {
+                [0, 0, 0]
+            }
+        },
+    },
+];
+
+/// One of the ways a frame can be read out.
+struct Output {
+    name: &'static str,
+    /// Captures into the buffer and returns the stride.
+    capture: fn(&mut Capturer, &mut Vec<u8>) -> io::Result<usize>,
+    bytes_per_pixel: usize,
+    /// A pixel's blue, green and red.
+    bgr: fn(&[u8]) -> [u8; 3],
+}
+
+const OUTPUTS: &[Output] = &[
+    Output {
+        name: "BGRA",
+        capture: |capturer, out| {
+            let frame = capturer.frame(TIMEOUT)?;
+            out.clear();
+            out.extend_from_slice(&frame);
+            let stride = frame.stride();
+            // Whole rows, padding and all.
+            assert_eq!(stride, capturer.pitch());
+            assert_eq!(out.len(), capturer.pitch() * capturer.height());
+            Ok(stride)
+        },
+        bytes_per_pixel: 4,
+        bgr: |px| [px[0], px[1], px[2]],
+    },
+    Output {
+        name: "packed",
+        capture: |capturer, out| {
+            let frame = capturer.frame_packed(TIMEOUT)?;
+            out.clear();
+            out.extend_from_slice(&frame);
+            Ok(frame.stride())
+        },
+        bytes_per_pixel: 4,
+        bgr: |px| [px[0], px[1], px[2]],
+    },
+    Output {
+        name: "RGBA",
+        capture: |capturer, out| {
+            capturer.frame_rgba(TIMEOUT, out)?;
+            Ok(capturer.width() * 4)
+        },
+        bytes_per_pixel: 4,
+        bgr: |px| [px[2], px[1], px[0]],
+    },
+    Output {
+        name: "RGB24",
+        capture: |capturer, out| {
+            capturer.frame_rgb24(TIMEOUT, out)?;
+            Ok(capturer.width() * 3)
+        },
+        bytes_per_pixel: 3,
+        bgr: |px| [px[2], px[1], px[0]],
+    },
+    Output {
+        name: "BGR24",
+        capture: |capturer, out| {
+            capturer.frame_bgr24(TIMEOUT, out)?;
+            Ok(capturer.width() * 3)
+        },
+        bytes_per_pixel: 3,
+        bgr: |px| [px[0], px[1], px[2]],
+    },
+];
+
+/// A frame read out one way, with where the window is on it.
+struct Captured<'a> {
+    data: &'a [u8],
+    stride: usize,
+    output: &'a Output,
+    left: usize,
+    top: usize,
+}
+
+impl<'a> Captured<'a> {
+    /// The pixel at `(x, y)` in the window.
+    fn bgr(&self, x: usize, y: usize) -> [u8; 3] {
+        let bpp = self.output.bytes_per_pixel;
+        let i = (self.top + y) * self.stride + (self.left + x) * bpp;
+        (self.output.bgr)(&self.data[i..][..bpp])
+    }
+}
+
+fn close(got: [u8; 3], expected: [u8; 3]) -> bool {
+    (0..3).all(|i| got[i].abs_diff(expected[i]) <= TOLERANCE)
+}
+
+/// A display to capture, and a window on it that paints `PATTERN`.
+struct Setup {
+    window: HWND,
+    desktop: Rect,
+    capturer: Capturer,
+}
+
+impl Setup {
+    fn new(capture_mouse: bool) -> Setup {
+        // Otherwise window coordinates are scaled and won't match the frame.
+        unsafe {
+            SetProcessDPIAware();
+        }
+
+        let display = Displays::new()
+            .expect("Couldn't enumerate displays.")
+            .next()
+            .expect("No displays.");
+        let desktop = display.desktop();
+        let capturer = Capturer::new(&display, capture_mouse).expect("Couldn't begin capture.");
+        let window = unsafe { create_window(desktop.x + 100, desktop.y + 100) };
+        Setup {
+            window,
+            desktop,
+            capturer,
+        }
+    }
+
+    /// The window's top-left corner, in the display's frames.
+    fn window_origin(&self) -> (usize, usize) {
+        let mut rect = unsafe { mem::zeroed::<RECT>() };
+        unsafe {
+            GetWindowRect(self.window, &mut rect);
+        }
+        (
+            (rect.left - self.desktop.x) as usize,
+            (rect.top - self.desktop.y) as usize,
+        )
+    }
+
+    fn show(&self, pattern: &Pattern) {
+        PATTERN.with(|p| {
+            let mut p = p.borrow_mut();
+            p.clear();
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let [b, g, r] = (pattern.pixel)(x, y);
+                    p.extend_from_slice(&[b, g, r, 255]);
+                }
+            }
+        });
+        unsafe {
+            InvalidateRect(self.window, ptr::null(), 0);
+            UpdateWindow(self.window);
+        }
+    }
+
+    /// Captures through `output` until `matches` is happy with a frame, and
+    /// otherwise returns what it said about the last one.
+    fn wait_for(
+        &mut self,
+        output: &Output,
+        matches: impl Fn(&Captured) -> Option<String>,
+    ) -> Result<(), String> {
+        let (left, top) = self.window_origin();
+        let mut data = Vec::new();
+        // The composited frame can lag a little behind the paint.
+        let give_up = Instant::now() + Duration::from_secs(2);
+        let mut last_mismatch = String::from("no frames arrived");
+        while Instant::now() < give_up {
+            unsafe { pump() };
+            let stride = match (output.capture)(&mut self.capturer, &mut data) {
+                Ok(stride) => stride,
+                Err(ref e) if e.kind() == WouldBlock || e.kind() == TimedOut => continue,
+                Err(e) => return Err(format!("capture failed: {}", e)),
+            };
+            let (width, height) = (self.capturer.width(), self.capturer.height());
+            if left + SIZE > width || top + SIZE > height {
+                return Err("the window isn't fully on the display".into());
+            }
+
+            let captured = Captured {
+                data: &data,
+                stride,
+                output,
+                left,
+                top,
+            };
+            match matches(&captured) {
+                None => return Ok(()),
+                Some(mismatch) => last_mismatch = mismatch,
+            }
+        }
+        Err(last_mismatch)
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        unsafe {
+            DestroyWindow(self.window);
+        }
+    }
+}
+
+/// Describes the first pixel that's off by more than `TOLERANCE`.
+fn compare(captured: &Captured, pattern: &Pattern) -> Option<String> {
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (expected, got) = ((pattern.pixel)(x, y), captured.bgr(x, y));
+            if !close(got, expected) {
+                return Some(format!(
+                    "at ({}, {}) expected BGR {:?}, got {:?}",
+                    x, y, expected, got
+                ));
+            }
+        }
+    }
+    None
+}
+
+/// Shows every pattern and checks it comes out of each of `outputs`.
+fn check_outputs(outputs: &[Output]) {
+    let _desktop = DESKTOP.lock().unwrap_or_else(|e| e.into_inner());
+    // The cursor would be drawn over the pattern, so leave it out.
+    let mut setup = Setup::new(false);
+
+    let mut failures = Vec::new();
+    for output in outputs {
+        // A new pattern each time, so there's always a frame to wait for.
+        for pattern in PATTERNS {
+            setup.show(pattern);
+            if let Err(reason) = setup.wait_for(output, |c| compare(c, pattern)) {
+                failures.push(format!("{} {}: {}", output.name, pattern.name, reason));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn patterns_in_bgra() {
+    check_outputs(&OUTPUTS[..1]);
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn patterns_in_packed_and_converted_frames() {
+    check_outputs(&OUTPUTS[1..]);
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn custom_cursor_is_blended() {
+    const CURSOR: usize = 16;
+    const MAGENTA: [u8; 3] = [255, 0, 255];
+    const GREY: [u8; 3] = [128, 128, 128];
+
+    let _desktop = DESKTOP.lock().unwrap_or_else(|e| e.into_inner());
+    let mut setup = Setup::new(true);
+    // Opaque magenta on the left half, half transparent on the right.
+    let mut data = Vec::with_capacity(CURSOR * CURSOR * 4);
+    for _ in 0..CURSOR {
+        for x in 0..CURSOR {
+            let alpha = if x < CURSOR / 2 { 255 } else { 128 };
+            data.extend_from_slice(&[255, 0, 255, alpha]);
+        }
+    }
+    setup.capturer.set_custom_cursor(Some(CursorImage {
+        width: CURSOR as u32,
+        height: CURSOR as u32,
+        hot_spot: (0, 0),
+        data,
+        invert: vec![false; CURSOR * CURSOR],
+    }));
+    setup.show(&PATTERNS[1]);
+
+    // Moving the pointer makes DXGI report where it is.
+    let (left, top) = setup.window_origin();
+    let at = SIZE / 4;
+    unsafe {
+        SetCursorPos(
+            setup.desktop.x + (left + at) as i32,
+            setup.desktop.y + (top + at) as i32,
+        );
+    }
+
+    // 128 over 128 stays 128, and 255 and 0 over it meet in the middle.
+    let half = [191, 64, 191];
+    let packed = &OUTPUTS[1];
+    let result = setup.wait_for(packed, |c| {
+        let checks = [
+            ("outside the cursor", (at - 1, at - 1), GREY),
+            ("under the opaque half", (at, at), MAGENTA),
+            ("under the opaque half", (at + 7, at + 15), MAGENTA),
+            ("under the blended half", (at + 8, at), half),
+            ("under the blended half", (at + 15, at + 15), half),
+            ("past the cursor", (at + CURSOR, at + CURSOR), GREY),
+        ];
+        for &(name, (x, y), expected) in &checks {
+            let got = c.bgr(x, y);
+            if !close(got, expected) {
+                return Some(format!(
+                    "{} at ({}, {}) expected BGR {:?}, got {:?}",
+                    name, x, y, expected, got
+                ));
+            }
+        }
+        None
+    });
+    if let Err(reason) = result {
+        panic!("{}", reason);
+    }
+}
+
+unsafe fn create_window(x: i32, y: i32) -> HWND {
+    let class: Vec<u16> = "scrap_pattern_check\0".encode_utf16().collect();
+    let instance = GetModuleHandleW(ptr::null());
+    let wc = WNDCLASSW {
+        style: 0,
+        lpfnWndProc: Some(window_proc),
+        cbClsExtra: 0,
+        cbWndExtra: 0,
+        hInstance: instance,
+        hIcon: ptr::null_mut(),
+        hCursor: ptr::null_mut(),
+        hbrBackground: ptr::null_mut(),
+        lpszMenuName: ptr::null(),
+        lpszClassName: class.as_ptr(),
+    };
+    // Fails harmlessly when an earlier test registered it.
+    RegisterClassW(&wc);
+
+    let window = CreateWindowExW(
+        WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
+        class.as_ptr(),
+        class.as_ptr(),
+        WS_POPUP | WS_VISIBLE,
+        x,
+        y,
+        SIZE as i32,
+        SIZE as i32,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        instance,
+        ptr::null_mut(),
+    );
+    assert!(!window.is_null(), "Couldn't create the window.");
+    pump();
+    window
+}
+
+unsafe fn pump() {
+    let mut msg = mem::zeroed();
+    while PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+        TranslateMessage(&msg);
+        DispatchMessageW(&msg);
+    }
+    thread::sleep(Duration::from_millis(16));
+}
+
+unsafe extern "system" fn window_proc(
+    window: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg != WM_PAINT {
+        return DefWindowProcW(window, msg, wparam, lparam);
+    }
+
+    let mut paint = mem::zeroed();
+    let dc = BeginPaint(window, &mut paint);
+    PATTERN.with(|p| {
+        let p = p.borrow();
+        if p.is_empty() {
+            return;
+        }
+        let mut info: BITMAPINFO = mem::zeroed();
+        info.bmiHeader = BITMAPINFOHEADER {
+            biSize: mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: SIZE as i32,
+            // Negative for top-down rows.
+            biHeight: -(SIZE as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB,
+            ..mem::zeroed()
+        };
+        SetDIBitsToDevice(
+            dc,
+            0,
+            0,
+            SIZE as u32,
+            SIZE as u32,
+            0,
+            0,
+            0,
+            SIZE as u32,
+            p.as_ptr() as *const _,
+            &info,
+            DIB_RGB_COLORS,
+        );
+    });
+    EndPaint(window, &paint);
+    0
+}
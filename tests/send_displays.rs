@@ -0,0 +1,59 @@
+//! Checks that displays found on one thread can be captured from another.
+//! Needs an interactive desktop, so these are ignored; run them by hand with
+//! `cargo test --test send_displays -- --ignored`.
+
+#![cfg(windows)]
+
+extern crate scrap;
+
+use scrap::dxgi::{Capturer, Displays};
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn capture_on_another_thread() {
+    let displays: Vec<_> = Displays::new().expect("Couldn't find displays.").collect();
+    assert!(!displays.is_empty(), "no displays");
+
+    for display in displays {
+        let size = (display.width() as usize, display.height() as usize);
+        let captured = thread::spawn(move || {
+            let mut capturer = Capturer::new(&display, false).expect("Couldn't begin capture.");
+            assert_eq!((capturer.width(), capturer.height()), size);
+
+            // The first frame usually comes straight away, but an idle
+            // desktop may not send one.
+            let give_up = Instant::now() + Duration::from_secs(2);
+            while Instant::now() < give_up {
+                match capturer.frame(100) {
+                    Ok(frame) => return Some(frame.len()),
+                    Err(ref e) if e.kind() == WouldBlock || e.kind() == TimedOut => continue,
+                    Err(e) => panic!("capture failed: {}", e),
+                }
+            }
+            None
+        })
+        .join()
+        .expect("the capture thread panicked");
+
+        if let Some(len) = captured {
+            assert!(len > 0);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn enumerate_on_another_thread() {
+    let displays = Displays::new().expect("Couldn't find displays.");
+    let names: Vec<_> = thread::spawn(move || displays.map(|d| d.name().to_vec()).collect())
+        .join()
+        .expect("the enumerating thread panicked");
+    let here: Vec<_> = Displays::new()
+        .expect("Couldn't find displays.")
+        .map(|d| d.name().to_vec())
+        .collect();
+    assert_eq!(names, here);
+}